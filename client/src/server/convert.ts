@@ -1,6 +1,8 @@
 import * as Info from '../twmap/types'
 import * as MapDir from '../twmap/mapdir'
 import * as Parser from '../twmap/parser'
+import type { Compression } from './protocol'
+import { inflate } from 'pako' // zlib
 
 const envTypeStr: MapDir.EnvelopeType[] = [
   MapDir.EnvelopeType.Color, // INVALID
@@ -127,8 +129,13 @@ export function tilesToData(tiles: Info.AnyTile[]): string {
 //   return tilesToData([tile])
 // }
 
-export function dataToTiles(data: string, kind: MapDir.LayerKind): Info.AnyTile[] {
-  const arr = base64ToBytes(data).buffer
+export function dataToTiles(
+  data: string,
+  kind: MapDir.LayerKind,
+  compression: Compression = 'none'
+): Info.AnyTile[] {
+  const bytes = base64ToBytes(data)
+  const arr = compression === 'zlib' ? inflate(bytes).buffer : bytes.buffer
 
   if (kind === 'tiles' || kind === 'game' || kind === 'front') {
     return Parser.parseTiles(arr, arr.byteLength / 4)
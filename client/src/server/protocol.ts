@@ -24,12 +24,16 @@ export interface MapDetail {
   users: number
 }
 
+// tilemaps are mostly empty, they compress very well.
+export type Compression = 'none' | 'zlib'
+
 export interface Tiles {
   x: number
   y: number
   w: number
   h: number
   tiles: Base64<Info.AnyTile[]>
+  compression?: Compression
 }
 
 // TODO
@@ -49,6 +53,16 @@ export interface Cursor {
 
 export type Cursors = Record<string, Cursor>
 
+export type Role = 'viewer' | 'editor' | 'owner'
+
+export interface Presence {
+  id: string
+  name?: string
+  color?: { r: number; g: number; b: number }
+  role?: Role
+  cursor?: Cursor
+}
+
 export enum AutomapperKind {
   DDNet = 'rules',
   Teeworlds = 'json',
@@ -112,14 +126,14 @@ export interface MapGetReq {
   group: number
   layers: number
   layer: [number, number]
-  tiles: [number, number]
+  tiles: [number, number, Compression?]
   quad: [number, number, number]
   automappers: undefined
   automapper: string
 }
 
 export interface MapGetResp {
-  users: number
+  users: Presence[]
   cursors: Cursors
   map: Base64<Blob>
   info: MapDir.Info
@@ -318,7 +332,7 @@ export interface Resp {
   config: Config
   cursor: undefined
   save: undefined
-  join: string
+  join: Presence
   leave: undefined
   create: undefined
   delete: undefined
@@ -20,6 +20,11 @@ async fn server_main() {
         max_connections: 100,
         max_http_bursts: 100,
         http_ratelimit_delay: 500,
+        autosave_interval: 0,
+        max_backups: 10,
+        cursor_interval: 50,
+        resume_grace: 30,
+        admin_password: None,
     };
     let server = Arc::new(twwe_server::create_server(&cli).expect("failed to create the server"));
 
@@ -1,7 +1,13 @@
 use base64::Engine;
-use serde::{de::Error, de::Unexpected, Deserialize, Serialize};
+#[cfg(feature = "schema")]
+use schemars::{json_schema, JsonSchema, Schema, SchemaGenerator};
+use serde::{
+    de::{Error, Unexpected, Visitor},
+    Deserialize, Serialize,
+};
 
 // TODO: use serde_with's base64?
+// Binary formats (see `protocol::Encoding`) store the raw bytes instead.
 #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Base64(pub Vec<u8>);
 
@@ -10,8 +16,37 @@ impl Serialize for Base64 {
     where
         S: serde::Serializer,
     {
-        let str = base64::engine::general_purpose::STANDARD.encode(&self.0);
-        serializer.serialize_str(&str)
+        if serializer.is_human_readable() {
+            let str = base64::engine::general_purpose::STANDARD.encode(&self.0);
+            serializer.serialize_str(&str)
+        } else {
+            serializer.serialize_bytes(&self.0)
+        }
+    }
+}
+
+struct Base64Visitor;
+
+impl<'de> Visitor<'de> for Base64Visitor {
+    type Value = Base64;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("a base64-encoded string or a byte array")
+    }
+
+    fn visit_str<E: Error>(self, v: &str) -> Result<Self::Value, E> {
+        base64::engine::general_purpose::STANDARD
+            .decode(v)
+            .map(Base64)
+            .map_err(|_| E::invalid_value(Unexpected::Str(v), &self))
+    }
+
+    fn visit_bytes<E: Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+        Ok(Base64(v.to_vec()))
+    }
+
+    fn visit_byte_buf<E: Error>(self, v: Vec<u8>) -> Result<Self::Value, E> {
+        Ok(Base64(v))
     }
 }
 
@@ -20,10 +55,18 @@ impl<'de> Deserialize<'de> for Base64 {
     where
         D: serde::Deserializer<'de>,
     {
-        let str: &str = Deserialize::deserialize(deserializer)?;
-        base64::engine::general_purpose::STANDARD
-            .decode(str)
-            .map(Base64)
-            .map_err(|_| D::Error::invalid_value(Unexpected::Str(str), &"a base64-encoded string"))
+        // not deserialize_str: flattened and untagged fields lose track of the format.
+        deserializer.deserialize_any(Base64Visitor)
+    }
+}
+
+#[cfg(feature = "schema")]
+impl JsonSchema for Base64 {
+    fn schema_name() -> std::borrow::Cow<'static, str> {
+        "Base64".into()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        json_schema!({ "type": "string", "format": "byte" })
     }
 }
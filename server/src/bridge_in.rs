@@ -97,6 +97,8 @@ impl Server {
             let packet = SendPacket {
                 timestamp: timestamp_now(),
                 id: None,
+                revision: None,
+                base_revision: None,
                 content: Message::Request(Request::LeaveMap(bridge.map.clone())),
             };
             let logout_msg = serde_json::to_string(&packet).unwrap();
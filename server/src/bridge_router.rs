@@ -30,6 +30,8 @@ pub(crate) async fn bridge_oneshot(
     let pkt = RecvPacket {
         timestamp: timestamp_now(),
         id: rand::random(),
+        revision: None,
+        base_revision: None,
         content: req,
     };
 
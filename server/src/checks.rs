@@ -9,6 +9,18 @@ pub(crate) trait PartialCheck {
     }
 }
 
+impl PartialCheck for UserProfile {
+    fn check_self(&self) -> Result<(), Error> {
+        if self.name.chars().count() > UserProfile::MAX_NAME_LENGTH {
+            return Err(Error::FieldTooLong("name"));
+        }
+        if self.name.trim().is_empty() {
+            return Err(Error::Invalid("name"));
+        }
+        Ok(())
+    }
+}
+
 impl PartialCheck for PartialInfo {
     fn check_self(&self) -> Result<(), Error> {
         macro_rules! check_length {
@@ -123,6 +135,7 @@ impl PartialCheck for PartialLayer {
             PartialLayer::Speedup(layer) => layer.check_self()?,
             PartialLayer::Switch(layer) => layer.check_self()?,
             PartialLayer::Tune(layer) => layer.check_self()?,
+            PartialLayer::Sounds(layer) => layer.check_self()?,
         }
         Ok(())
     }
@@ -137,6 +150,7 @@ impl PartialCheck for PartialLayer {
             PartialLayer::Speedup(layer) => layer.check_map(map)?,
             PartialLayer::Switch(layer) => layer.check_map(map)?,
             PartialLayer::Tune(layer) => layer.check_map(map)?,
+            PartialLayer::Sounds(layer) => layer.check_map(map)?,
         }
 
         Ok(())
@@ -202,6 +216,28 @@ impl PartialCheck for PartialQuadsLayer {
     }
 }
 
+impl PartialCheck for PartialSoundsLayer {
+    fn check_self(&self) -> Result<(), Error> {
+        if let Some(name) = &self.name {
+            if name.len() > twmap::Layer::MAX_NAME_LENGTH {
+                return Err(Error::FieldTooLong("name"));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_map(&self, map: &twmap::TwMap) -> Result<(), Error> {
+        if let Some(Some(index)) = self.sound {
+            if index as usize >= map.sounds.len() {
+                return Err(Error::SoundNotFound);
+            }
+        }
+
+        Ok(())
+    }
+}
+
 impl PartialCheck for PartialPhysicsLayer {}
 
 impl PartialCheck for twmap::Quad {
@@ -233,3 +269,32 @@ impl PartialCheck for twmap::Quad {
         Ok(())
     }
 }
+
+impl PartialCheck for twmap::SoundSource {
+    fn check_map(&self, map: &twmap::TwMap) -> Result<(), Error> {
+        self.check(map, &mut ())
+            .map_err(|e| Error::Map(e.to_string()))?;
+
+        if let Some(index) = self.position_env {
+            let env = map
+                .envelopes
+                .get(index as usize)
+                .ok_or(Error::EnvelopeNotFound)?;
+            if !matches!(env, twmap::Envelope::Position(_)) {
+                return Err(Error::WrongEnvelopeType);
+            }
+        }
+
+        if let Some(index) = self.sound_env {
+            let env = map
+                .envelopes
+                .get(index as usize)
+                .ok_or(Error::EnvelopeNotFound)?;
+            if !matches!(env, twmap::Envelope::Sound(_)) {
+                return Err(Error::WrongEnvelopeType);
+            }
+        }
+
+        Ok(())
+    }
+}
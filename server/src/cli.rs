@@ -63,4 +63,29 @@ pub struct Cli {
     /// Once an IP is rate-limited, delay after which 1 request quota is replenished. In milliseconds.
     #[arg(long, default_value_t = 500)]
     pub http_ratelimit_delay: u64,
+
+    /// Interval between automatic saves of maps with unsaved edits, in seconds.
+    /// Autosave is disabled if 0.
+    #[arg(long, default_value_t = 0)]
+    pub autosave_interval: u64,
+
+    /// Number of timestamped backups to keep for each map, a backup is made on each autosave.
+    #[arg(long, default_value_t = 10)]
+    pub max_backups: usize,
+
+    /// Interval between broadcasts of cursor updates to the other users of a room. In milliseconds.
+    /// Cursors are only available with get/cursors if 0.
+    #[arg(long, default_value_t = 50)]
+    pub cursor_interval: u64,
+
+    /// Delay during which a disconnected user can resume their session, in seconds.
+    /// The user stays in their room meanwhile, and receives the edits they missed on resume.
+    #[arg(long, default_value_t = 30)]
+    pub resume_grace: u64,
+
+    /// Password that grants the owner role on every map, over the websocket or as a
+    /// REST bearer token. Maps with an editor password but no owner password can only
+    /// be deleted or configured with it.
+    #[arg(long)]
+    pub admin_password: Option<String>,
 }
@@ -11,8 +11,10 @@ pub enum Error {
     GroupNotFound,
     LayerNotFound,
     QuadNotFound,
+    SoundNotFound,
+    SourceNotFound,
     AutomapperNotFound,
-    #[allow(unused)]
+    CommentNotFound,
     NotFound(&'static str),
 
     MaxEnvelopes,
@@ -21,8 +23,11 @@ pub enum Error {
     MaxGroups,
     MaxLayers,
     MaxQuads,
+    MaxSounds,
+    MaxSources,
 
     InvalidImage,
+    InvalidSound,
     InvalidTiles,
     InvalidMapName,
     InvalidFileName,
@@ -37,6 +42,7 @@ pub enum Error {
     WrongTilesImage,
 
     ImageInUse,
+    SoundInUse,
     EnvelopeInUse,
 
     MapNameTaken,
@@ -53,7 +59,15 @@ pub enum Error {
 
     AlreadyJoined,
     NotJoined,
+    NothingToUndo,
+    NothingToRedo,
+    Conflict,
+    LayerLocked,
+    RecoveryNotFound,
+    BackupNotFound,
     Password,
+    InvalidInvite,
+    TooManyAttempts(u64), // seconds to wait
     Unauthorized,
 
     Map(String),
@@ -68,6 +82,7 @@ pub enum Error {
     CreateDuplicatePhysicsLayer,
     PhysicsLayerChangeGroup,
     EditPhysicsGroup,
+    Forbidden,
 
     // 500 internal server error
     Internal(Cow<'static, str>),
@@ -84,7 +99,10 @@ impl Display for Error {
             Error::GroupNotFound => write!(f, "group not found"),
             Error::LayerNotFound => write!(f, "layer not found"),
             Error::QuadNotFound => write!(f, "quad not found"),
+            Error::SoundNotFound => write!(f, "sound not found"),
+            Error::SourceNotFound => write!(f, "sound source not found"),
             Error::AutomapperNotFound => write!(f, "automapper not found"),
+            Error::CommentNotFound => write!(f, "comment not found"),
             Error::NotFound(x) => write!(f, "{x} not found"),
             Error::MaxEnvelopes => write!(f, "maximum number of envelopes reached"),
             Error::MaxEnvPoints => write!(f, "maximum number of envelope points reached"),
@@ -92,7 +110,10 @@ impl Display for Error {
             Error::MaxGroups => write!(f, "maximum number of groups reached"),
             Error::MaxLayers => write!(f, "maximum number of layers reached"),
             Error::MaxQuads => write!(f, "maximum number of quads reached"),
+            Error::MaxSounds => write!(f, "maximum number of sounds reached"),
+            Error::MaxSources => write!(f, "maximum number of sound sources reached"),
             Error::InvalidImage => write!(f, "invalid image"),
+            Error::InvalidSound => write!(f, "invalid sound"),
             Error::InvalidTiles => write!(f, "invalid tiles"),
             Error::InvalidMapName => write!(f, "invalid map name"),
             Error::InvalidFileName => write!(f, "invalid file name"),
@@ -107,6 +128,7 @@ impl Display for Error {
                 "invalid image for tile layer (dimensions must be divisible by 16)"
             ),
             Error::ImageInUse => write!(f, "image in use"),
+            Error::SoundInUse => write!(f, "sound in use"),
             Error::EnvelopeInUse => write!(f, "envelope in use"),
             Error::MapNameTaken => write!(f, "map name already taken"),
             Error::MapTooBig => write!(f, "map size exceeds limit"),
@@ -120,7 +142,17 @@ impl Display for Error {
             Error::BridgeClosed => write!(f, "connection with the remote server closed"),
             Error::AlreadyJoined => write!(f, "already joined"),
             Error::NotJoined => write!(f, "not joined"),
+            Error::NothingToUndo => write!(f, "nothing to undo"),
+            Error::NothingToRedo => write!(f, "nothing to redo"),
+            Error::Conflict => write!(f, "request conflicts with a concurrent edit"),
+            Error::LayerLocked => write!(f, "layer is locked by another user"),
+            Error::RecoveryNotFound => write!(f, "no recovery file for this map"),
+            Error::BackupNotFound => write!(f, "backup not found"),
             Error::Password => write!(f, "incorrect password"),
+            Error::InvalidInvite => write!(f, "invalid or expired invite"),
+            Error::TooManyAttempts(x) => {
+                write!(f, "too many failed attempts, retry in {x} seconds")
+            }
             Error::Unauthorized => write!(f, "access denied"),
             Error::Map(x) => write!(f, "twmap error: {x}"),
             Error::Automapper(x) => write!(f, "automapper error: {x}"),
@@ -139,6 +171,7 @@ impl Display for Error {
                 write!(f, "cannot move a physics layer out of the physics group")
             }
             Error::EditPhysicsGroup => write!(f, "cannot edit properties of the physics group"),
+            Error::Forbidden => write!(f, "your role does not allow this on this map"),
             Error::Internal(x) => write!(f, "internal server error: {x}"),
             Error::ToDo => write!(f, "this functionality is not implemented yet"),
         }
@@ -162,7 +195,10 @@ impl IntoResponse for Error {
             Error::GroupNotFound => StatusCode::NOT_FOUND,
             Error::LayerNotFound => StatusCode::NOT_FOUND,
             Error::QuadNotFound => StatusCode::NOT_FOUND,
+            Error::SoundNotFound => StatusCode::NOT_FOUND,
+            Error::SourceNotFound => StatusCode::NOT_FOUND,
             Error::AutomapperNotFound => StatusCode::NOT_FOUND,
+            Error::CommentNotFound => StatusCode::NOT_FOUND,
             Error::NotFound(_) => StatusCode::NOT_FOUND,
             Error::MaxEnvelopes => StatusCode::BAD_REQUEST,
             Error::MaxEnvPoints => StatusCode::BAD_REQUEST,
@@ -170,7 +206,10 @@ impl IntoResponse for Error {
             Error::MaxGroups => StatusCode::BAD_REQUEST,
             Error::MaxLayers => StatusCode::BAD_REQUEST,
             Error::MaxQuads => StatusCode::BAD_REQUEST,
+            Error::MaxSounds => StatusCode::BAD_REQUEST,
+            Error::MaxSources => StatusCode::BAD_REQUEST,
             Error::InvalidImage => StatusCode::BAD_REQUEST,
+            Error::InvalidSound => StatusCode::BAD_REQUEST,
             Error::InvalidTiles => StatusCode::BAD_REQUEST,
             Error::InvalidMapName => StatusCode::BAD_REQUEST,
             Error::InvalidFileName => StatusCode::BAD_REQUEST,
@@ -182,6 +221,7 @@ impl IntoResponse for Error {
             Error::WrongLayerType => StatusCode::BAD_REQUEST,
             Error::WrongTilesImage => StatusCode::BAD_REQUEST,
             Error::ImageInUse => StatusCode::BAD_REQUEST,
+            Error::SoundInUse => StatusCode::BAD_REQUEST,
             Error::EnvelopeInUse => StatusCode::BAD_REQUEST,
             Error::MapNameTaken => StatusCode::BAD_REQUEST,
             Error::MapTooBig => StatusCode::BAD_REQUEST,
@@ -195,7 +235,15 @@ impl IntoResponse for Error {
             Error::BridgeClosed => StatusCode::BAD_GATEWAY,
             Error::AlreadyJoined => StatusCode::BAD_REQUEST,
             Error::NotJoined => StatusCode::BAD_REQUEST,
+            Error::NothingToUndo => StatusCode::BAD_REQUEST,
+            Error::NothingToRedo => StatusCode::BAD_REQUEST,
+            Error::Conflict => StatusCode::CONFLICT,
+            Error::LayerLocked => StatusCode::LOCKED,
+            Error::RecoveryNotFound => StatusCode::NOT_FOUND,
+            Error::BackupNotFound => StatusCode::NOT_FOUND,
             Error::Password => StatusCode::BAD_REQUEST,
+            Error::InvalidInvite => StatusCode::UNAUTHORIZED,
+            Error::TooManyAttempts(_) => StatusCode::TOO_MANY_REQUESTS,
             Error::Unauthorized => StatusCode::UNAUTHORIZED,
             Error::Map(_) => StatusCode::BAD_REQUEST,
             Error::Automapper(_) => StatusCode::BAD_REQUEST,
@@ -207,6 +255,7 @@ impl IntoResponse for Error {
             Error::CreateDuplicatePhysicsLayer => StatusCode::FORBIDDEN,
             Error::PhysicsLayerChangeGroup => StatusCode::FORBIDDEN,
             Error::EditPhysicsGroup => StatusCode::FORBIDDEN,
+            Error::Forbidden => StatusCode::FORBIDDEN,
             Error::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
             Error::ToDo => StatusCode::INTERNAL_SERVER_ERROR,
         };
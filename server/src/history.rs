@@ -0,0 +1,893 @@
+use std::collections::VecDeque;
+
+use image::ImageFormat;
+use vek::Rect;
+
+use crate::{base64::Base64, protocol::*, util::tiles_region_bytes};
+
+// The history mirrors the one in the client (client/src/server/history.ts), but is
+// shared by all users of a room. Each operation stores the requests that were
+// applied and the requests that revert them. Undoing or redoing an operation just
+// replays those requests, which lets the server broadcast them to clients like any
+// other edit.
+
+const MAX_HISTORY_LEN: usize = 100;
+
+#[derive(Clone, Debug)]
+pub struct Operation {
+    pub forward: Vec<Request>,
+    pub reverse: Vec<Request>,
+}
+
+#[derive(Debug)]
+pub struct History {
+    ops: VecDeque<Operation>,
+    index: usize, // points past last modification
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self {
+            ops: VecDeque::with_capacity(MAX_HISTORY_LEN),
+            index: 0,
+        }
+    }
+}
+
+impl History {
+    pub fn clear(&mut self) {
+        self.ops.clear();
+        self.index = 0;
+    }
+
+    pub fn push(&mut self, op: Operation) {
+        // discard forward history
+        self.ops.truncate(self.index);
+        self.ops.push_back(op);
+        if self.ops.len() > MAX_HISTORY_LEN {
+            self.ops.pop_front();
+        }
+        self.index = self.ops.len();
+    }
+
+    pub fn undo(&mut self) -> Option<Operation> {
+        let op = self.ops.get(self.index.checked_sub(1)?)?.clone();
+        self.index -= 1;
+        Some(op)
+    }
+
+    pub fn redo(&mut self) -> Option<Operation> {
+        let op = self.ops.get(self.index)?.clone();
+        self.index += 1;
+        Some(op)
+    }
+}
+
+fn layer_tiles(layer: &twmap::Layer, rect: Option<Rect<u32, u32>>) -> Option<Tiles> {
+    macro_rules! tiles {
+        ($layer:ident) => {{
+            let shape = $layer.tiles.shape();
+            let rect = rect.unwrap_or(Rect::new(0, 0, shape.w as u32, shape.h as u32));
+            let right = rect.x.checked_add(rect.w)? as usize;
+            let bottom = rect.y.checked_add(rect.h)? as usize;
+            if right > shape.w || bottom > shape.h {
+                return None;
+            }
+            Some(Tiles {
+                rect,
+                tiles: Base64(tiles_region_bytes($layer, rect).into()),
+                compression: Compression::None,
+            })
+        }};
+    }
+
+    match layer {
+        twmap::Layer::Game(layer) => tiles!(layer),
+        twmap::Layer::Tiles(layer) => tiles!(layer),
+        twmap::Layer::Front(layer) => tiles!(layer),
+        twmap::Layer::Tele(layer) => tiles!(layer),
+        twmap::Layer::Speedup(layer) => tiles!(layer),
+        twmap::Layer::Switch(layer) => tiles!(layer),
+        twmap::Layer::Tune(layer) => tiles!(layer),
+        twmap::Layer::Quads(_) | twmap::Layer::Sounds(_) | twmap::Layer::Invalid(_) => None,
+    }
+}
+
+fn partial_group(group: &twmap::Group) -> PartialGroup {
+    PartialGroup {
+        name: Some(group.name.clone()),
+        offset: Some(group.offset),
+        parallax: Some(group.parallax),
+        clipping: Some(group.clipping),
+        clip: Some(group.clip),
+    }
+}
+
+fn partial_envelope(env: &twmap::Envelope) -> PartialEnvelope {
+    macro_rules! partial_env {
+        ($env:ident) => {
+            PartialEnv {
+                name: Some($env.name.clone()),
+                synchronized: Some($env.synchronized),
+                points: Some($env.points.clone()),
+            }
+        };
+    }
+
+    match env {
+        twmap::Envelope::Position(env) => PartialEnvelope::Position(partial_env!(env)),
+        twmap::Envelope::Color(env) => PartialEnvelope::Color(partial_env!(env)),
+        twmap::Envelope::Sound(env) => PartialEnvelope::Sound(partial_env!(env)),
+    }
+}
+
+fn create_image(image: &twmap::Image) -> Option<CreateReq> {
+    let create = match image {
+        twmap::Image::External(image) => Image::External { size: image.size },
+        twmap::Image::Embedded(image) => {
+            let mut buf = Vec::new();
+            image
+                .image
+                .unwrap_ref()
+                .write_to(&mut std::io::Cursor::new(&mut buf), ImageFormat::Png)
+                .ok()?;
+            Image::Embedded(Base64(buf))
+        }
+    };
+    Some(CreateReq::Image(image.name().to_owned(), create))
+}
+
+/// Requests that recreate `layer` at index `l` (the end) of group `g`.
+fn create_layer(g: u16, l: u16, layer: &twmap::Layer) -> Option<Vec<Request>> {
+    macro_rules! create_physics_layer {
+        ($kind:ident) => {
+            vec![
+                Request::Create(CreateReq::Layer(
+                    g,
+                    Box::new(PartialLayer::$kind(Default::default())),
+                )),
+                Request::Edit(EditReq::Tiles(g, l, Box::new(layer_tiles(layer, None)?))),
+            ]
+        };
+    }
+
+    let reqs = match layer {
+        twmap::Layer::Tiles(tiles_layer) => {
+            let shape = tiles_layer.tiles.shape();
+            let part_layer = PartialTilesLayer {
+                name: Some(tiles_layer.name.clone()),
+                detail: Some(tiles_layer.detail),
+                color: Some(tiles_layer.color),
+                color_env: Some(tiles_layer.color_env),
+                color_env_offset: Some(tiles_layer.color_env_offset),
+                image: Some(tiles_layer.image),
+                automapper_config: Some(tiles_layer.automapper_config.clone()),
+                ..Default::default()
+            };
+            let part_dims = PartialTilesLayer {
+                width: Some(shape.w),
+                height: Some(shape.h),
+                ..Default::default()
+            };
+            vec![
+                Request::Create(CreateReq::Layer(
+                    g,
+                    Box::new(PartialLayer::Tiles(part_layer)),
+                )),
+                Request::Edit(EditReq::Layer(
+                    g,
+                    l,
+                    Box::new(PartialLayer::Tiles(part_dims)),
+                )),
+                Request::Edit(EditReq::Tiles(g, l, Box::new(layer_tiles(layer, None)?))),
+            ]
+        }
+        twmap::Layer::Quads(quads_layer) => {
+            let part_layer = PartialQuadsLayer {
+                name: Some(quads_layer.name.clone()),
+                detail: Some(quads_layer.detail),
+                image: Some(quads_layer.image),
+            };
+            let create = Request::Create(CreateReq::Layer(
+                g,
+                Box::new(PartialLayer::Quads(part_layer)),
+            ));
+            std::iter::once(create)
+                .chain(
+                    quads_layer
+                        .quads
+                        .iter()
+                        .map(|quad| Request::Create(CreateReq::Quad(g, l, Box::new(quad.clone())))),
+                )
+                .collect()
+        }
+        twmap::Layer::Sounds(sounds_layer) => {
+            let part_layer = PartialSoundsLayer {
+                name: Some(sounds_layer.name.clone()),
+                detail: Some(sounds_layer.detail),
+                sound: Some(sounds_layer.sound),
+            };
+            let create = Request::Create(CreateReq::Layer(
+                g,
+                Box::new(PartialLayer::Sounds(part_layer)),
+            ));
+            std::iter::once(create)
+                .chain(sounds_layer.sources.iter().map(|source| {
+                    Request::Create(CreateReq::Source(g, l, Box::new(source.clone())))
+                }))
+                .collect()
+        }
+        twmap::Layer::Front(_) => create_physics_layer!(Front),
+        twmap::Layer::Tele(_) => create_physics_layer!(Tele),
+        twmap::Layer::Speedup(_) => create_physics_layer!(Speedup),
+        twmap::Layer::Switch(_) => create_physics_layer!(Switch),
+        twmap::Layer::Tune(_) => create_physics_layer!(Tune),
+        twmap::Layer::Game(_) | twmap::Layer::Invalid(_) => return None,
+    };
+
+    Some(reqs)
+}
+
+fn rev_edit_info(map: &twmap::TwMap, part: &PartialInfo) -> PartialInfo {
+    let info = &map.info;
+    PartialInfo {
+        author: part.author.as_ref().map(|_| info.author.clone()),
+        version: part.version.as_ref().map(|_| info.version.clone()),
+        credits: part.credits.as_ref().map(|_| info.credits.clone()),
+        license: part.license.as_ref().map(|_| info.license.clone()),
+        settings: part.settings.as_ref().map(|_| info.settings.clone()),
+    }
+}
+
+fn rev_edit_envelope(env: &twmap::Envelope, part: &PartialEnvelope) -> Option<PartialEnvelope> {
+    macro_rules! rev_env {
+        ($env:ident, $part:ident) => {
+            PartialEnv {
+                name: $part.name.as_ref().map(|_| $env.name.clone()),
+                synchronized: $part.synchronized.map(|_| $env.synchronized),
+                points: $part.points.as_ref().map(|_| $env.points.clone()),
+            }
+        };
+    }
+
+    let rev_part = match (env, part) {
+        (twmap::Envelope::Position(env), PartialEnvelope::Position(part)) => {
+            PartialEnvelope::Position(rev_env!(env, part))
+        }
+        (twmap::Envelope::Color(env), PartialEnvelope::Color(part)) => {
+            PartialEnvelope::Color(rev_env!(env, part))
+        }
+        (twmap::Envelope::Sound(env), PartialEnvelope::Sound(part)) => {
+            PartialEnvelope::Sound(rev_env!(env, part))
+        }
+        _ => return None,
+    };
+
+    Some(rev_part)
+}
+
+fn rev_edit_group(group: &twmap::Group, part: &PartialGroup) -> PartialGroup {
+    PartialGroup {
+        name: part.name.as_ref().map(|_| group.name.clone()),
+        offset: part.offset.map(|_| group.offset),
+        parallax: part.parallax.map(|_| group.parallax),
+        clipping: part.clipping.map(|_| group.clipping),
+        clip: part.clip.map(|_| group.clip),
+    }
+}
+
+fn rev_edit_layer(map: &twmap::TwMap, g: u16, l: u16, part: &PartialLayer) -> Option<Vec<Request>> {
+    let group = map.groups.get(g as usize)?;
+    let layer = group.layers.get(l as usize)?;
+
+    // resizing a layer loses tiles, so the reverse must restore them all.
+    macro_rules! rev_physics {
+        ($kind:ident, $part:ident) => {{
+            let shape = layer.shape()?;
+            let rev_part = PartialPhysicsLayer {
+                width: $part.width.map(|_| shape.w),
+                height: $part.height.map(|_| shape.h),
+            };
+            let mut reqs = vec![Request::Edit(EditReq::Layer(
+                g,
+                l,
+                Box::new(PartialLayer::$kind(rev_part)),
+            ))];
+            if $part.width.is_some() || $part.height.is_some() {
+                for (i, layer) in group.layers.iter().enumerate() {
+                    if layer.kind().is_physics_layer() {
+                        let tiles = layer_tiles(layer, None)?;
+                        reqs.push(Request::Edit(EditReq::Tiles(g, i as u16, Box::new(tiles))));
+                    }
+                }
+            }
+            reqs
+        }};
+    }
+
+    let reqs = match (layer, part) {
+        (twmap::Layer::Game(_), PartialLayer::Game(part)) => rev_physics!(Game, part),
+        (twmap::Layer::Front(_), PartialLayer::Front(part)) => rev_physics!(Front, part),
+        (twmap::Layer::Tele(_), PartialLayer::Tele(part)) => rev_physics!(Tele, part),
+        (twmap::Layer::Speedup(_), PartialLayer::Speedup(part)) => rev_physics!(Speedup, part),
+        (twmap::Layer::Switch(_), PartialLayer::Switch(part)) => rev_physics!(Switch, part),
+        (twmap::Layer::Tune(_), PartialLayer::Tune(part)) => rev_physics!(Tune, part),
+        (twmap::Layer::Tiles(tiles_layer), PartialLayer::Tiles(part)) => {
+            let shape = tiles_layer.tiles.shape();
+            let rev_part = PartialTilesLayer {
+                width: part.width.map(|_| shape.w),
+                height: part.height.map(|_| shape.h),
+                name: part.name.as_ref().map(|_| tiles_layer.name.clone()),
+                detail: part.detail.map(|_| tiles_layer.detail),
+                color: part.color.map(|_| tiles_layer.color),
+                color_env: part.color_env.map(|_| tiles_layer.color_env),
+                color_env_offset: part.color_env_offset.map(|_| tiles_layer.color_env_offset),
+                image: part.image.map(|_| tiles_layer.image),
+                automapper_config: part
+                    .automapper_config
+                    .as_ref()
+                    .map(|_| tiles_layer.automapper_config.clone()),
+            };
+            let mut reqs = vec![Request::Edit(EditReq::Layer(
+                g,
+                l,
+                Box::new(PartialLayer::Tiles(rev_part)),
+            ))];
+            if part.width.is_some() || part.height.is_some() {
+                let tiles = layer_tiles(layer, None)?;
+                reqs.push(Request::Edit(EditReq::Tiles(g, l, Box::new(tiles))));
+            }
+            reqs
+        }
+        (twmap::Layer::Quads(quads_layer), PartialLayer::Quads(part)) => {
+            let rev_part = PartialQuadsLayer {
+                name: part.name.as_ref().map(|_| quads_layer.name.clone()),
+                detail: part.detail.map(|_| quads_layer.detail),
+                image: part.image.map(|_| quads_layer.image),
+            };
+            vec![Request::Edit(EditReq::Layer(
+                g,
+                l,
+                Box::new(PartialLayer::Quads(rev_part)),
+            ))]
+        }
+        (twmap::Layer::Sounds(sounds_layer), PartialLayer::Sounds(part)) => {
+            let rev_part = PartialSoundsLayer {
+                name: part.name.as_ref().map(|_| sounds_layer.name.clone()),
+                detail: part.detail.map(|_| sounds_layer.detail),
+                sound: part.sound.map(|_| sounds_layer.sound),
+            };
+            vec![Request::Edit(EditReq::Layer(
+                g,
+                l,
+                Box::new(PartialLayer::Sounds(rev_part)),
+            ))]
+        }
+        _ => return None,
+    };
+
+    Some(reqs)
+}
+
+fn quads_layer(map: &twmap::TwMap, g: u16, l: u16) -> Option<&twmap::QuadsLayer> {
+    match map.groups.get(g as usize)?.layers.get(l as usize)? {
+        twmap::Layer::Quads(layer) => Some(layer),
+        _ => None,
+    }
+}
+
+fn sounds_layer(map: &twmap::TwMap, g: u16, l: u16) -> Option<&twmap::SoundsLayer> {
+    match map.groups.get(g as usize)?.layers.get(l as usize)? {
+        twmap::Layer::Sounds(layer) => Some(layer),
+        _ => None,
+    }
+}
+
+/// Computes the requests that revert `req`, which is about to be applied to `map`.
+/// Returns `None` if the request cannot be reverted, or if it is invalid.
+pub(crate) fn reverse(map: &twmap::TwMap, req: &Request) -> Option<Vec<Request>> {
+    let reqs = match req {
+        Request::Create(req) => match req {
+            CreateReq::Image(..) => {
+                vec![Request::Delete(DeleteReq::Image(map.images.len() as u16))]
+            }
+            CreateReq::Envelope(_) => {
+                vec![Request::Delete(DeleteReq::Envelope(
+                    map.envelopes.len() as u16
+                ))]
+            }
+            CreateReq::Group(_) => vec![Request::Delete(DeleteReq::Group(map.groups.len() as u16))],
+            CreateReq::Layer(g, _) => {
+                let l = map.groups.get(*g as usize)?.layers.len() as u16;
+                vec![Request::Delete(DeleteReq::Layer(*g, l))]
+            }
+            CreateReq::Quad(g, l, _) => {
+                let q = quads_layer(map, *g, *l)?.quads.len() as u16;
+                vec![Request::Delete(DeleteReq::Quad(*g, *l, q))]
+            }
+            CreateReq::Sound(..) => {
+                vec![Request::Delete(DeleteReq::Sound(map.sounds.len() as u16))]
+            }
+            CreateReq::Source(g, l, _) => {
+                let s = sounds_layer(map, *g, *l)?.sources.len() as u16;
+                vec![Request::Delete(DeleteReq::Source(*g, *l, s))]
+            }
+            CreateReq::Automapper(..) => return None,
+        },
+        Request::Edit(req) => match req {
+            EditReq::Config(_) => return None,
+            EditReq::Info(part) => {
+                vec![Request::Edit(EditReq::Info(Box::new(rev_edit_info(
+                    map, part,
+                ))))]
+            }
+            EditReq::Envelope(e, part) => {
+                let env = map.envelopes.get(*e as usize)?;
+                let rev_part = rev_edit_envelope(env, part)?;
+                vec![Request::Edit(EditReq::Envelope(*e, Box::new(rev_part)))]
+            }
+            EditReq::Group(g, part) => {
+                let group = map.groups.get(*g as usize)?;
+                let rev_part = rev_edit_group(group, part);
+                vec![Request::Edit(EditReq::Group(*g, Box::new(rev_part)))]
+            }
+            EditReq::Layer(g, l, part) => rev_edit_layer(map, *g, *l, part)?,
+            EditReq::Tiles(g, l, tiles) => {
+                let layer = map.groups.get(*g as usize)?.layers.get(*l as usize)?;
+                let rev_tiles = layer_tiles(layer, Some(tiles.rect))?;
+                vec![Request::Edit(EditReq::Tiles(*g, *l, Box::new(rev_tiles)))]
+            }
+            EditReq::Quad(g, l, q, _) => {
+                let quad = quads_layer(map, *g, *l)?.quads.get(*q as usize)?;
+                vec![Request::Edit(EditReq::Quad(
+                    *g,
+                    *l,
+                    *q,
+                    Box::new(quad.clone()),
+                ))]
+            }
+            EditReq::Source(g, l, s, _) => {
+                let source = sounds_layer(map, *g, *l)?.sources.get(*s as usize)?;
+                vec![Request::Edit(EditReq::Source(
+                    *g,
+                    *l,
+                    *s,
+                    Box::new(source.clone()),
+                ))]
+            }
+            EditReq::Automap(g, l) => {
+                let layer = map.groups.get(*g as usize)?.layers.get(*l as usize)?;
+                let rev_tiles = layer_tiles(layer, None)?;
+                vec![Request::Edit(EditReq::Tiles(*g, *l, Box::new(rev_tiles)))]
+            }
+        },
+        Request::Delete(req) => match req {
+            DeleteReq::Image(i) => {
+                let image = map.images.get(*i as usize)?;
+                let last = map.images.len() as u16 - 1;
+                vec![
+                    Request::Create(create_image(image)?),
+                    Request::Move(MoveReq::Image(last, *i)),
+                ]
+            }
+            DeleteReq::Envelope(e) => {
+                let env = map.envelopes.get(*e as usize)?;
+                let last = map.envelopes.len() as u16 - 1;
+                vec![
+                    Request::Create(CreateReq::Envelope(Box::new(partial_envelope(env)))),
+                    Request::Move(MoveReq::Envelope(last, *e)),
+                ]
+            }
+            DeleteReq::Group(g) => {
+                let group = map.groups.get(*g as usize)?;
+                let last = map.groups.len() as u16 - 1;
+                let mut reqs = vec![Request::Create(CreateReq::Group(Box::new(partial_group(
+                    group,
+                ))))];
+                for (l, layer) in group.layers.iter().enumerate() {
+                    reqs.extend(create_layer(last, l as u16, layer)?);
+                }
+                reqs.push(Request::Move(MoveReq::Group(last, *g)));
+                reqs
+            }
+            DeleteReq::Layer(g, l) => {
+                let group = map.groups.get(*g as usize)?;
+                let layer = group.layers.get(*l as usize)?;
+                let last = group.layers.len() as u16 - 1;
+                let mut reqs = create_layer(*g, last, layer)?;
+                reqs.push(Request::Move(MoveReq::Layer((*g, last), (*g, *l))));
+                reqs
+            }
+            DeleteReq::Quad(g, l, q) => {
+                let quads = &quads_layer(map, *g, *l)?.quads;
+                let quad = quads.get(*q as usize)?;
+                let last = quads.len() as u16 - 1;
+                vec![
+                    Request::Create(CreateReq::Quad(*g, *l, Box::new(quad.clone()))),
+                    Request::Move(MoveReq::Quad((*g, *l, last), *q)),
+                ]
+            }
+            DeleteReq::Sound(i) => {
+                let sound = map.sounds.get(*i as usize)?;
+                let last = map.sounds.len() as u16 - 1;
+                let data = Base64(sound.data.unwrap_ref().clone());
+                vec![
+                    Request::Create(CreateReq::Sound(sound.name.clone(), data)),
+                    Request::Move(MoveReq::Sound(last, *i)),
+                ]
+            }
+            DeleteReq::Source(g, l, s) => {
+                let sources = &sounds_layer(map, *g, *l)?.sources;
+                let source = sources.get(*s as usize)?;
+                let last = sources.len() as u16 - 1;
+                vec![
+                    Request::Create(CreateReq::Source(*g, *l, Box::new(source.clone()))),
+                    Request::Move(MoveReq::Source((*g, *l, last), *s)),
+                ]
+            }
+            DeleteReq::Automapper(_) => return None,
+        },
+        Request::Move(req) => match req {
+            MoveReq::Image(src, tgt) => vec![Request::Move(MoveReq::Image(*tgt, *src))],
+            MoveReq::Envelope(src, tgt) => vec![Request::Move(MoveReq::Envelope(*tgt, *src))],
+            MoveReq::Group(src, tgt) => vec![Request::Move(MoveReq::Group(*tgt, *src))],
+            MoveReq::Layer(src, tgt) => vec![Request::Move(MoveReq::Layer(*tgt, *src))],
+            MoveReq::Quad((g, l, q), tgt) => {
+                vec![Request::Move(MoveReq::Quad((*g, *l, *tgt), *q))]
+            }
+            MoveReq::Sound(src, tgt) => vec![Request::Move(MoveReq::Sound(*tgt, *src))],
+            MoveReq::Source((g, l, s), tgt) => {
+                vec![Request::Move(MoveReq::Source((*g, *l, *tgt), *s))]
+            }
+        },
+        _ => return None,
+    };
+
+    Some(reqs)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use fixed::types::I17F15;
+    use vek::{Extent2, Vec2};
+
+    use super::*;
+    use crate::{error::Error, server::User, testing::TestServer};
+
+    fn game_layer(w: usize, h: usize) -> twmap::Layer {
+        twmap::Layer::Game(twmap::GameLayer {
+            tiles: twmap::CompressedData::Loaded(ndarray::Array2::default((h, w))),
+        })
+    }
+
+    #[test]
+    fn layer_tiles_in_bounds() {
+        let layer = game_layer(4, 3);
+        let tiles = layer_tiles(&layer, Some(Rect::new(1, 1, 3, 2))).unwrap();
+        assert_eq!(tiles.rect, Rect::new(1, 1, 3, 2));
+        assert_eq!(
+            tiles.tiles.0.len(),
+            3 * 2 * std::mem::size_of::<twmap::GameTile>()
+        );
+        assert!(layer_tiles(&layer, None).is_some());
+    }
+
+    #[test]
+    fn layer_tiles_out_of_bounds() {
+        let layer = game_layer(4, 3);
+        assert!(layer_tiles(&layer, Some(Rect::new(2, 0, 3, 1))).is_none());
+        assert!(layer_tiles(&layer, Some(Rect::new(0, 2, 1, 2))).is_none());
+    }
+
+    #[test]
+    fn layer_tiles_overflow() {
+        let layer = game_layer(4, 3);
+        assert!(layer_tiles(&layer, Some(Rect::new(u32::MAX, 0, 2, 1))).is_none());
+        assert!(layer_tiles(&layer, Some(Rect::new(0, 1, 1, u32::MAX))).is_none());
+    }
+
+    /// Tiles with the id `DEATH`, which is valid in all layers used below.
+    fn tiles(x: u32, y: u32, w: u32, h: u32) -> Box<Tiles> {
+        Box::new(Tiles {
+            rect: Rect::new(x, y, w, h),
+            tiles: Base64([2, 0, 0, 0].repeat((w * h) as usize)),
+            compression: Compression::None,
+        })
+    }
+
+    fn quad(x: i32) -> Box<twmap::Quad> {
+        let pos = Vec2::new(I17F15::from_num(x), I17F15::from_num(0));
+        let size = Extent2::new(I17F15::from_num(1), I17F15::from_num(1));
+        Box::new(twmap::Quad::new(pos, size).unwrap())
+    }
+
+    /// A map with a game and front layer in the physics group 0, and a design
+    /// group 1 with a tiles layer and a quads layer of 3 quads.
+    fn setup(name: &str) -> (TestServer, Arc<User>) {
+        let server = TestServer::new(name);
+        let user = server.join(None).unwrap();
+        let reqs = [
+            Request::Create(CreateReq::Layer(
+                0,
+                Box::new(PartialLayer::Front(Default::default())),
+            )),
+            Request::Edit(EditReq::Tiles(0, 0, tiles(5, 5, 3, 3))),
+            Request::Edit(EditReq::Tiles(0, 1, tiles(0, 6, 8, 2))),
+            Request::Create(CreateReq::Group(Box::new(PartialGroup {
+                name: Some("design".to_owned()),
+                ..Default::default()
+            }))),
+            Request::Create(CreateReq::Layer(
+                1,
+                Box::new(PartialLayer::Tiles(Default::default())),
+            )),
+            Request::Edit(EditReq::Tiles(1, 0, tiles(1, 1, 4, 4))),
+            Request::Create(CreateReq::Layer(
+                1,
+                Box::new(PartialLayer::Quads(Default::default())),
+            )),
+            Request::Create(CreateReq::Quad(1, 1, quad(0))),
+            Request::Create(CreateReq::Quad(1, 1, quad(1))),
+            Request::Create(CreateReq::Quad(1, 1, quad(2))),
+        ];
+        for req in reqs {
+            server.request(&user, req).unwrap();
+        }
+        (server, user)
+    }
+
+    /// Applies a request, then checks that undo restores the map and that redo
+    /// applies the request again.
+    #[track_caller]
+    fn check_undo_redo(server: &TestServer, user: &Arc<User>, req: Request) {
+        let before = server.map();
+        server.request(user, req).unwrap();
+        let after = server.map();
+        assert!(before != after, "the request did not change the map");
+
+        server.request(user, Request::Undo).unwrap();
+        assert!(server.map() == before, "undo did not restore the map");
+        server.request(user, Request::Redo).unwrap();
+        assert!(server.map() == after, "redo did not apply the request");
+        server.request(user, Request::Undo).unwrap();
+    }
+
+    #[test]
+    fn undo_delete_layer() {
+        let (server, user) = setup("undo-delete-layer");
+        check_undo_redo(&server, &user, Request::Delete(DeleteReq::Layer(1, 0)));
+        check_undo_redo(&server, &user, Request::Delete(DeleteReq::Layer(1, 1)));
+        check_undo_redo(&server, &user, Request::Delete(DeleteReq::Layer(0, 1)));
+    }
+
+    #[test]
+    fn undo_delete_group() {
+        let (server, user) = setup("undo-delete-group");
+        server
+            .request(&user, Request::Create(CreateReq::Group(Box::default())))
+            .unwrap();
+        // the deleted group is not the last one, it must be moved back in place.
+        check_undo_redo(&server, &user, Request::Delete(DeleteReq::Group(1)));
+    }
+
+    #[test]
+    fn undo_resize_physics_layer() {
+        let (server, user) = setup("undo-resize-physics-layer");
+        let shrink = PartialPhysicsLayer {
+            width: Some(4),
+            height: Some(4),
+        };
+        let req = Request::Edit(EditReq::Layer(0, 0, Box::new(PartialLayer::Game(shrink))));
+        // the tiles outside of the new size, in both physics layers, are restored.
+        check_undo_redo(&server, &user, req);
+
+        let grow = PartialPhysicsLayer {
+            width: Some(12),
+            height: None,
+        };
+        let req = Request::Edit(EditReq::Layer(0, 1, Box::new(PartialLayer::Front(grow))));
+        check_undo_redo(&server, &user, req);
+    }
+
+    #[test]
+    fn undo_moves() {
+        let (server, user) = setup("undo-moves");
+        check_undo_redo(&server, &user, Request::Move(MoveReq::Group(1, 0)));
+        check_undo_redo(
+            &server,
+            &user,
+            Request::Move(MoveReq::Layer((1, 1), (1, 0))),
+        );
+        check_undo_redo(
+            &server,
+            &user,
+            Request::Move(MoveReq::Layer((1, 0), (0, 0))),
+        );
+        check_undo_redo(&server, &user, Request::Move(MoveReq::Quad((1, 1, 0), 2)));
+        check_undo_redo(&server, &user, Request::Move(MoveReq::Quad((1, 1, 2), 1)));
+    }
+
+    #[test]
+    fn undo_quad_edits() {
+        let (server, user) = setup("undo-quad-edits");
+        check_undo_redo(
+            &server,
+            &user,
+            Request::Create(CreateReq::Quad(1, 1, quad(5))),
+        );
+        check_undo_redo(
+            &server,
+            &user,
+            Request::Edit(EditReq::Quad(1, 1, 1, quad(7))),
+        );
+        check_undo_redo(&server, &user, Request::Delete(DeleteReq::Quad(1, 1, 0)));
+        check_undo_redo(&server, &user, Request::Delete(DeleteReq::Quad(1, 1, 2)));
+    }
+
+    #[test]
+    fn undo_redo_sequence() {
+        let (server, user) = setup("undo-redo-sequence");
+        let first = server.map();
+        server
+            .request(&user, Request::Delete(DeleteReq::Quad(1, 1, 0)))
+            .unwrap();
+        let second = server.map();
+        server
+            .request(
+                &user,
+                Request::Edit(EditReq::Tiles(0, 0, tiles(0, 0, 2, 2))),
+            )
+            .unwrap();
+        let third = server.map();
+
+        server.request(&user, Request::Undo).unwrap();
+        server.request(&user, Request::Undo).unwrap();
+        assert!(server.map() == first);
+        server.request(&user, Request::Redo).unwrap();
+        assert!(server.map() == second);
+        server.request(&user, Request::Redo).unwrap();
+        assert!(server.map() == third);
+        assert!(matches!(
+            server.request(&user, Request::Redo),
+            Err(Error::NothingToRedo)
+        ));
+
+        // a new edit discards the undone operations.
+        server.request(&user, Request::Undo).unwrap();
+        server
+            .request(&user, Request::Move(MoveReq::Group(1, 0)))
+            .unwrap();
+        assert!(matches!(
+            server.request(&user, Request::Redo),
+            Err(Error::NothingToRedo)
+        ));
+    }
+
+    #[test]
+    fn irreversible_edit_clears_history() {
+        let (server, user) = setup("irreversible-edit-clears-history");
+        let invalid = twmap::Layer::Invalid(twmap::InvalidLayerKind::Unknown(42));
+        server.room().write().map().groups[1].layers.push(invalid);
+
+        // a group with a layer of unknown kind cannot be created again.
+        let irreversible = [
+            Request::Delete(DeleteReq::Group(1)),
+            Request::Batch(vec![
+                Request::Move(MoveReq::Quad((1, 1, 0), 2)),
+                Request::Delete(DeleteReq::Group(1)),
+            ]),
+        ];
+        for req in irreversible {
+            let before = server.map();
+            server
+                .request(&user, Request::Move(MoveReq::Group(1, 0)))
+                .unwrap();
+            server
+                .request(&user, Request::Move(MoveReq::Group(0, 1)))
+                .unwrap();
+            server.request(&user, req).unwrap();
+            assert!(server.map() != before);
+            assert!(matches!(
+                server.request(&user, Request::Undo),
+                Err(Error::NothingToUndo)
+            ));
+            server.room().write().restore_snapshot(before);
+        }
+
+        // edits after the irreversible one can be undone again.
+        check_undo_redo(&server, &user, Request::Move(MoveReq::Group(1, 0)));
+    }
+
+    fn design_batch() -> Request {
+        Request::Batch(vec![
+            Request::Create(CreateReq::Layer(
+                1,
+                Box::new(PartialLayer::Tiles(Default::default())),
+            )),
+            Request::Edit(EditReq::Tiles(1, 2, tiles(0, 0, 2, 2))),
+            Request::Delete(DeleteReq::Quad(1, 1, 0)),
+            Request::Move(MoveReq::Layer((1, 2), (1, 0))),
+        ])
+    }
+
+    #[test]
+    fn failed_batch_restores_map() {
+        let (server, user) = setup("failed-batch-restores-map");
+        let other = server.join(None).unwrap();
+        server.received(&other);
+        let before = server.map();
+
+        let Request::Batch(mut reqs) = design_batch() else {
+            unreachable!()
+        };
+        reqs.push(Request::Delete(DeleteReq::Quad(1, 2, 9)));
+        let res = server.request(&user, Request::Batch(reqs));
+        assert!(matches!(res, Err(Error::QuadNotFound)), "{res:?}");
+        assert!(server.map() == before);
+        assert!(server.received(&other).is_empty());
+
+        // the failed batch is not in the history, undo reverts the last quad creation.
+        server.request(&user, Request::Undo).unwrap();
+        assert_eq!(quads_layer(&server.map(), 1, 1).unwrap().quads.len(), 2);
+    }
+
+    #[test]
+    fn batch_is_broadcast_once() {
+        let (server, user) = setup("batch-is-broadcast-once");
+        let other = server.join(None).unwrap();
+        server.received(&other);
+
+        server.request(&user, design_batch()).unwrap();
+        let received = server.received(&other);
+        assert_eq!(received.len(), 1, "{received:?}");
+        assert!(
+            matches!(
+                &received[0].content,
+                Message::Request(Request::Batch(reqs)) if reqs.len() == 4
+            ),
+            "{received:?}"
+        );
+    }
+
+    #[test]
+    fn undo_batch() {
+        let (server, user) = setup("undo-batch");
+        check_undo_redo(&server, &user, design_batch());
+    }
+
+    fn op(i: u16) -> Operation {
+        Operation {
+            forward: vec![Request::Delete(DeleteReq::Image(i))],
+            reverse: vec![],
+        }
+    }
+
+    fn op_index(op: Option<Operation>) -> Option<u16> {
+        match op?.forward.as_slice() {
+            [Request::Delete(DeleteReq::Image(i))] => Some(*i),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn history_is_capped() {
+        let mut history = History::default();
+        let len = MAX_HISTORY_LEN as u16;
+        for i in 0..len + 5 {
+            history.push(op(i));
+        }
+
+        for i in (5..len + 5).rev() {
+            assert_eq!(op_index(history.undo()), Some(i));
+        }
+        // the oldest operations were dropped.
+        assert!(history.undo().is_none());
+
+        for i in 5..len + 5 {
+            assert_eq!(op_index(history.redo()), Some(i));
+        }
+        assert!(history.redo().is_none());
+    }
+}
@@ -10,11 +10,20 @@ mod base64;
 mod checks;
 pub mod cli;
 mod error;
+mod history;
+mod lints;
+mod locks;
 mod map_cfg;
 mod protocol;
+mod revision;
 mod room;
 pub mod router;
+#[cfg(feature = "schema")]
+mod schema;
 mod server;
+#[cfg(test)]
+mod testing;
+mod throttle;
 mod twmap_map_checks;
 mod twmap_map_edit;
 mod util;
@@ -69,6 +78,7 @@ pub fn create_server(cli: &Cli) -> std::io::Result<Server> {
         for path in cli.data_dirs.iter() {
             let rooms = std::fs::read_dir(path.join("maps"))?
                 .filter_map(|e| e.ok())
+                .filter(|e| e.path().extension().is_some_and(|ext| ext == "map"))
                 .filter_map(|e| {
                     let map_path = e.path();
                     let am_path = path.join("editor/automap");
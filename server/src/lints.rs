@@ -0,0 +1,735 @@
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+
+use ndarray::Array2;
+use twmap::{CompressedData, GameTile, Layer, TilesLoadInfo, TwMap};
+
+use crate::{
+    base64::Base64,
+    protocol::{MapLint, Reachability},
+    twmap_map_checks::tile_id::*,
+};
+
+// Gameplay lints of DDNet maps. Unlike the map checks, a map with lints is valid and
+// can be played, but it probably does not play as intended. Only the layers of the
+// physics group are analyzed. Each problem is reported once, at its first tile.
+
+struct Tiles<'a, T> {
+    layer: u16,
+    tiles: &'a Array2<T>,
+}
+
+fn loaded<T>(
+    layer: usize,
+    tiles: &CompressedData<Array2<T>, TilesLoadInfo>,
+) -> Option<Tiles<'_, T>> {
+    match tiles {
+        CompressedData::Loaded(tiles) => Some(Tiles {
+            layer: layer as u16,
+            tiles,
+        }),
+        CompressedData::Compressed(..) => None,
+    }
+}
+
+/// Position of the first tile of each number, for the tiles matching `number`.
+fn first_by_number<T>(
+    tiles: &Array2<T>,
+    number: impl Fn(&T) -> Option<u8>,
+) -> BTreeMap<u8, (usize, usize)> {
+    let mut res = BTreeMap::new();
+    for ((y, x), tile) in tiles.indexed_iter() {
+        if let Some(n) = number(tile) {
+            res.entry(n).or_insert((x, y));
+        }
+    }
+    res
+}
+
+fn first_tile(tiles: &Array2<GameTile>, id: u8) -> Option<(usize, usize)> {
+    tiles
+        .indexed_iter()
+        .find(|(_, tile)| tile.id == id)
+        .map(|((y, x), _)| (x, y))
+}
+
+struct Linter {
+    group: u16,
+    lints: Vec<MapLint>,
+}
+
+impl Linter {
+    fn push(&mut self, layer: u16, (x, y): (usize, usize), msg: String) {
+        self.lints.push(MapLint {
+            group: self.group,
+            layer,
+            x: x as u32,
+            y: y as u32,
+            msg,
+        })
+    }
+
+    fn lint_tele(&mut self, tele: &Tiles<twmap::Tele>) {
+        let numbers = |ids: &[u8]| {
+            first_by_number(tele.tiles, |t| {
+                (t.number != 0 && ids.contains(&t.id)).then_some(t.number)
+            })
+        };
+
+        let outs = numbers(&[TELEOUT]);
+        let ins = numbers(&[TELEIN, TELEINEVIL, TELEINWEAPON, TELEINHOOK]);
+        for (n, pos) in ins {
+            if !outs.contains_key(&n) {
+                self.push(
+                    tele.layer,
+                    pos,
+                    format!("teleporter {n} has no tele out {n}"),
+                );
+            }
+        }
+
+        let check_outs = numbers(&[TELECHECKOUT]);
+        let checks = numbers(&[TELECHECK]);
+        for (n, pos) in checks {
+            if !check_outs.contains_key(&n) {
+                self.push(
+                    tele.layer,
+                    pos,
+                    format!("tele checkpoint {n} has no checkpoint tele out {n}"),
+                );
+            }
+        }
+    }
+
+    fn lint_switch(&mut self, switch: &Tiles<twmap::Switch>) {
+        let is_activator = |id| (SWITCHTIMEDOPEN..=SWITCHCLOSE).contains(&id);
+        let activators = first_by_number(switch.tiles, |t| {
+            (t.number != 0 && is_activator(t.id)).then_some(t.number)
+        });
+        let controlled = first_by_number(switch.tiles, |t| {
+            (t.number != 0 && t.id != AIR && !is_activator(t.id)).then_some(t.number)
+        });
+        for (n, pos) in activators {
+            if !controlled.contains_key(&n) {
+                self.push(
+                    switch.layer,
+                    pos,
+                    format!("switch {n} is activated but controls no door, laser or tile"),
+                );
+            }
+        }
+    }
+
+    fn lint_tune(&mut self, tune: &Tiles<twmap::Tune>, settings: &[String]) {
+        let configured: HashSet<u8> = settings
+            .iter()
+            .filter_map(|setting| {
+                let mut args = setting.split_whitespace();
+                match args.next() {
+                    Some("tune_zone") => args.next()?.parse().ok(),
+                    _ => None,
+                }
+            })
+            .collect();
+        let zones = first_by_number(tune.tiles, |t| {
+            (t.number != 0 && t.id == TUNE).then_some(t.number)
+        });
+        for (n, pos) in zones {
+            if !configured.contains(&n) {
+                self.push(
+                    tune.layer,
+                    pos,
+                    format!("tune zone {n} has no tune_zone setting in the map info"),
+                );
+            }
+        }
+    }
+
+    fn lint_race(&mut self, game: &Tiles<GameTile>, front: Option<&Tiles<GameTile>>) {
+        let layers = || std::iter::once(game).chain(front);
+        let find = |id| layers().find_map(|l| Some((l.layer, first_tile(l.tiles, id)?)));
+
+        match (find(START), find(FINISH)) {
+            (Some((layer, pos)), None) => {
+                self.push(layer, pos, "start line without finish line".to_owned())
+            }
+            (None, Some((layer, pos))) => {
+                self.push(layer, pos, "finish line without start line".to_owned())
+            }
+            _ => (),
+        }
+
+        if find(START).is_none() {
+            return;
+        }
+
+        // tiles of a region are connected without going through solid tiles. Regions
+        // with a teleporter are assumed to reach the rest of the map.
+        let regions = regions(game.tiles);
+        let reaching: HashSet<u32> = layers()
+            .flat_map(|l| l.tiles.indexed_iter())
+            .filter(|(_, t)| matches!(t.id, START | TELEIN | TELEINEVIL | TELECHECKIN))
+            .filter_map(|(pos, _)| regions.get(pos).copied())
+            .collect();
+
+        let spawns = (ENTITY_OFFSET + ENTITY_SPAWN)..=(ENTITY_OFFSET + ENTITY_SPAWN_BLUE);
+        for l in layers() {
+            for ((y, x), tile) in l.tiles.indexed_iter() {
+                let region = regions.get((y, x)).copied().unwrap_or_default();
+                if spawns.contains(&tile.id) && !reaching.contains(&region) {
+                    self.push(
+                        l.layer,
+                        (x, y),
+                        "spawn cannot reach any start line".to_owned(),
+                    );
+                }
+            }
+        }
+    }
+}
+
+fn is_wall(tile: &GameTile) -> bool {
+    matches!(tile.id, SOLID | NOHOOK)
+}
+
+/// Labels the connected areas of non-solid tiles. Solid tiles have label 0.
+fn regions(game: &Array2<GameTile>) -> Array2<u32> {
+    let (h, w) = game.dim();
+    let mut labels = Array2::zeros((h, w));
+    let mut next = 1;
+    let mut queue = VecDeque::new();
+
+    for ((y, x), tile) in game.indexed_iter() {
+        if labels[(y, x)] != 0 || is_wall(tile) {
+            continue;
+        }
+        labels[(y, x)] = next;
+        queue.push_back((y, x));
+        while let Some((y, x)) = queue.pop_front() {
+            let neighbours = [
+                (y.wrapping_sub(1), x),
+                (y + 1, x),
+                (y, x.wrapping_sub(1)),
+                (y, x + 1),
+            ];
+            for pos in neighbours {
+                if game.get(pos).is_some_and(|t| !is_wall(t)) && labels[pos] == 0 {
+                    labels[pos] = next;
+                    queue.push_back(pos);
+                }
+            }
+        }
+        next += 1;
+    }
+
+    labels
+}
+
+struct PhysicsLayers<'a> {
+    group: u16,
+    game: Option<Tiles<'a, GameTile>>,
+    front: Option<Tiles<'a, GameTile>>,
+    tele: Option<Tiles<'a, twmap::Tele>>,
+    switch: Option<Tiles<'a, twmap::Switch>>,
+    tune: Option<Tiles<'a, twmap::Tune>>,
+}
+
+fn physics_layers(map: &TwMap) -> Option<PhysicsLayers<'_>> {
+    let (group_index, group) = map
+        .groups
+        .iter()
+        .enumerate()
+        .find(|(_, group)| group.is_physics_group())?;
+    Some(group_layers(
+        group_index as u16,
+        group.layers.iter().enumerate(),
+    ))
+}
+
+fn group_layers<'a>(
+    group_index: u16,
+    group_layers: impl Iterator<Item = (usize, &'a Layer)>,
+) -> PhysicsLayers<'a> {
+    let mut layers = PhysicsLayers {
+        group: group_index,
+        game: None,
+        front: None,
+        tele: None,
+        switch: None,
+        tune: None,
+    };
+    for (i, layer) in group_layers {
+        match layer {
+            Layer::Game(l) => layers.game = loaded(i, &l.tiles),
+            Layer::Front(l) => layers.front = loaded(i, &l.tiles),
+            Layer::Tele(l) => layers.tele = loaded(i, &l.tiles),
+            Layer::Switch(l) => layers.switch = loaded(i, &l.tiles),
+            Layer::Tune(l) => layers.tune = loaded(i, &l.tiles),
+            _ => (),
+        }
+    }
+    layers
+}
+
+/// A copy of the game, front and tele layers of a map with their index in the
+/// physics group, to analyse them without locking the room.
+pub struct PhysicsGroup {
+    index: u16,
+    layers: Vec<(usize, Layer)>,
+}
+
+pub fn physics_group(map: &TwMap) -> Option<PhysicsGroup> {
+    let (index, group) = map
+        .groups
+        .iter()
+        .enumerate()
+        .find(|(_, group)| group.is_physics_group())?;
+    let layers = group
+        .layers
+        .iter()
+        .enumerate()
+        .filter(|(_, l)| matches!(l, Layer::Game(_) | Layer::Front(_) | Layer::Tele(_)))
+        .map(|(i, l)| (i, l.clone()))
+        .collect();
+    Some(PhysicsGroup {
+        index: index as u16,
+        layers,
+    })
+}
+
+pub fn lint_map(map: &TwMap) -> Vec<MapLint> {
+    let Some(layers) = physics_layers(map) else {
+        return Vec::new();
+    };
+
+    let mut linter = Linter {
+        group: layers.group,
+        lints: Vec::new(),
+    };
+    if let Some(game) = &layers.game {
+        linter.lint_race(game, layers.front.as_ref());
+    }
+    if let Some(tele) = &layers.tele {
+        linter.lint_tele(tele);
+    }
+    if let Some(switch) = &layers.switch {
+        linter.lint_switch(switch);
+    }
+    if let Some(tune) = &layers.tune {
+        linter.lint_tune(tune, &map.info.settings);
+    }
+    linter.lints
+}
+
+// Reachability is a flood fill from the spawns through non-solid tiles and
+// teleporters. It ignores the physics: jumps, freeze, hook and speedups. Death tiles
+// are reached but nothing is reached through them. The result is downsampled to
+// chunks of tiles, which is enough to spot closed-off areas.
+
+const REACHABILITY_CHUNK: usize = 4;
+
+fn is_death(tile: &GameTile) -> bool {
+    tile.id == DEATH
+}
+
+/// Computes the tiles reachable from the spawns, and reports finish lines and
+/// areas with gameplay tiles that cannot be reached.
+pub fn reachability(group: &PhysicsGroup) -> Option<Reachability> {
+    let layers = group_layers(group.index, group.layers.iter().map(|(i, l)| (*i, l)));
+    let game = layers.game.as_ref()?;
+    let front = layers.front.as_ref();
+    let physics = || std::iter::once(game).chain(front);
+    let (h, w) = game.tiles.dim();
+
+    let mut tele_outs: HashMap<u8, Vec<(usize, usize)>> = HashMap::new();
+    let mut check_outs = Vec::new();
+    if let Some(tele) = &layers.tele {
+        for (pos, tile) in tele.tiles.indexed_iter() {
+            match tile.id {
+                TELEOUT => tele_outs.entry(tile.number).or_default().push(pos),
+                TELECHECKOUT => check_outs.push(pos),
+                _ => (),
+            }
+        }
+    }
+
+    let spawns = (ENTITY_OFFSET + ENTITY_SPAWN)..=(ENTITY_OFFSET + ENTITY_SPAWN_BLUE);
+    let mut reached = Array2::from_elem((h, w), false);
+    let mut queue = VecDeque::new();
+    let visit = |pos: (usize, usize), reached: &mut Array2<bool>, queue: &mut VecDeque<_>| {
+        if game.tiles.get(pos).is_some_and(|t| !is_wall(t)) && !reached[pos] {
+            reached[pos] = true;
+            queue.push_back(pos);
+        }
+    };
+
+    for l in physics() {
+        for (pos, tile) in l.tiles.indexed_iter() {
+            if spawns.contains(&tile.id) {
+                visit(pos, &mut reached, &mut queue);
+            }
+        }
+    }
+
+    while let Some((y, x)) = queue.pop_front() {
+        if physics().any(|l| l.tiles.get((y, x)).is_some_and(is_death)) {
+            continue;
+        }
+        if let Some(tile) = layers.tele.as_ref().and_then(|l| l.tiles.get((y, x))) {
+            let outs = match tile.id {
+                TELEIN | TELEINEVIL | TELEINWEAPON | TELEINHOOK => {
+                    tele_outs.get(&tile.number).map(Vec::as_slice)
+                }
+                TELECHECKIN | TELECHECKINEVIL => Some(check_outs.as_slice()),
+                _ => None,
+            };
+            for pos in outs.unwrap_or_default() {
+                visit(*pos, &mut reached, &mut queue);
+            }
+        }
+        let neighbours = [
+            (y.wrapping_sub(1), x),
+            (y + 1, x),
+            (y, x.wrapping_sub(1)),
+            (y, x + 1),
+        ];
+        for pos in neighbours {
+            visit(pos, &mut reached, &mut queue);
+        }
+    }
+
+    // one lint per region, at its first unreachable finish or gameplay tile.
+    let mut linter = Linter {
+        group: layers.group,
+        lints: Vec::new(),
+    };
+    let regions = regions(game.tiles);
+    let mut reported = HashSet::new();
+    for (id, msg) in [
+        (Some(FINISH), "finish line is not reachable from the spawns"),
+        (None, "closed-off area is not reachable from the spawns"),
+    ] {
+        for l in physics() {
+            for ((y, x), tile) in l.tiles.indexed_iter() {
+                let region = regions[(y, x)];
+                let matches = match id {
+                    Some(id) => tile.id == id,
+                    None => tile.id != AIR && !is_wall(tile),
+                };
+                if matches && region != 0 && !reached[(y, x)] && reported.insert(region) {
+                    linter.push(l.layer, (x, y), msg.to_owned());
+                }
+            }
+        }
+    }
+
+    let cw = w.div_ceil(REACHABILITY_CHUNK);
+    let ch = h.div_ceil(REACHABILITY_CHUNK);
+    let mut bitmap = vec![0u8; (cw * ch).div_ceil(8)];
+    for ((y, x), _) in reached.indexed_iter().filter(|(_, r)| **r) {
+        let i = (y / REACHABILITY_CHUNK) * cw + x / REACHABILITY_CHUNK;
+        bitmap[i / 8] |= 1 << (i % 8);
+    }
+
+    Some(Reachability {
+        width: w as u32,
+        height: h as u32,
+        chunk_size: REACHABILITY_CHUNK as u32,
+        reachable: Base64(bitmap),
+        lints: linter.lints,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use twmap::TileFlags;
+
+    use super::*;
+    use crate::{
+        error::Error,
+        testing::{TestServer, MAP},
+    };
+
+    /// Tiles of a layer drawn as text, one line per row.
+    fn tiles<T: Clone>(rows: &[&str], tile: impl Fn(char) -> T) -> Array2<T> {
+        let tiles = rows.iter().flat_map(|row| row.chars()).map(tile).collect();
+        Array2::from_shape_vec((rows.len(), rows[0].len()), tiles).unwrap()
+    }
+
+    fn game(rows: &[&str]) -> Layer {
+        let tiles = tiles(rows, |c| {
+            let id = match c {
+                '.' => AIR,
+                '#' => SOLID,
+                'x' => DEATH,
+                '*' => FREEZE,
+                'S' => ENTITY_OFFSET + ENTITY_SPAWN,
+                '>' => START,
+                '<' => FINISH,
+                _ => panic!("unknown game tile {c}"),
+            };
+            GameTile::new(id, TileFlags::empty())
+        });
+        Layer::Game(twmap::GameLayer {
+            tiles: CompressedData::Loaded(tiles),
+        })
+    }
+
+    fn tele(rows: &[&str]) -> Layer {
+        let tiles = tiles(rows, |c| {
+            let (id, number) = match c {
+                '.' => (AIR, 0),
+                'i' => (TELEIN, 1),
+                'o' => (TELEOUT, 1),
+                'k' => (TELECHECK, 1),
+                'c' => (TELECHECKIN, 1),
+                'C' => (TELECHECKOUT, 1),
+                _ => panic!("unknown tele tile {c}"),
+            };
+            twmap::Tele { number, id }
+        });
+        Layer::Tele(twmap::TeleLayer {
+            tiles: CompressedData::Loaded(tiles),
+        })
+    }
+
+    fn switch(rows: &[&str]) -> Layer {
+        let tiles = tiles(rows, |c| {
+            let (id, number) = match c {
+                '.' => (AIR, 0),
+                'a' => (SWITCHTIMEDOPEN, 1),
+                'd' => (ENTITY_OFFSET + ENTITY_DOOR, 1),
+                _ => panic!("unknown switch tile {c}"),
+            };
+            twmap::Switch {
+                number,
+                id,
+                flags: TileFlags::empty(),
+                delay: 0,
+            }
+        });
+        Layer::Switch(twmap::SwitchLayer {
+            tiles: CompressedData::Loaded(tiles),
+        })
+    }
+
+    fn tune(rows: &[&str]) -> Layer {
+        let tiles = tiles(rows, |c| match c {
+            '.' => twmap::Tune { number: 0, id: AIR },
+            't' => twmap::Tune {
+                number: 1,
+                id: TUNE,
+            },
+            _ => panic!("unknown tune tile {c}"),
+        });
+        Layer::Tune(twmap::TuneLayer {
+            tiles: CompressedData::Loaded(tiles),
+        })
+    }
+
+    /// A map with the layers in its physics group, which is the second group.
+    fn physics_map(layers: Vec<Layer>) -> TwMap {
+        let mut map = TwMap::empty(twmap::Version::DDNet06);
+        map.groups.push(twmap::Group::default());
+        map.groups.push(twmap::Group {
+            layers,
+            ..twmap::Group::physics()
+        });
+        map
+    }
+
+    fn reachability_of(layers: Vec<Layer>) -> Reachability {
+        let group = physics_group(&physics_map(layers)).unwrap();
+        reachability(&group).unwrap()
+    }
+
+    /// (layer, x, y, message) of the lints.
+    fn lint_positions(lints: &[MapLint]) -> Vec<(u16, u32, u32, &str)> {
+        assert!(lints.iter().all(|l| l.group == 1));
+        lints
+            .iter()
+            .map(|l| (l.layer, l.x, l.y, l.msg.as_str()))
+            .collect()
+    }
+
+    // 12x8 tiles, the chunks are 3x2.
+    const WALLED: [&str; 8] = [
+        "S...#.......",
+        "....#...<...",
+        "....#.......",
+        "....#.......",
+        "############",
+        "............",
+        "..*.........",
+        "............",
+    ];
+
+    #[test]
+    fn reachability_stops_at_walls() {
+        let res = reachability_of(vec![game(&WALLED)]);
+        assert_eq!((res.width, res.height, res.chunk_size), (12, 8, 4));
+        assert_eq!(res.reachable.0, [0b000001]);
+        assert_eq!(
+            lint_positions(&res.lints),
+            [
+                (0, 8, 1, "finish line is not reachable from the spawns"),
+                (0, 2, 6, "closed-off area is not reachable from the spawns"),
+            ]
+        );
+    }
+
+    #[test]
+    fn reachability_through_teleporters() {
+        let tele = tele(&[
+            "...i........",
+            "............",
+            "......o...c.",
+            "............",
+            "............",
+            "............",
+            ".C..........",
+            "............",
+        ]);
+        let res = reachability_of(vec![game(&WALLED), tele]);
+        assert_eq!(res.reachable.0, [0b111111]);
+        assert_eq!(lint_positions(&res.lints), []);
+    }
+
+    #[test]
+    fn reachability_death_tiles_are_sinks() {
+        let mut rows = WALLED;
+        rows[4] = "x###########";
+        // the teleporter on the death tile is never taken.
+        let tele = tele(&[
+            "............",
+            "............",
+            "............",
+            "............",
+            "i...........",
+            "............",
+            "........o...",
+            "............",
+        ]);
+        let res = reachability_of(vec![game(&rows), tele]);
+        assert_eq!(res.reachable.0, [0b001001]);
+        assert_eq!(
+            lint_positions(&res.lints),
+            [
+                (0, 8, 1, "finish line is not reachable from the spawns"),
+                (0, 2, 6, "closed-off area is not reachable from the spawns"),
+            ]
+        );
+    }
+
+    #[test]
+    fn reachability_without_game_layer() {
+        let map = physics_map(vec![tele(&["i"])]);
+        let group = physics_group(&map).unwrap();
+        assert!(reachability(&group).is_none());
+        assert!(physics_group(&TwMap::empty(twmap::Version::DDNet06)).is_none());
+    }
+
+    #[test]
+    fn get_reachability() {
+        let server = TestServer::new("get-reachability");
+        let res = server.server.get_reachability(MAP).unwrap();
+        assert_eq!((res.width, res.height), (8, 8));
+        assert_eq!(res.reachable.0, [0]);
+
+        {
+            let room = server.room();
+            let mut room = room.write();
+            let game = room.map().find_physics_layer_mut::<twmap::GameLayer>();
+            let tiles = game.unwrap().tiles.unwrap_mut();
+            tiles[(5, 1)] = GameTile::new(ENTITY_OFFSET + ENTITY_SPAWN, TileFlags::empty());
+            for x in 0..8 {
+                tiles[(3, x)] = GameTile::new(SOLID, TileFlags::empty());
+            }
+        }
+        let res = server.server.get_reachability(MAP).unwrap();
+        assert_eq!(res.reachable.0, [0b1100]);
+
+        let res = server.server.get_reachability("nope");
+        assert!(matches!(res, Err(Error::MapNotFound)), "{res:?}");
+    }
+
+    #[test]
+    fn spawn_cannot_reach_start() {
+        let lints = lint_map(&physics_map(vec![game(&["S.#>.<", "..#..."])]));
+        assert_eq!(
+            lint_positions(&lints),
+            [(0, 0, 0, "spawn cannot reach any start line")]
+        );
+
+        let lints = lint_map(&physics_map(vec![game(&["S..>.<", "..#..."])]));
+        assert_eq!(lint_positions(&lints), []);
+    }
+
+    /// The lints of a map with a game layer and another physics layer.
+    fn lints_with(layer: Layer) -> Vec<MapLint> {
+        lint_map(&physics_map(vec![game(&["..."]), layer]))
+    }
+
+    #[test]
+    fn lint_tele_without_tele_out() {
+        assert_eq!(
+            lint_positions(&lints_with(tele(&[".i."]))),
+            [(1, 1, 0, "teleporter 1 has no tele out 1")]
+        );
+        assert_eq!(lint_positions(&lints_with(tele(&[".io"]))), []);
+    }
+
+    #[test]
+    fn lint_tele_checkpoint_without_tele_out() {
+        assert_eq!(
+            lint_positions(&lints_with(tele(&["..k"]))),
+            [(1, 2, 0, "tele checkpoint 1 has no checkpoint tele out 1")]
+        );
+        assert_eq!(lint_positions(&lints_with(tele(&["C.k"]))), []);
+    }
+
+    #[test]
+    fn lint_unused_switch_activator() {
+        assert_eq!(
+            lint_positions(&lints_with(switch(&["a.."]))),
+            [(
+                1,
+                0,
+                0,
+                "switch 1 is activated but controls no door, laser or tile"
+            )]
+        );
+        assert_eq!(lint_positions(&lints_with(switch(&["a.d"]))), []);
+    }
+
+    #[test]
+    fn lint_unconfigured_tune_zone() {
+        let mut map = physics_map(vec![game(&["..."]), tune(&[".t."])]);
+        assert_eq!(
+            lint_positions(&lint_map(&map)),
+            [(
+                1,
+                1,
+                0,
+                "tune zone 1 has no tune_zone setting in the map info"
+            )]
+        );
+        map.info.settings.push("tune_zone 1 gravity 0".to_owned());
+        assert_eq!(lint_positions(&lint_map(&map)), []);
+    }
+
+    #[test]
+    fn lint_start_without_finish() {
+        let lints = lint_map(&physics_map(vec![game(&["S>."])]));
+        assert_eq!(
+            lint_positions(&lints),
+            [(0, 1, 0, "start line without finish line")]
+        );
+        let lints = lint_map(&physics_map(vec![game(&["S.<"])]));
+        assert_eq!(
+            lint_positions(&lints),
+            [(0, 2, 0, "finish line without start line")]
+        );
+        let lints = lint_map(&physics_map(vec![game(&["S><"])]));
+        assert_eq!(lint_positions(&lints), []);
+    }
+}
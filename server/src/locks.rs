@@ -0,0 +1,262 @@
+use std::collections::HashMap;
+
+use uuid::Uuid;
+
+use crate::{error::Error, protocol::*, revision::map_layer};
+
+// Users can lock a layer while they work on it. Edits of a locked layer by other
+// users are rejected with `Error::LayerLocked`. Locks are soft: they only live in
+// memory and are released when the user leaves the room or disconnects. Locks are
+// keyed by layer index, so they follow the layer when other layers are moved or
+// deleted, like requests are rebased in the revision log.
+
+#[derive(Debug, Default, Clone)]
+pub struct LayerLocks {
+    locks: HashMap<(u16, u16), Uuid>,
+}
+
+impl LayerLocks {
+    pub fn lock(&mut self, layer: (u16, u16), user: Uuid) -> Result<(), Error> {
+        match self.locks.get(&layer) {
+            Some(owner) if *owner != user => Err(Error::LayerLocked),
+            _ => {
+                self.locks.insert(layer, user);
+                Ok(())
+            }
+        }
+    }
+
+    pub fn unlock(&mut self, layer: (u16, u16), user: Uuid) -> Result<(), Error> {
+        match self.locks.get(&layer) {
+            Some(owner) if *owner != user => Err(Error::LayerLocked),
+            _ => {
+                self.locks.remove(&layer);
+                Ok(())
+            }
+        }
+    }
+
+    /// Releases all locks held by a user. Returns whether the user held any lock.
+    pub fn release(&mut self, user: Uuid) -> bool {
+        let len = self.locks.len();
+        self.locks.retain(|_, owner| *owner != user);
+        self.locks.len() != len
+    }
+
+    pub fn clear(&mut self) {
+        self.locks.clear();
+    }
+
+    pub fn list(&self) -> Vec<LayerLock> {
+        let mut locks: Vec<_> = self
+            .locks
+            .iter()
+            .map(|((g, l), owner)| LayerLock {
+                group: *g,
+                layer: *l,
+                user: owner.to_string(),
+            })
+            .collect();
+        locks.sort_by_key(|lock| (lock.group, lock.layer));
+        locks
+    }
+
+    /// Fails if the request edits a layer locked by another user.
+    pub fn check(&self, req: &Request, user: Uuid) -> Result<(), Error> {
+        let locked =
+            |(g, l): (u16, u16)| self.locks.get(&(g, l)).is_some_and(|owner| *owner != user);
+
+        let is_locked = match req {
+            Request::Batch(reqs) => {
+                // the locked layers move with the requests applied earlier in the batch.
+                let mut locks = self.clone();
+                return reqs.iter().try_for_each(|req| {
+                    locks.check(req, user)?;
+                    locks.rebase(req);
+                    Ok(())
+                });
+            }
+            Request::Delete(DeleteReq::Group(g)) => self
+                .locks
+                .iter()
+                .any(|((lg, _), owner)| lg == g && *owner != user),
+            req => edited_layer(req).is_some_and(locked),
+        };
+
+        if is_locked {
+            Err(Error::LayerLocked)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Shifts the locked layers after a request was applied. Locks of deleted
+    /// layers are dropped.
+    pub fn rebase(&mut self, applied: &Request) {
+        if let Request::Batch(reqs) = applied {
+            reqs.iter().for_each(|req| self.rebase(req));
+            return;
+        }
+
+        self.locks = self
+            .locks
+            .drain()
+            .filter_map(|(layer, owner)| Some((map_layer(applied, layer).ok()?, owner)))
+            .collect();
+    }
+}
+
+fn edited_layer(req: &Request) -> Option<(u16, u16)> {
+    match req {
+        Request::Create(CreateReq::Quad(g, l, _))
+        | Request::Create(CreateReq::Source(g, l, _))
+        | Request::Edit(EditReq::Layer(g, l, _))
+        | Request::Edit(EditReq::Tiles(g, l, _))
+        | Request::Edit(EditReq::Quad(g, l, _, _))
+        | Request::Edit(EditReq::Source(g, l, _, _))
+        | Request::Edit(EditReq::Automap(g, l))
+        | Request::Delete(DeleteReq::Layer(g, l))
+        | Request::Delete(DeleteReq::Quad(g, l, _))
+        | Request::Delete(DeleteReq::Source(g, l, _))
+        | Request::Move(MoveReq::Layer((g, l), _))
+        | Request::Move(MoveReq::Quad((g, l, _), _))
+        | Request::Move(MoveReq::Source((g, l, _), _)) => Some((*g, *l)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{TestServer, MAP};
+
+    fn automap(g: u16, l: u16) -> Request {
+        Request::Edit(EditReq::Automap(g, l))
+    }
+
+    fn locked_layers(locks: &LayerLocks) -> Vec<(u16, u16)> {
+        locks.list().iter().map(|l| (l.group, l.layer)).collect()
+    }
+
+    #[track_caller]
+    fn assert_locked(res: Result<(), Error>) {
+        assert!(matches!(res, Err(Error::LayerLocked)), "{res:?}");
+    }
+
+    #[test]
+    fn lock_rejects_other_users() {
+        let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut locks = LayerLocks::default();
+        locks.lock((1, 2), alice).unwrap();
+        assert_locked(locks.lock((1, 2), bob));
+        assert_locked(locks.unlock((1, 2), bob));
+
+        let reqs = [
+            automap(1, 2),
+            Request::Edit(EditReq::Layer(
+                1,
+                2,
+                Box::new(PartialLayer::Game(Default::default())),
+            )),
+            Request::Delete(DeleteReq::Layer(1, 2)),
+            Request::Delete(DeleteReq::Quad(1, 2, 0)),
+            Request::Move(MoveReq::Layer((1, 2), (1, 0))),
+            Request::Batch(vec![automap(0, 0), automap(1, 2)]),
+        ];
+        for req in reqs {
+            assert_locked(locks.check(&req, bob));
+            locks.check(&req, alice).unwrap();
+        }
+
+        // other layers are not locked.
+        locks.check(&automap(1, 1), bob).unwrap();
+        locks
+            .check(&Request::Delete(DeleteReq::Layer(1, 1)), bob)
+            .unwrap();
+        locks
+            .check(&Request::Batch(vec![automap(0, 0), automap(1, 1)]), bob)
+            .unwrap();
+
+        locks.unlock((1, 2), alice).unwrap();
+        locks.check(&automap(1, 2), bob).unwrap();
+    }
+
+    #[test]
+    fn delete_group_with_locked_layer() {
+        let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut locks = LayerLocks::default();
+        locks.lock((1, 2), alice).unwrap();
+
+        assert_locked(locks.check(&Request::Delete(DeleteReq::Group(1)), bob));
+        locks
+            .check(&Request::Delete(DeleteReq::Group(1)), alice)
+            .unwrap();
+        locks
+            .check(&Request::Delete(DeleteReq::Group(2)), bob)
+            .unwrap();
+    }
+
+    #[test]
+    fn batch_moves_locked_layer() {
+        let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut locks = LayerLocks::default();
+        locks.lock((1, 2), alice).unwrap();
+
+        let batch = Request::Batch(vec![Request::Move(MoveReq::Group(1, 0)), automap(0, 2)]);
+        assert_locked(locks.check(&batch, bob));
+        locks.check(&batch, alice).unwrap();
+
+        let batch = Request::Batch(vec![Request::Delete(DeleteReq::Layer(1, 0)), automap(1, 1)]);
+        assert_locked(locks.check(&batch, bob));
+
+        // the layer that took the old index of the locked layer is free.
+        let batch = Request::Batch(vec![Request::Move(MoveReq::Group(1, 0)), automap(1, 2)]);
+        locks.check(&batch, bob).unwrap();
+        assert_eq!(locked_layers(&locks), [(1, 2)]);
+    }
+
+    #[test]
+    fn locks_follow_layers() {
+        let alice = Uuid::new_v4();
+        let mut locks = LayerLocks::default();
+        locks.lock((1, 2), alice).unwrap();
+        locks.lock((2, 0), alice).unwrap();
+
+        locks.rebase(&Request::Delete(DeleteReq::Layer(1, 0)));
+        assert_eq!(locked_layers(&locks), [(1, 1), (2, 0)]);
+
+        locks.rebase(&Request::Move(MoveReq::Layer((1, 1), (2, 0))));
+        assert_eq!(locked_layers(&locks), [(2, 0), (2, 1)]);
+
+        locks.rebase(&Request::Move(MoveReq::Group(2, 0)));
+        assert_eq!(locked_layers(&locks), [(0, 0), (0, 1)]);
+
+        locks.rebase(&Request::Batch(vec![
+            Request::Create(CreateReq::Group(Box::default())),
+            Request::Delete(DeleteReq::Layer(0, 0)),
+        ]));
+        assert_eq!(locked_layers(&locks), [(0, 0)]);
+
+        // the locks of deleted layers are dropped.
+        locks.rebase(&Request::Delete(DeleteReq::Group(0)));
+        assert!(locks.list().is_empty());
+    }
+
+    #[test]
+    fn locks_released_on_leave() {
+        let server = TestServer::new("locks-released-on-leave");
+        let alice = server.join(None).unwrap();
+        let bob = server.join(None).unwrap();
+
+        server.request(&alice, Request::LockLayer(0, 0)).unwrap();
+        let res = server.request(&bob, automap(0, 0));
+        assert!(matches!(res, Err(Error::LayerLocked)), "{res:?}");
+        assert_eq!(locked_layers(&server.room().read().locks), [(0, 0)]);
+
+        server
+            .request(&alice, Request::LeaveMap(MAP.to_owned()))
+            .unwrap();
+        assert!(server.room().read().locks.list().is_empty());
+        server.request(&bob, Request::LockLayer(0, 0)).unwrap();
+    }
+}
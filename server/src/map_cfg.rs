@@ -2,6 +2,12 @@ use std::{fs::File, path::Path};
 
 use serde::{Deserialize, Serialize};
 
+use crate::{
+    error::Error,
+    protocol::{Invite, Role},
+    util::{timestamp_now, write_atomic},
+};
+
 // I updated the content of MapConfig, so this is to convert from the old format
 #[derive(Deserialize)]
 struct MapConfigOld {
@@ -13,16 +19,110 @@ struct MapConfigOld {
 pub struct MapConfig {
     pub name: String,
     pub public: bool,
-    pub password: Option<String>,
+    pub password: Option<String>, // bcrypt hash of the editor password
+    #[serde(default)]
+    pub viewer_password: Option<String>,
+    #[serde(default)]
+    pub owner_password: Option<String>,
+    #[serde(default)]
+    pub invites: Vec<MapInvite>,
     pub version: twmap::Version,
 }
 
+/// Invite tokens are `<id>.<secret>`, only a bcrypt hash of the secret is stored.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MapInvite {
+    pub hash: String,
+    #[serde(flatten)]
+    pub invite: Invite,
+}
+
+impl MapInvite {
+    pub fn is_expired(&self) -> bool {
+        self.invite.expires.is_some_and(|t| t <= timestamp_now())
+    }
+
+    pub fn is_valid(&self) -> bool {
+        let invite = &self.invite;
+        let used_up = invite.max_uses.is_some_and(|max| invite.uses >= max);
+        !self.is_expired() && !used_up
+    }
+}
+
+impl MapConfig {
+    /// Role of the users that join without a password. A map without any password
+    /// is not protected, everyone owns it. A map with a viewer or editor password is
+    /// closed to users without a password, otherwise knowing the password would grant
+    /// less than not knowing it.
+    pub fn open_role(&self) -> Option<Role> {
+        if self.password.is_some() || self.viewer_password.is_some() {
+            None
+        } else if self.owner_password.is_some() {
+            Some(Role::Editor)
+        } else {
+            Some(Role::Owner)
+        }
+    }
+
+    /// Finds the role granted by a password. This is costly (bcrypt), so it should
+    /// not be called while holding the room lock.
+    pub fn role(&self, password: Option<&str>) -> Result<Role, Error> {
+        let password = password.filter(|pwd| !pwd.is_empty());
+        let Some(password) = password else {
+            return self.open_role().ok_or(Error::Password);
+        };
+
+        let verify = |hash: &Option<String>| {
+            hash.as_ref()
+                .is_some_and(|hash| bcrypt::verify(password, hash).unwrap_or(false))
+        };
+
+        if verify(&self.owner_password) {
+            Ok(Role::Owner)
+        } else if verify(&self.password) {
+            // without an owner password, only the server admin password grants owner.
+            Ok(Role::Editor)
+        } else if verify(&self.viewer_password) {
+            Ok(Role::Viewer)
+        } else {
+            Err(Error::Password)
+        }
+    }
+
+    /// Finds the invite of a token and checks that it can still be used. This is
+    /// costly (bcrypt), so it should not be called while holding the room lock.
+    pub fn invite(&self, token: &str) -> Result<&Invite, Error> {
+        let (id, secret) = token.split_once('.').ok_or(Error::InvalidInvite)?;
+        self.invites
+            .iter()
+            .find(|inv| inv.invite.id == id)
+            .filter(|inv| inv.is_valid())
+            .filter(|inv| bcrypt::verify(secret, &inv.hash).unwrap_or(false))
+            .map(|inv| &inv.invite)
+            .ok_or(Error::InvalidInvite)
+    }
+}
+
+/// Hashes a password to be stored in the map config. An empty password removes it.
+pub fn hash_password(password: &str) -> Result<Option<String>, Error> {
+    if password.is_empty() {
+        Ok(None)
+    } else {
+        bcrypt::hash(password, bcrypt::DEFAULT_COST)
+            .map(Some)
+            .map_err(|_| Error::Password)
+    }
+}
+
 impl Default for MapConfig {
     fn default() -> Self {
         Self {
             name: Default::default(),
             public: true,
             password: None,
+            viewer_password: None,
+            owner_password: None,
+            invites: Vec::new(),
             version: twmap::Version::DDNet06,
         }
     }
@@ -41,16 +141,116 @@ pub fn read_map_config(path: &Path) -> Option<MapConfig> {
                         name: old.name,
                         public: old.access == "public",
                         password: None,
+                        viewer_password: None,
+                        owner_password: None,
+                        invites: Vec::new(),
                         version: twmap::Version::DDNet06,
                     };
                     log::info!(
                         "converting config.json file to new format: {}",
                         path.display()
                     );
-                    File::create(path)
+                    serde_json::to_vec(&new)
                         .ok()
-                        .and_then(|f| serde_json::to_writer(f, &new).ok());
+                        .and_then(|buf| write_atomic(path, &buf).ok());
                     new
                 })
         })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(viewer: Option<&str>, editor: Option<&str>, owner: Option<&str>) -> MapConfig {
+        // the lowest cost keeps the tests fast.
+        let hash = |pwd: Option<&str>| pwd.map(|pwd| bcrypt::hash(pwd, 4).unwrap());
+        MapConfig {
+            viewer_password: hash(viewer),
+            password: hash(editor),
+            owner_password: hash(owner),
+            ..Default::default()
+        }
+    }
+
+    fn role(config: &MapConfig, password: Option<&str>) -> Option<Role> {
+        match config.role(password) {
+            Ok(role) => Some(role),
+            Err(Error::Password) => None,
+            Err(e) => panic!("unexpected error: {e}"),
+        }
+    }
+
+    #[test]
+    fn no_password() {
+        let cfg = config(None, None, None);
+        assert_eq!(cfg.open_role(), Some(Role::Owner));
+        assert_eq!(role(&cfg, None), Some(Role::Owner));
+        assert_eq!(role(&cfg, Some("")), Some(Role::Owner));
+        assert_eq!(role(&cfg, Some("wrong")), None);
+    }
+
+    #[test]
+    fn viewer_password() {
+        let cfg = config(Some("view"), None, None);
+        assert_eq!(cfg.open_role(), None);
+        assert_eq!(role(&cfg, None), None);
+        assert_eq!(role(&cfg, Some("view")), Some(Role::Viewer));
+        assert_eq!(role(&cfg, Some("wrong")), None);
+    }
+
+    #[test]
+    fn editor_password() {
+        let cfg = config(None, Some("edit"), None);
+        assert_eq!(cfg.open_role(), None);
+        assert_eq!(role(&cfg, None), None);
+        assert_eq!(role(&cfg, Some("edit")), Some(Role::Editor));
+    }
+
+    #[test]
+    fn owner_password() {
+        let cfg = config(None, None, Some("own"));
+        assert_eq!(cfg.open_role(), Some(Role::Editor));
+        assert_eq!(role(&cfg, None), Some(Role::Editor));
+        assert_eq!(role(&cfg, Some("own")), Some(Role::Owner));
+        assert_eq!(role(&cfg, Some("wrong")), None);
+    }
+
+    #[test]
+    fn viewer_and_editor_passwords() {
+        let cfg = config(Some("view"), Some("edit"), None);
+        assert_eq!(cfg.open_role(), None);
+        assert_eq!(role(&cfg, None), None);
+        assert_eq!(role(&cfg, Some("view")), Some(Role::Viewer));
+        assert_eq!(role(&cfg, Some("edit")), Some(Role::Editor));
+    }
+
+    #[test]
+    fn viewer_and_owner_passwords() {
+        let cfg = config(Some("view"), None, Some("own"));
+        assert_eq!(cfg.open_role(), None);
+        assert_eq!(role(&cfg, None), None);
+        assert_eq!(role(&cfg, Some("view")), Some(Role::Viewer));
+        assert_eq!(role(&cfg, Some("own")), Some(Role::Owner));
+    }
+
+    #[test]
+    fn editor_and_owner_passwords() {
+        let cfg = config(None, Some("edit"), Some("own"));
+        assert_eq!(cfg.open_role(), None);
+        assert_eq!(role(&cfg, None), None);
+        assert_eq!(role(&cfg, Some("edit")), Some(Role::Editor));
+        assert_eq!(role(&cfg, Some("own")), Some(Role::Owner));
+    }
+
+    #[test]
+    fn all_passwords() {
+        let cfg = config(Some("view"), Some("edit"), Some("own"));
+        assert_eq!(cfg.open_role(), None);
+        assert_eq!(role(&cfg, None), None);
+        assert_eq!(role(&cfg, Some("view")), Some(Role::Viewer));
+        assert_eq!(role(&cfg, Some("edit")), Some(Role::Editor));
+        assert_eq!(role(&cfg, Some("own")), Some(Role::Owner));
+        assert_eq!(role(&cfg, Some("wrong")), None);
+    }
+}
@@ -1,14 +1,18 @@
 use std::{collections::HashMap, fmt::Display, str::FromStr};
 
 use fixed::types::{I17F15, I22F10, I27F5};
+#[cfg(feature = "schema")]
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use serde_with::{
     rust::double_option, serde_as, skip_serializing_none, DeserializeAs, DisplayFromStr,
     SerializeAs,
 };
 use twmap::{AutomapperConfig, EnvPoint, Position, Volume};
-use vek::{Extent2, Rect, Rgba, Uv, Vec2};
+use vek::{Extent2, Rect, Rgb, Rgba, Uv, Vec2};
 
+#[cfg(feature = "schema")]
+use crate::schema;
 use crate::{base64::Base64, error::Error, util::timestamp_now};
 
 // Some documentation about the communication between clients and the server:
@@ -28,11 +32,15 @@ use crate::{base64::Base64, error::Error, util::timestamp_now};
 // communication with the server to see if it agrees with the transaction.
 //
 // For those requests, the client has to wait for the server which leads to poor ux.
-// This could be fixed in the future by implementing a history and versionning
-// system similar to how databases handle concurrent modifications.
+// To mitigate this, each room has a revision number, which the server sends along
+// with broadcasts and responses. Clients can set the `base_revision` of their
+// requests to the last revision they saw, the server will then rebase the
+// request on top of the edits they missed (see revision.rs).
 
 #[derive(Debug, Ord, PartialOrd, Eq, PartialEq, Copy, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
 #[serde(remote = "twmap::Version", rename_all = "lowercase")]
+#[cfg_attr(feature = "schema", schemars(rename = "Version"))]
 pub enum SerdeVersion {
     DDNet06,
     Teeworlds07,
@@ -56,27 +64,46 @@ impl<'de> DeserializeAs<'de, twmap::Version> for SerdeVersion {
     }
 }
 
+/// Access level of a user in a map. Each role has the permissions of the previous ones:
+/// viewers can read the map, editors can edit it and owners can configure or delete it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Viewer,
+    Editor,
+    Owner,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
 pub struct Config {
     pub name: String,
     pub public: bool,
-    pub password: bool,
+    pub password: bool, // the editor password
+    pub viewer_password: bool,
+    pub owner_password: bool,
     #[serde(with = "SerdeVersion")]
     pub version: twmap::Version,
 }
 
 #[serde_as]
 #[derive(Default, Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
 #[serde(default)]
 pub struct PartialConfig {
     pub name: Option<String>,
     pub public: Option<bool>,
     pub password: Option<String>,
+    pub viewer_password: Option<String>,
+    pub owner_password: Option<String>,
     #[serde_as(as = "Option<SerdeVersion>")]
+    #[cfg_attr(feature = "schema", schemars(with = "Option<SerdeVersion>"))]
     pub version: Option<twmap::Version>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
 pub struct MapDetail {
     pub name: String,
     pub users: usize,
@@ -85,6 +112,7 @@ pub struct MapDetail {
 // AUTOMAPPERS
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
 pub enum AutomapperKind {
     #[serde(rename = "rules")]
     DDNet,
@@ -95,6 +123,7 @@ pub enum AutomapperKind {
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
 pub struct AutomapperDetail {
     pub name: String,
     pub image: String,
@@ -103,6 +132,7 @@ pub struct AutomapperDetail {
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
 pub struct Span {
     pub line_start: u32,
     pub col_start: u32,
@@ -111,51 +141,83 @@ pub struct Span {
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
 pub struct AutomapperDiagnostic {
     pub span: Span,
     pub msg: String,
 }
 
-// TILES
+// MAP CHECKS
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct Tile {
-    pub id: u8,
-    pub flags: u8,
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum MapItemKind {
+    Info,
+    Image,
+    Envelope,
+    EnvPoint,
+    Group,
+    Layer,
+    Quad,
+    Sound,
+    SoundSource,
 }
 
+/// One step of the path to a map item, e.g. `[group 1, layer 3, quad 0]`.
 #[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct Tele {
-    pub number: u8,
-    pub id: u8,
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+pub struct MapItemIndex {
+    pub item: MapItemKind,
+    pub index: Option<usize>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
-#[serde(rename_all = "snake_case")]
-pub struct Speedup {
-    pub force: u8,
-    pub max_speed: u8,
-    pub id: u8,
-    pub angle: i16,
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+pub struct MapDiagnostic {
+    pub severity: Severity,
+    pub path: Vec<MapItemIndex>,
+    pub msg: String,
 }
 
+/// Gameplay problem found at a tile of a physics layer.
 #[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct Switch {
-    pub number: u8,
-    pub id: u8,
-    pub flags: u8,
-    pub delay: u8,
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+pub struct MapLint {
+    pub group: u16,
+    pub layer: u16,
+    pub x: u32,
+    pub y: u32,
+    pub msg: String,
 }
 
+/// Tiles of the physics layers reachable from the spawns.
 #[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct Tune {
-    pub number: u8,
-    pub id: u8,
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+pub struct Reachability {
+    pub width: u32,  // in tiles
+    pub height: u32, // in tiles
+    /// Side of the square chunks of tiles in the bitmap.
+    pub chunk_size: u32,
+    /// One bit per chunk, row by row, least significant bit first. A chunk is set
+    /// if any of its tiles is reachable.
+    pub reachable: Base64,
+    pub lints: Vec<MapLint>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
 pub struct Cursor {
     #[serde(flatten)]
+    #[cfg_attr(feature = "schema", schemars(with = "schema::Vec2<f32>"))]
     pub point: Vec2<f32>,
     #[serde(rename = "g")]
     pub group: i32,
@@ -163,7 +225,92 @@ pub struct Cursor {
     pub layer: i32,
 }
 
+/// A user in a room, identified by their stable user id, with their last known
+/// cursor position and selected group and layer.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+pub struct Presence {
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "schema", schemars(with = "Option<schema::Rgb<u8>>"))]
+    pub color: Option<Rgb<u8>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role: Option<Role>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<Cursor>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+pub struct ChatMessage {
+    pub id: String, // id of the sender
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub text: String,
+}
+
+impl ChatMessage {
+    pub const MAX_TEXT_LENGTH: usize = 500;
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+pub struct CommentReq {
+    #[serde(rename = "g")]
+    pub group: u16,
+    #[serde(rename = "l")]
+    pub layer: u16,
+    pub x: f32,
+    pub y: f32,
+    pub text: String,
+}
+
+/// A comment pinned to a position in a layer, e.g. review feedback. Comments are
+/// stored next to the map and are not part of the map file.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+pub struct Comment {
+    pub id: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    pub timestamp: u64,
+    pub resolved: bool,
+    #[serde(flatten)]
+    pub content: CommentReq,
+}
+
+impl Comment {
+    pub const MAX_TEXT_LENGTH: usize = 2000;
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+pub struct LayerLock {
+    #[serde(rename = "g")]
+    pub group: u16,
+    #[serde(rename = "l")]
+    pub layer: u16,
+    pub user: String, // id of the user holding the lock
+}
+
+/// Display name and colour chosen by a user, shown to the other users of the room.
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+pub struct UserProfile {
+    pub name: String,
+    #[serde(default)]
+    #[cfg_attr(feature = "schema", schemars(with = "Option<schema::Rgb<u8>>"))]
+    pub color: Option<Rgb<u8>>,
+}
+
+impl UserProfile {
+    pub const MAX_NAME_LENGTH: usize = 32;
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
 #[serde(rename_all = "snake_case")]
 pub enum CreationMethod {
     Upload(Base64),
@@ -173,8 +320,10 @@ pub enum CreationMethod {
 
 #[serde_as]
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
 pub struct MapCreation {
     #[serde_as(as = "Option<SerdeVersion>")]
+    #[cfg_attr(feature = "schema", schemars(with = "Option<SerdeVersion>"))]
     pub version: Option<twmap::Version>,
     #[serde(default)]
     pub public: Option<bool>,
@@ -186,6 +335,7 @@ pub struct MapCreation {
 
 #[skip_serializing_none]
 #[derive(Default, Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
 #[serde(default)]
 pub struct PartialInfo {
     pub author: Option<String>,
@@ -197,34 +347,67 @@ pub struct PartialInfo {
 
 #[skip_serializing_none]
 #[derive(Default, Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
 #[serde(default)]
+#[cfg_attr(
+    feature = "schema",
+    schemars(bound = "T: JsonSchema + Default", rename = "PartialEnv_for_{T}")
+)]
 pub struct PartialEnv<T: Copy> {
     pub name: Option<String>,
     pub synchronized: Option<bool>,
+    #[cfg_attr(
+        feature = "schema",
+        schemars(with = "Option<Vec<schema::EnvPoint<T>>>")
+    )]
     pub points: Option<Vec<EnvPoint<T>>>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
 #[serde(rename_all = "snake_case", tag = "type")]
 pub enum PartialEnvelope {
-    Position(PartialEnv<Position>),
-    Color(PartialEnv<Rgba<I22F10>>),
-    Sound(PartialEnv<Volume>),
+    Position(
+        #[cfg_attr(feature = "schema", schemars(with = "PartialEnv<schema::Position>"))]
+        PartialEnv<Position>,
+    ),
+    Color(
+        #[cfg_attr(
+            feature = "schema",
+            schemars(with = "PartialEnv<schema::Rgba<schema::Fixed>>")
+        )]
+        PartialEnv<Rgba<I22F10>>,
+    ),
+    Sound(
+        #[cfg_attr(feature = "schema", schemars(with = "PartialEnv<schema::Volume>"))]
+        PartialEnv<Volume>,
+    ),
 }
 
 #[skip_serializing_none]
 #[derive(Default, Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
 #[serde(default)]
 pub struct PartialGroup {
     pub name: Option<String>,
+    #[cfg_attr(
+        feature = "schema",
+        schemars(with = "Option<schema::Vec2<schema::Fixed>>")
+    )]
     pub offset: Option<Vec2<I27F5>>,
+    #[cfg_attr(feature = "schema", schemars(with = "Option<schema::Vec2<i32>>"))]
     pub parallax: Option<Vec2<i32>>,
     pub clipping: Option<bool>,
+    #[cfg_attr(
+        feature = "schema",
+        schemars(with = "Option<schema::Rect<schema::Fixed, schema::Fixed>>")
+    )]
     pub clip: Option<Rect<I27F5, I27F5>>,
 }
 
 #[skip_serializing_none]
 #[derive(Default, Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
 pub struct PartialPhysicsLayer {
     pub width: Option<usize>,
     pub height: Option<usize>,
@@ -232,40 +415,71 @@ pub struct PartialPhysicsLayer {
 
 #[skip_serializing_none]
 #[derive(Default, Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
 #[serde(default)]
 pub struct PartialTilesLayer {
     pub width: Option<usize>,
     pub height: Option<usize>,
     pub name: Option<String>,
     pub detail: Option<bool>,
+    #[cfg_attr(feature = "schema", schemars(with = "Option<schema::Rgba<u8>>"))]
     pub color: Option<Rgba<u8>>,
     #[serde(with = "double_option")]
+    #[cfg_attr(feature = "schema", schemars(with = "Option<Option<u16>>"))]
     pub color_env: Option<Option<u16>>,
     pub color_env_offset: Option<i32>,
     #[serde(with = "double_option")]
+    #[cfg_attr(feature = "schema", schemars(with = "Option<Option<u16>>"))]
     pub image: Option<Option<u16>>,
+    #[cfg_attr(
+        feature = "schema",
+        schemars(with = "Option<schema::AutomapperConfig>")
+    )]
     pub automapper_config: Option<AutomapperConfig>,
 }
 
 #[skip_serializing_none]
 #[derive(Default, Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
 #[serde(default)]
 pub struct PartialQuadsLayer {
     pub name: Option<String>,
     pub detail: Option<bool>,
     #[serde(with = "double_option")]
+    #[cfg_attr(feature = "schema", schemars(with = "Option<Option<u16>>"))]
     pub image: Option<Option<u16>>,
 }
 
+#[skip_serializing_none]
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(default)]
+pub struct PartialSoundsLayer {
+    pub name: Option<String>,
+    pub detail: Option<bool>,
+    #[serde(with = "double_option")]
+    #[cfg_attr(feature = "schema", schemars(with = "Option<Option<u16>>"))]
+    pub sound: Option<Option<u16>>,
+}
+
 // the sole purpose of this remote struct is to serialize color_env and
 // position_env as numbers instead of strings (like twmap does), because twmap
 // deserialization panics.
 #[derive(Default, Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
 #[serde(remote = "twmap::Quad")]
+#[cfg_attr(feature = "schema", schemars(rename = "Quad"))]
 pub struct SerdeQuad {
+    #[cfg_attr(
+        feature = "schema",
+        schemars(with = "[schema::Vec2<schema::Fixed>; 4]")
+    )]
     pub corners: [Vec2<I17F15>; 4],
+    #[cfg_attr(feature = "schema", schemars(with = "schema::Vec2<schema::Fixed>"))]
     pub position: Vec2<I17F15>,
+    #[cfg_attr(feature = "schema", schemars(with = "[schema::Rgba<u8>; 4]"))]
     pub colors: [Rgba<u8>; 4],
+    #[cfg_attr(feature = "schema", schemars(with = "[schema::Uv<schema::Fixed>; 4]"))]
     pub texture_coords: [Uv<I22F10>; 4],
     pub position_env: Option<u16>,
     pub position_env_offset: i32,
@@ -291,14 +505,72 @@ impl<'de> DeserializeAs<'de, twmap::Quad> for SerdeQuad {
     }
 }
 
+// same as SerdeQuad, for position_env and sound_env.
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(remote = "twmap::SoundSource")]
+#[cfg_attr(feature = "schema", schemars(rename = "SoundSource"))]
+pub struct SerdeSoundSource {
+    #[cfg_attr(feature = "schema", schemars(with = "schema::SoundArea"))]
+    pub area: twmap::SoundArea,
+    pub looping: bool,
+    pub panning: bool,
+    pub delay: i32,
+    pub falloff: u8,
+    pub position_env: Option<u16>,
+    pub position_env_offset: i32,
+    pub sound_env: Option<u16>,
+    pub sound_env_offset: i32,
+}
+
+impl SerializeAs<twmap::SoundSource> for SerdeSoundSource {
+    fn serialize_as<S>(value: &twmap::SoundSource, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        SerdeSoundSource::serialize(value, serializer)
+    }
+}
+
+impl<'de> DeserializeAs<'de, twmap::SoundSource> for SerdeSoundSource {
+    fn deserialize_as<D>(deserializer: D) -> Result<twmap::SoundSource, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        SerdeSoundSource::deserialize(deserializer)
+    }
+}
+
+/// Compression of tile data in get/tiles and edit/tiles. Tilemaps are mostly
+/// empty, so they compress very well.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum Compression {
+    #[default]
+    None,
+    Zlib,
+}
+
+impl Compression {
+    pub fn is_none(&self) -> bool {
+        *self == Compression::None
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
 pub struct Tiles {
     #[serde(flatten)]
+    #[cfg_attr(feature = "schema", schemars(with = "schema::Rect<u32, u32>"))]
     pub rect: vek::Rect<u32, u32>,
     pub tiles: Base64,
+    #[serde(default, skip_serializing_if = "Compression::is_none")]
+    pub compression: Compression,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
 #[serde(rename_all = "snake_case", tag = "type")]
 pub enum PartialLayer {
     Game(PartialPhysicsLayer),
@@ -309,34 +581,62 @@ pub enum PartialLayer {
     Speedup(PartialPhysicsLayer),
     Switch(PartialPhysicsLayer),
     Tune(PartialPhysicsLayer),
-    // Sounds(SoundsLayer), // TODO
+    Sounds(PartialSoundsLayer),
 }
 
-#[derive(Default, Clone, Debug, Serialize, Deserialize)]
-#[serde(rename_all = "snake_case")]
-pub struct PartialAutomapper {}
-
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
 #[serde(untagged)]
 pub enum Image {
-    External { size: Extent2<u32> },
+    External {
+        #[cfg_attr(feature = "schema", schemars(with = "schema::Extent2<u32>"))]
+        size: Extent2<u32>,
+    },
     Embedded(Base64),
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct HttpReq {
-    pub method: String,
-    pub path: String,
-    pub body: String,
-}
-
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
 pub struct JoinReq {
     pub name: String,
     pub password: Option<String>,
+    #[serde(default)]
+    pub user: Option<UserProfile>,
+    #[serde(default)]
+    pub invite: Option<String>, // invite token, replaces the password
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+pub struct InviteReq {
+    pub role: Role,
+    #[serde(default)]
+    pub expires_in: Option<u64>, // in seconds
+    #[serde(default)]
+    pub max_uses: Option<u32>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+pub struct Invite {
+    pub id: String,
+    pub role: Role,
+    pub expires: Option<u64>, // UNIX timestamp
+    pub max_uses: Option<u32>,
+    pub uses: u32,
+}
+
+/// A newly created invite. The token is only known by its creator.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+pub struct InviteToken {
+    pub token: String,
+    #[serde(flatten)]
+    pub invite: Invite,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
 #[serde(tag = "type", content = "content")]
 pub enum GetReq {
     #[serde(rename = "get/map")]
@@ -349,6 +649,8 @@ pub enum GetReq {
     Config,
     #[serde(rename = "get/info")]
     Info,
+    #[serde(rename = "get/backups")]
+    Backups,
     #[serde(rename = "get/images")]
     Images,
     #[serde(rename = "get/image")]
@@ -365,10 +667,36 @@ pub enum GetReq {
     Layers(u16),
     #[serde(rename = "get/layer")]
     Layer(u16, u16),
+    #[serde(rename = "get/check")]
+    Check,
+    #[serde(rename = "get/lints")]
+    Lints,
+    #[serde(rename = "get/reachability")]
+    Reachability,
     #[serde(rename = "get/tiles")]
-    Tiles(u16, u16),
+    Tiles(u16, u16, #[serde(default)] Compression),
+    #[serde(rename = "get/tiles_region")]
+    TilesRegion(
+        u16,
+        u16,
+        #[cfg_attr(feature = "schema", schemars(with = "schema::Rect<u32, u32>"))]
+        vek::Rect<u32, u32>,
+        #[serde(default)] Compression,
+    ),
     #[serde(rename = "get/quad")]
     Quad(u16, u16, u16),
+    #[serde(rename = "get/sounds")]
+    Sounds,
+    #[serde(rename = "get/sound")]
+    Sound(u16),
+    #[serde(rename = "get/source")]
+    Source(u16, u16, u16),
+    #[serde(rename = "get/comments")]
+    Comments,
+    #[serde(rename = "get/locks")]
+    Locks,
+    #[serde(rename = "get/invites")]
+    Invites,
     #[serde(rename = "get/automappers")]
     Automappers,
     #[serde(rename = "get/automapper")]
@@ -377,6 +705,7 @@ pub enum GetReq {
 
 #[serde_as]
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
 #[serde(tag = "type", content = "content")]
 pub enum CreateReq {
     #[serde(rename = "create/image")]
@@ -391,7 +720,19 @@ pub enum CreateReq {
     Quad(
         u16,
         u16,
-        #[serde_as(as = "Box<SerdeQuad>")] Box<twmap::Quad>,
+        #[serde_as(as = "Box<SerdeQuad>")]
+        #[cfg_attr(feature = "schema", schemars(with = "SerdeQuad"))]
+        Box<twmap::Quad>,
+    ),
+    #[serde(rename = "create/sound")]
+    Sound(String, Base64),
+    #[serde(rename = "create/source")]
+    Source(
+        u16,
+        u16,
+        #[serde_as(as = "Box<SerdeSoundSource>")]
+        #[cfg_attr(feature = "schema", schemars(with = "SerdeSoundSource"))]
+        Box<twmap::SoundSource>,
     ),
     #[serde(rename = "create/automapper")]
     Automapper(String, String),
@@ -399,6 +740,7 @@ pub enum CreateReq {
 
 #[serde_as]
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
 #[serde(tag = "type", content = "content")]
 pub enum EditReq {
     #[serde(rename = "edit/config")]
@@ -418,13 +760,25 @@ pub enum EditReq {
         u16,
         u16,
         u16,
-        #[serde_as(as = "Box<SerdeQuad>")] Box<twmap::Quad>,
+        #[serde_as(as = "Box<SerdeQuad>")]
+        #[cfg_attr(feature = "schema", schemars(with = "SerdeQuad"))]
+        Box<twmap::Quad>,
+    ),
+    #[serde(rename = "edit/source")]
+    Source(
+        u16,
+        u16,
+        u16,
+        #[serde_as(as = "Box<SerdeSoundSource>")]
+        #[cfg_attr(feature = "schema", schemars(with = "SerdeSoundSource"))]
+        Box<twmap::SoundSource>,
     ),
     #[serde(rename = "edit/automap")]
     Automap(u16, u16),
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
 #[serde(tag = "type", content = "content")]
 pub enum DeleteReq {
     #[serde(rename = "delete/image")]
@@ -437,11 +791,16 @@ pub enum DeleteReq {
     Layer(u16, u16),
     #[serde(rename = "delete/quad")]
     Quad(u16, u16, u16),
+    #[serde(rename = "delete/sound")]
+    Sound(u16),
+    #[serde(rename = "delete/source")]
+    Source(u16, u16, u16),
     #[serde(rename = "delete/automapper")]
     Automapper(String),
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
 #[serde(tag = "type", content = "content")]
 pub enum MoveReq {
     #[serde(rename = "move/image")]
@@ -454,9 +813,14 @@ pub enum MoveReq {
     Layer((u16, u16), (u16, u16)),
     #[serde(rename = "move/quad")]
     Quad((u16, u16, u16), u16),
+    #[serde(rename = "move/sound")]
+    Sound(u16, u16),
+    #[serde(rename = "move/source")]
+    Source((u16, u16, u16), u16),
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
 #[serde(tag = "type", content = "content")]
 pub enum Request {
     #[serde(rename = "list")]
@@ -465,6 +829,8 @@ pub enum Request {
     Config(String),
     #[serde(rename = "join")]
     JoinMap(JoinReq),
+    #[serde(rename = "set/name")]
+    SetName(UserProfile),
     #[serde(rename = "leave")]
     LeaveMap(String),
     #[serde(rename = "get")]
@@ -477,6 +843,34 @@ pub enum Request {
     Save,
     #[serde(rename = "cursor")]
     Cursor(Box<Cursor>),
+    #[serde(rename = "undo")]
+    Undo,
+    #[serde(rename = "redo")]
+    Redo,
+    #[serde(rename = "batch")]
+    Batch(Vec<Request>),
+    #[serde(rename = "restore/recovery")]
+    RestoreRecovery,
+    #[serde(rename = "delete/recovery")]
+    DeleteRecovery,
+    #[serde(rename = "restore/backup")]
+    RestoreBackup(String),
+    #[serde(rename = "chat")]
+    Chat(String),
+    #[serde(rename = "create/comment")]
+    CreateComment(Box<CommentReq>),
+    #[serde(rename = "resolve/comment")]
+    ResolveComment(u32),
+    #[serde(rename = "delete/comment")]
+    DeleteComment(u32),
+    #[serde(rename = "lock/layer")]
+    LockLayer(u16, u16),
+    #[serde(rename = "unlock/layer")]
+    UnlockLayer(u16, u16),
+    #[serde(rename = "create/invite")]
+    CreateInvite(InviteReq),
+    #[serde(rename = "revoke/invite")]
+    RevokeInvite(String),
     #[serde(untagged)]
     Get(GetReq),
     #[serde(untagged)]
@@ -491,67 +885,135 @@ pub enum Request {
 
 #[serde_as]
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
 #[serde(untagged)]
 pub enum Response {
     Ok,
-    Token(String),
+    // a plain String would also accept the raw bytes of binary encodings.
+    Token(
+        #[serde_as(as = "DisplayFromStr")]
+        #[cfg_attr(feature = "schema", schemars(with = "String"))]
+        String,
+    ),
     Maps(Vec<MapDetail>),
     Map(Base64),
-    Users(usize),
+    Users(Vec<Presence>),
     Cursors(HashMap<String, Cursor>),
     Config(Box<Config>),
-    Info(Box<twmap::Info>),
+    Info(#[cfg_attr(feature = "schema", schemars(with = "schema::Info"))] Box<twmap::Info>),
     Images(Vec<String>),
     Image(Base64),
     Envelopes(Vec<String>),
-    Envelope(Box<twmap::Envelope>),
+    Envelope(
+        #[cfg_attr(feature = "schema", schemars(with = "schema::Envelope"))] Box<twmap::Envelope>,
+    ),
     Groups(Vec<String>),
-    Group(Box<twmap::Group>),
+    Group(#[cfg_attr(feature = "schema", schemars(with = "schema::Group"))] Box<twmap::Group>),
     Layers(Vec<String>),
-    Layer(Box<twmap::Layer>),
+    Layer(#[cfg_attr(feature = "schema", schemars(with = "schema::Layer"))] Box<twmap::Layer>),
     Tiles(Base64),
-    Quad(#[serde_as(as = "Box<SerdeQuad>")] Box<twmap::Quad>),
+    Quad(
+        #[serde_as(as = "Box<SerdeQuad>")]
+        #[cfg_attr(feature = "schema", schemars(with = "SerdeQuad"))]
+        Box<twmap::Quad>,
+    ),
+    Sounds(Vec<String>),
+    Sound(Base64),
+    Source(
+        #[serde_as(as = "Box<SerdeSoundSource>")]
+        #[cfg_attr(feature = "schema", schemars(with = "SerdeSoundSource"))]
+        Box<twmap::SoundSource>,
+    ),
     Automappers(Vec<AutomapperDetail>),
     AutomapperDiagnostics(Vec<AutomapperDiagnostic>),
+    MapDiagnostics(Vec<MapDiagnostic>),
+    Lints(Vec<MapLint>),
+    Reachability(Box<Reachability>),
     Automapper(String),
+    Backups(Vec<String>),
+    Comments(Vec<Comment>),
+    Comment(Box<Comment>),
+    Locks(Vec<LayerLock>),
+    User(Box<Presence>), // sent on join
+    Invites(Vec<Invite>),
+    Invite(Box<InviteToken>),
 }
 
 // Messages that are sent unrequested from the client.
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
 #[serde(tag = "type", content = "content", rename_all = "snake_case")]
 pub enum Broadcast {
     MapCreated(String),
     MapDeleted(String),
+    MapUsers(MapDetail),            // sent to the lobby, for public maps
+    MapConfig(String, Box<Config>), // sent to the lobby
     Users(usize),
     Saved,
+    RecoveryAvailable, // sent on join, when unsaved edits were recovered
+    MapReloaded,       // the map was replaced, clients must fetch it again
+    UserJoined(Presence),
+    UserLeft(Presence),
+    UserUpdated(Presence), // the user changed their name or colour
+    Chat(ChatMessage),
+    Comments(Vec<Comment>), // sent when a comment is created, resolved or deleted
+    Locks(Vec<LayerLock>),  // sent when a layer is locked or unlocked
+    Cursor(Presence),       // throttled, see Cli::cursor_interval
+}
+
+/// Results are flattened in packets, next to the packet fields.
+#[cfg(feature = "schema")]
+fn flattened(schema: &mut schemars::Schema) {
+    if let Some(variants) = schema.get_mut("oneOf").and_then(|v| v.as_array_mut()) {
+        for variant in variants.iter_mut().filter_map(|v| v.as_object_mut()) {
+            variant.remove("additionalProperties");
+        }
+    }
 }
 
 #[serde_as]
 #[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
 #[serde(remote = "Result", rename_all = "lowercase")]
+#[cfg_attr(feature = "schema", schemars(rename = "Result_for_{T}", transform = flattened))]
 enum SerdeResult<T, E>
 where
     E: Display + FromStr,
     E::Err: Display,
 {
     Ok(T),
-    Err(#[serde_as(as = "DisplayFromStr")] E),
+    Err(
+        #[serde_as(as = "DisplayFromStr")]
+        #[cfg_attr(feature = "schema", schemars(with = "String"))]
+        E,
+    ),
 }
 
 #[serde_as]
 #[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
 #[serde(untagged)]
 pub enum Message {
     Request(Request),
-    Response(#[serde(with = "SerdeResult")] Result<Response, Error>),
+    Response(
+        #[serde(with = "SerdeResult")]
+        #[cfg_attr(feature = "schema", schemars(with = "SerdeResult<Response, String>"))]
+        Result<Response, Error>,
+    ),
     Broadcast(Broadcast),
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[cfg_attr(feature = "schema", schemars(rename = "Packet_for_{T}"))]
 pub struct Packet<T> {
     pub timestamp: u64, // UNIX timestamp set by sender
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub id: Option<u32>, // same ID will be set by client request
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revision: Option<u64>, // room revision, set by the server
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_revision: Option<u64>, // last room revision seen by the client
     #[serde(flatten)]
     pub content: T,
 }
@@ -561,9 +1023,37 @@ impl<T> Packet<T> {
         Self {
             timestamp: timestamp_now(),
             id,
+            revision: None,
+            base_revision: None,
             content,
         }
     }
+
+    pub fn with_revision(mut self, revision: u64) -> Self {
+        self.revision = Some(revision);
+        self
+    }
+}
+
+/// How packets are serialized on a websocket. Clients can ask for MessagePack
+/// with the `Sec-WebSocket-Protocol` header, which avoids the base64 and JSON
+/// overhead of large tile and map payloads. The default is JSON.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Encoding {
+    #[default]
+    Json,
+    MessagePack,
+}
+
+impl Encoding {
+    pub const MSGPACK_PROTOCOL: &'static str = "twwe.msgpack";
+
+    pub fn from_protocol(protocol: Option<&str>) -> Self {
+        match protocol {
+            Some(Self::MSGPACK_PROTOCOL) => Encoding::MessagePack,
+            _ => Encoding::Json,
+        }
+    }
 }
 
 pub type SendPacket = Packet<Message>;
@@ -0,0 +1,503 @@
+use std::collections::VecDeque;
+
+use crate::{error::Error, protocol::*, util::move_index};
+
+// Each room has a revision number, incremented every time an edit is applied.
+// Clients stamp their requests with the revision they last saw (`base_revision`).
+// If edits were applied in-between, the request is rebased on top of them: indices
+// of images, sounds, envelopes, groups, layers, quads and sound sources are shifted
+// to follow concurrent moves and deletions. If the object targeted by the request
+// was deleted, the request is rejected with `Error::Conflict`.
+
+const MAX_LOG_LEN: usize = 1000;
+
+#[derive(Debug, Default)]
+pub struct Revisions {
+    revision: u64,
+    log: VecDeque<(u64, Request)>,
+}
+
+impl Revisions {
+    pub fn current(&self) -> u64 {
+        self.revision
+    }
+
+    /// Records an applied edit and returns the new revision.
+    pub fn push(&mut self, req: Request) -> u64 {
+        self.revision += 1;
+        self.log.push_back((self.revision, req));
+        if self.log.len() > MAX_LOG_LEN {
+            self.log.pop_front();
+        }
+        self.revision
+    }
+
+    /// Forgets the logged edits, but not the revision number.
+    pub fn clear(&mut self) {
+        self.log.clear();
+    }
+
+    /// Invalidates all previous revisions, when the whole map was replaced.
+    pub fn reset(&mut self) {
+        self.revision += 1;
+        self.log.clear();
+    }
+
+    /// Edits applied after revision `base`, if they are still in the log.
+    pub fn since(&self, base: u64) -> Option<impl Iterator<Item = &(u64, Request)>> {
+        if base > self.revision {
+            return None;
+        }
+        let oldest = self
+            .log
+            .front()
+            .map(|(rev, _)| *rev)
+            .unwrap_or(self.revision + 1);
+        if base + 1 < oldest {
+            return None;
+        }
+        Some(self.log.iter().filter(move |(rev, _)| *rev > base))
+    }
+
+    pub fn rebase(&self, base: u64, mut req: Request) -> Result<Request, Error> {
+        for (_, applied) in self.since(base).ok_or(Error::Conflict)? {
+            req = rebase(applied, req)?;
+        }
+        Ok(req)
+    }
+}
+
+fn shift_deleted(i: u16, deleted: u16) -> Result<u16, Error> {
+    if i == deleted {
+        Err(Error::Conflict)
+    } else if i > deleted {
+        Ok(i - 1)
+    } else {
+        Ok(i)
+    }
+}
+
+fn map_image(applied: &Request, i: u16) -> Result<u16, Error> {
+    match applied {
+        Request::Delete(DeleteReq::Image(deleted)) => shift_deleted(i, *deleted),
+        Request::Move(MoveReq::Image(src, tgt)) => Ok(move_index(i, *src, *tgt)),
+        _ => Ok(i),
+    }
+}
+
+fn map_envelope(applied: &Request, e: u16) -> Result<u16, Error> {
+    match applied {
+        Request::Delete(DeleteReq::Envelope(deleted)) => shift_deleted(e, *deleted),
+        Request::Move(MoveReq::Envelope(src, tgt)) => Ok(move_index(e, *src, *tgt)),
+        _ => Ok(e),
+    }
+}
+
+fn map_sound(applied: &Request, s: u16) -> Result<u16, Error> {
+    match applied {
+        Request::Delete(DeleteReq::Sound(deleted)) => shift_deleted(s, *deleted),
+        Request::Move(MoveReq::Sound(src, tgt)) => Ok(move_index(s, *src, *tgt)),
+        _ => Ok(s),
+    }
+}
+
+fn map_group(applied: &Request, g: u16) -> Result<u16, Error> {
+    match applied {
+        Request::Delete(DeleteReq::Group(deleted)) => shift_deleted(g, *deleted),
+        Request::Move(MoveReq::Group(src, tgt)) => Ok(move_index(g, *src, *tgt)),
+        _ => Ok(g),
+    }
+}
+
+pub(crate) fn map_layer(applied: &Request, (g, l): (u16, u16)) -> Result<(u16, u16), Error> {
+    match applied {
+        Request::Delete(DeleteReq::Layer(dg, dl)) if g == *dg => Ok((g, shift_deleted(l, *dl)?)),
+        Request::Move(MoveReq::Layer(src, tgt)) => {
+            if (g, l) == *src {
+                Ok(*tgt)
+            } else {
+                // the layer is removed from src, then inserted in tgt.
+                let l = if g == src.0 && l > src.1 { l - 1 } else { l };
+                let l = if g == tgt.0 && l >= tgt.1 { l + 1 } else { l };
+                Ok((g, l))
+            }
+        }
+        _ => Ok((map_group(applied, g)?, l)),
+    }
+}
+
+fn map_quad(applied: &Request, (g, l, q): (u16, u16, u16)) -> Result<(u16, u16, u16), Error> {
+    match applied {
+        Request::Delete(DeleteReq::Quad(dg, dl, dq)) if (g, l) == (*dg, *dl) => {
+            Ok((g, l, shift_deleted(q, *dq)?))
+        }
+        Request::Move(MoveReq::Quad((sg, sl, sq), tgt)) if (g, l) == (*sg, *sl) => {
+            Ok((g, l, move_index(q, *sq, *tgt)))
+        }
+        _ => {
+            let (g, l) = map_layer(applied, (g, l))?;
+            Ok((g, l, q))
+        }
+    }
+}
+
+fn map_source(applied: &Request, (g, l, s): (u16, u16, u16)) -> Result<(u16, u16, u16), Error> {
+    match applied {
+        Request::Delete(DeleteReq::Source(dg, dl, ds)) if (g, l) == (*dg, *dl) => {
+            Ok((g, l, shift_deleted(s, *ds)?))
+        }
+        Request::Move(MoveReq::Source((sg, sl, ss), tgt)) if (g, l) == (*sg, *sl) => {
+            Ok((g, l, move_index(s, *ss, *tgt)))
+        }
+        _ => {
+            let (g, l) = map_layer(applied, (g, l))?;
+            Ok((g, l, s))
+        }
+    }
+}
+
+fn rebase_env_ref(applied: &Request, e: Option<u16>) -> Result<Option<u16>, Error> {
+    e.map(|e| map_envelope(applied, e)).transpose()
+}
+
+fn rebase_image_ref(applied: &Request, i: Option<u16>) -> Result<Option<u16>, Error> {
+    i.map(|i| map_image(applied, i)).transpose()
+}
+
+fn rebase_quad(applied: &Request, mut quad: Box<twmap::Quad>) -> Result<Box<twmap::Quad>, Error> {
+    quad.position_env = rebase_env_ref(applied, quad.position_env)?;
+    quad.color_env = rebase_env_ref(applied, quad.color_env)?;
+    Ok(quad)
+}
+
+fn rebase_source(
+    applied: &Request,
+    mut source: Box<twmap::SoundSource>,
+) -> Result<Box<twmap::SoundSource>, Error> {
+    source.position_env = rebase_env_ref(applied, source.position_env)?;
+    source.sound_env = rebase_env_ref(applied, source.sound_env)?;
+    Ok(source)
+}
+
+fn rebase_partial_layer(
+    applied: &Request,
+    mut part: Box<PartialLayer>,
+) -> Result<Box<PartialLayer>, Error> {
+    match part.as_mut() {
+        PartialLayer::Tiles(part) => {
+            if let Some(image) = part.image {
+                part.image = Some(rebase_image_ref(applied, image)?);
+            }
+            if let Some(env) = part.color_env {
+                part.color_env = Some(rebase_env_ref(applied, env)?);
+            }
+        }
+        PartialLayer::Quads(part) => {
+            if let Some(image) = part.image {
+                part.image = Some(rebase_image_ref(applied, image)?);
+            }
+        }
+        PartialLayer::Sounds(part) => {
+            if let Some(sound) = part.sound {
+                part.sound = Some(sound.map(|s| map_sound(applied, s)).transpose()?);
+            }
+        }
+        _ => (),
+    }
+    Ok(part)
+}
+
+/// Rebases `req` on top of the concurrent request `applied`.
+fn rebase(applied: &Request, req: Request) -> Result<Request, Error> {
+    if let Request::Batch(batch) = applied {
+        return batch
+            .iter()
+            .try_fold(req, |req, applied| rebase(applied, req));
+    }
+
+    // move targets are positions, not objects: they cannot conflict.
+    let target = |res: Result<u16, Error>, tgt: u16| res.unwrap_or(tgt);
+
+    let req = match req {
+        Request::Create(req) => Request::Create(match req {
+            CreateReq::Layer(g, part) => {
+                CreateReq::Layer(map_group(applied, g)?, rebase_partial_layer(applied, part)?)
+            }
+            CreateReq::Quad(g, l, quad) => {
+                let (g, l) = map_layer(applied, (g, l))?;
+                CreateReq::Quad(g, l, rebase_quad(applied, quad)?)
+            }
+            CreateReq::Source(g, l, source) => {
+                let (g, l) = map_layer(applied, (g, l))?;
+                CreateReq::Source(g, l, rebase_source(applied, source)?)
+            }
+            req => req,
+        }),
+        Request::Edit(req) => Request::Edit(match req {
+            EditReq::Envelope(e, part) => EditReq::Envelope(map_envelope(applied, e)?, part),
+            EditReq::Group(g, part) => EditReq::Group(map_group(applied, g)?, part),
+            EditReq::Layer(g, l, part) => {
+                let (g, l) = map_layer(applied, (g, l))?;
+                EditReq::Layer(g, l, rebase_partial_layer(applied, part)?)
+            }
+            EditReq::Tiles(g, l, tiles) => {
+                let (g, l) = map_layer(applied, (g, l))?;
+                EditReq::Tiles(g, l, tiles)
+            }
+            EditReq::Quad(g, l, q, quad) => {
+                let (g, l, q) = map_quad(applied, (g, l, q))?;
+                EditReq::Quad(g, l, q, rebase_quad(applied, quad)?)
+            }
+            EditReq::Source(g, l, s, source) => {
+                let (g, l, s) = map_source(applied, (g, l, s))?;
+                EditReq::Source(g, l, s, rebase_source(applied, source)?)
+            }
+            EditReq::Automap(g, l) => {
+                let (g, l) = map_layer(applied, (g, l))?;
+                EditReq::Automap(g, l)
+            }
+            req @ (EditReq::Config(_) | EditReq::Info(_)) => req,
+        }),
+        Request::Delete(req) => Request::Delete(match req {
+            DeleteReq::Image(i) => DeleteReq::Image(map_image(applied, i)?),
+            DeleteReq::Envelope(e) => DeleteReq::Envelope(map_envelope(applied, e)?),
+            DeleteReq::Group(g) => DeleteReq::Group(map_group(applied, g)?),
+            DeleteReq::Layer(g, l) => {
+                let (g, l) = map_layer(applied, (g, l))?;
+                DeleteReq::Layer(g, l)
+            }
+            DeleteReq::Quad(g, l, q) => {
+                let (g, l, q) = map_quad(applied, (g, l, q))?;
+                DeleteReq::Quad(g, l, q)
+            }
+            DeleteReq::Sound(s) => DeleteReq::Sound(map_sound(applied, s)?),
+            DeleteReq::Source(g, l, s) => {
+                let (g, l, s) = map_source(applied, (g, l, s))?;
+                DeleteReq::Source(g, l, s)
+            }
+            req @ DeleteReq::Automapper(_) => req,
+        }),
+        Request::Move(req) => Request::Move(match req {
+            MoveReq::Image(src, tgt) => MoveReq::Image(
+                map_image(applied, src)?,
+                target(map_image(applied, tgt), tgt),
+            ),
+            MoveReq::Envelope(src, tgt) => MoveReq::Envelope(
+                map_envelope(applied, src)?,
+                target(map_envelope(applied, tgt), tgt),
+            ),
+            MoveReq::Group(src, tgt) => MoveReq::Group(
+                map_group(applied, src)?,
+                target(map_group(applied, tgt), tgt),
+            ),
+            MoveReq::Layer(src, tgt) => MoveReq::Layer(
+                map_layer(applied, src)?,
+                map_layer(applied, tgt).unwrap_or(tgt),
+            ),
+            MoveReq::Quad(src, tgt) => {
+                let (g, l, q) = map_quad(applied, src)?;
+                let tgt = map_quad(applied, (src.0, src.1, tgt)).map_or(tgt, |(_, _, q)| q);
+                MoveReq::Quad((g, l, q), tgt)
+            }
+            MoveReq::Sound(src, tgt) => MoveReq::Sound(
+                map_sound(applied, src)?,
+                target(map_sound(applied, tgt), tgt),
+            ),
+            MoveReq::Source(src, tgt) => {
+                let (g, l, s) = map_source(applied, src)?;
+                let tgt = map_source(applied, (src.0, src.1, tgt)).map_or(tgt, |(_, _, s)| s);
+                MoveReq::Source((g, l, s), tgt)
+            }
+        }),
+        Request::Batch(reqs) => Request::Batch(
+            reqs.into_iter()
+                .map(|req| rebase(applied, req))
+                .collect::<Result<_, _>>()?,
+        ),
+        req => req,
+    };
+
+    Ok(req)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn revisions(applied: impl IntoIterator<Item = Request>) -> Revisions {
+        let mut revs = Revisions::default();
+        for req in applied {
+            revs.push(req);
+        }
+        revs
+    }
+
+    #[track_caller]
+    fn assert_rebase(revs: &Revisions, base: u64, req: Request, expected: Request) {
+        let rebased = revs.rebase(base, req).unwrap();
+        assert_eq!(
+            serde_json::to_value(rebased).unwrap(),
+            serde_json::to_value(expected).unwrap()
+        );
+    }
+
+    #[track_caller]
+    fn assert_conflict(revs: &Revisions, base: u64, req: Request) {
+        let res = revs.rebase(base, req);
+        assert!(matches!(res, Err(Error::Conflict)), "{res:?}");
+    }
+
+    fn edit_group(g: u16) -> Request {
+        Request::Edit(EditReq::Group(g, Box::default()))
+    }
+
+    fn automap(g: u16, l: u16) -> Request {
+        Request::Edit(EditReq::Automap(g, l))
+    }
+
+    fn delete_quad(g: u16, l: u16, q: u16) -> Request {
+        Request::Delete(DeleteReq::Quad(g, l, q))
+    }
+
+    #[test]
+    fn shift_after_delete() {
+        let revs = revisions([Request::Delete(DeleteReq::Layer(1, 1))]);
+        assert_rebase(&revs, 0, automap(1, 0), automap(1, 0));
+        assert_rebase(&revs, 0, automap(1, 3), automap(1, 2));
+        assert_rebase(&revs, 0, automap(0, 3), automap(0, 3));
+        assert_rebase(&revs, 0, delete_quad(1, 2, 5), delete_quad(1, 1, 5));
+
+        let revs = revisions([Request::Delete(DeleteReq::Image(1))]);
+        let delete_image = |i| Request::Delete(DeleteReq::Image(i));
+        assert_rebase(&revs, 0, delete_image(0), delete_image(0));
+        assert_rebase(&revs, 0, delete_image(2), delete_image(1));
+
+        let revs = revisions([delete_quad(1, 0, 1)]);
+        assert_rebase(&revs, 0, delete_quad(1, 0, 3), delete_quad(1, 0, 2));
+        assert_rebase(&revs, 0, delete_quad(1, 1, 3), delete_quad(1, 1, 3));
+    }
+
+    #[test]
+    fn shift_after_move() {
+        let revs = revisions([Request::Move(MoveReq::Group(0, 2))]);
+        assert_rebase(&revs, 0, edit_group(0), edit_group(2));
+        assert_rebase(&revs, 0, edit_group(1), edit_group(0));
+        assert_rebase(&revs, 0, edit_group(2), edit_group(1));
+        assert_rebase(&revs, 0, edit_group(3), edit_group(3));
+        // layers follow the move of their group.
+        assert_rebase(&revs, 0, automap(0, 4), automap(2, 4));
+
+        let revs = revisions([Request::Move(MoveReq::Quad((1, 0, 0), 2))]);
+        assert_rebase(&revs, 0, delete_quad(1, 0, 0), delete_quad(1, 0, 2));
+        assert_rebase(&revs, 0, delete_quad(1, 0, 1), delete_quad(1, 0, 0));
+        assert_rebase(&revs, 0, delete_quad(1, 1, 1), delete_quad(1, 1, 1));
+    }
+
+    #[test]
+    fn move_targets_are_rebased() {
+        let revs = revisions([Request::Delete(DeleteReq::Image(0))]);
+        let move_image = |src, tgt| Request::Move(MoveReq::Image(src, tgt));
+        assert_rebase(&revs, 0, move_image(2, 3), move_image(1, 2));
+        // the target is a position, the deleted image at this position is no conflict.
+        assert_rebase(&revs, 0, move_image(3, 0), move_image(2, 0));
+
+        let revs = revisions([Request::Delete(DeleteReq::Group(0))]);
+        let move_layer = |src, tgt| Request::Move(MoveReq::Layer(src, tgt));
+        assert_rebase(
+            &revs,
+            0,
+            move_layer((1, 0), (2, 1)),
+            move_layer((0, 0), (1, 1)),
+        );
+    }
+
+    #[test]
+    fn move_layer_across_groups() {
+        let revs = revisions([Request::Move(MoveReq::Layer((1, 2), (2, 0)))]);
+        assert_rebase(&revs, 0, automap(1, 2), automap(2, 0));
+        assert_rebase(&revs, 0, automap(1, 1), automap(1, 1));
+        assert_rebase(&revs, 0, automap(1, 3), automap(1, 2));
+        assert_rebase(&revs, 0, automap(2, 0), automap(2, 1));
+        assert_rebase(&revs, 0, automap(2, 4), automap(2, 5));
+        assert_rebase(&revs, 0, automap(0, 2), automap(0, 2));
+        assert_rebase(&revs, 0, delete_quad(1, 2, 3), delete_quad(2, 0, 3));
+
+        // moving a layer within its group behaves like other moves.
+        let revs = revisions([Request::Move(MoveReq::Layer((1, 0), (1, 2)))]);
+        assert_rebase(&revs, 0, automap(1, 0), automap(1, 2));
+        assert_rebase(&revs, 0, automap(1, 1), automap(1, 0));
+        assert_rebase(&revs, 0, automap(1, 2), automap(1, 1));
+        assert_rebase(&revs, 0, automap(1, 3), automap(1, 3));
+    }
+
+    #[test]
+    fn conflict_on_deleted_target() {
+        let revs = revisions([Request::Delete(DeleteReq::Group(1))]);
+        assert_conflict(&revs, 0, edit_group(1));
+        assert_conflict(&revs, 0, automap(1, 0));
+        assert_conflict(&revs, 0, delete_quad(1, 0, 0));
+        assert_conflict(&revs, 0, Request::Move(MoveReq::Group(1, 0)));
+        assert_rebase(&revs, 0, edit_group(2), edit_group(1));
+
+        let revs = revisions([Request::Delete(DeleteReq::Layer(1, 0))]);
+        assert_conflict(&revs, 0, automap(1, 0));
+        assert_conflict(&revs, 0, delete_quad(1, 0, 2));
+
+        let revs = revisions([delete_quad(1, 0, 2)]);
+        assert_conflict(&revs, 0, delete_quad(1, 0, 2));
+
+        // the request is up-to-date: there is nothing to rebase.
+        assert_rebase(&revs, 1, delete_quad(1, 0, 2), delete_quad(1, 0, 2));
+    }
+
+    #[test]
+    fn rebase_batch() {
+        let revs = revisions([Request::Delete(DeleteReq::Group(1))]);
+        let batch = Request::Batch(vec![edit_group(0), edit_group(2), automap(3, 1)]);
+        let expected = Request::Batch(vec![edit_group(0), edit_group(1), automap(2, 1)]);
+        assert_rebase(&revs, 0, batch, expected);
+
+        // a single conflict rejects the whole batch.
+        let batch = Request::Batch(vec![edit_group(2), edit_group(1)]);
+        assert_conflict(&revs, 0, batch);
+
+        // requests are rebased on each request of an applied batch, in order.
+        let applied = Request::Batch(vec![
+            Request::Delete(DeleteReq::Group(0)),
+            Request::Move(MoveReq::Group(0, 2)),
+        ]);
+        let revs = revisions([applied]);
+        assert_rebase(&revs, 0, edit_group(1), edit_group(2));
+        assert_rebase(&revs, 0, edit_group(2), edit_group(0));
+        assert_conflict(&revs, 0, edit_group(0));
+    }
+
+    #[test]
+    fn rebase_on_several_revisions() {
+        let revs = revisions([
+            Request::Delete(DeleteReq::Group(0)),
+            Request::Delete(DeleteReq::Group(0)),
+        ]);
+        assert_rebase(&revs, 0, edit_group(3), edit_group(1));
+        assert_rebase(&revs, 1, edit_group(3), edit_group(2));
+        assert_rebase(&revs, 2, edit_group(3), edit_group(3));
+    }
+
+    #[test]
+    fn base_revision_out_of_window() {
+        let revs = revisions((0..MAX_LOG_LEN + 2).map(|_| edit_group(0)));
+        let current = revs.current();
+        assert_eq!(current, MAX_LOG_LEN as u64 + 2);
+
+        assert_conflict(&revs, 0, edit_group(0));
+        assert_conflict(&revs, 1, edit_group(0));
+        assert_rebase(&revs, 2, edit_group(0), edit_group(0));
+        assert_rebase(&revs, current, edit_group(0), edit_group(0));
+        // revisions from the future are rejected too.
+        assert_conflict(&revs, current + 1, edit_group(0));
+
+        let mut revs = revs;
+        revs.reset();
+        assert_conflict(&revs, current, edit_group(0));
+    }
+}
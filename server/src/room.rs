@@ -1,16 +1,22 @@
 use std::{
     cmp::min,
     collections::HashMap,
-    fs::File,
-    io::Write,
     path::{Path, PathBuf},
     sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
+use parking_lot::Mutex;
+
 use crate::{
     error::Error,
+    history::History,
+    locks::LayerLocks,
     map_cfg::{read_map_config, MapConfig},
+    protocol::Comment,
+    revision::Revisions,
     server::User,
+    util::write_atomic,
 };
 
 fn server_error<E: std::fmt::Display>(err: E) -> Error {
@@ -18,6 +24,13 @@ fn server_error<E: std::fmt::Display>(err: E) -> Error {
     Error::Internal("".into())
 }
 
+fn read_comments(path: &Path) -> Vec<Comment> {
+    std::fs::File::open(path)
+        .ok()
+        .and_then(|file| serde_json::from_reader(file).ok())
+        .unwrap_or_default()
+}
+
 fn load_map(path: &Path) -> Result<twmap::TwMap, twmap::Error> {
     let mut map = twmap::TwMap::parse(&std::fs::read(path)?)?;
     map.load()?;
@@ -30,13 +43,21 @@ pub struct Room {
     cfg_path: Option<PathBuf>,
     am_path: Option<PathBuf>,
     pub config: MapConfig,
+    pub comments: Vec<Comment>,
     users: HashMap<String, Arc<User>>,
     map: Option<twmap::TwMap>,
+    pub history: History,
+    pub revisions: Revisions,
+    pub locks: LayerLocks,
+    dirty: bool, // the map has edits that were not saved
+    edit_lock: Arc<Mutex<()>>,
 }
 
 const MAP_FILE_NAME: &str = "map.map";
 const CFG_FILE_NAME: &str = "config.json";
+const COMMENTS_FILE_NAME: &str = "comments.json";
 const AUTOMAPPER_DIR_NAME: &str = "automappers";
+const BACKUP_DIR_NAME: &str = "backups";
 
 impl Room {
     pub fn new_from_dir(dir_path: PathBuf) -> Option<Self> {
@@ -60,15 +81,23 @@ impl Room {
             ..Default::default()
         });
 
-        Some(Self {
+        let mut room = Self {
             dir_path: Some(dir_path),
             map_path,
             cfg_path: Some(cfg_path),
             am_path: Some(am_path),
             config,
+            comments: Vec::new(),
             users: HashMap::new(),
             map: None,
-        })
+            history: Default::default(),
+            revisions: Default::default(),
+            locks: Default::default(),
+            dirty: false,
+            edit_lock: Default::default(),
+        };
+        room.comments = read_comments(&room.comments_path());
+        Some(room)
     }
 
     pub fn new_from_files(
@@ -86,15 +115,23 @@ impl Room {
                 ..Default::default()
             });
 
-        Some(Self {
+        let mut room = Self {
             dir_path: None,
             map_path,
             cfg_path,
             am_path,
             config,
+            comments: Vec::new(),
             users: HashMap::new(),
             map: None,
-        })
+            history: Default::default(),
+            revisions: Default::default(),
+            locks: Default::default(),
+            dirty: false,
+            edit_lock: Default::default(),
+        };
+        room.comments = read_comments(&room.comments_path());
+        Some(room)
     }
 
     pub fn delete(&self) {
@@ -102,6 +139,11 @@ impl Room {
             std::fs::remove_dir_all(path).ok();
         } else {
             std::fs::remove_file(&self.map_path).ok();
+            std::fs::remove_file(self.recovery_path()).ok();
+            std::fs::remove_file(self.comments_path()).ok();
+            if let Some(path) = self.backup_dir() {
+                std::fs::remove_dir_all(path).ok();
+            }
             if let Some(path) = &self.cfg_path {
                 std::fs::remove_file(path).ok();
             }
@@ -118,6 +160,12 @@ impl Room {
             .unwrap_or_else(|| panic!("failed to load map `{}`", self.map_path.display()))
     }
 
+    /// Lock held while a request is applied to the map, so that requests to the
+    /// same room are applied (and recorded in the history) one at a time.
+    pub fn edit_lock(&self) -> Arc<Mutex<()>> {
+        self.edit_lock.clone()
+    }
+
     pub fn name(&self) -> &str {
         self.config.name.as_ref()
     }
@@ -149,8 +197,7 @@ impl Room {
     pub fn remove_user(&mut self, user: &User) {
         self.users.remove(&user.token);
         if self.users.is_empty() {
-            self.map = None;
-            log::debug!("map unloaded `{}`", self.map_path.display());
+            self.unload();
         }
     }
 
@@ -167,37 +214,307 @@ impl Room {
     pub fn remove_closed_users(&mut self) {
         self.users.retain(|_, p| !p.tx.is_closed());
         if self.users.is_empty() {
-            self.map = None;
-            log::debug!("map unloaded `{}`", self.map_path.display());
+            self.unload();
+        }
+    }
+
+    fn unload(&mut self) {
+        if self.dirty {
+            if let Err(e) = self.write_recovery() {
+                log::error!(
+                    "failed to write recovery file for `{}`: {e}",
+                    self.map_path.display()
+                );
+            }
+        }
+        self.map = None;
+        self.dirty = false;
+        self.history.clear();
+        self.revisions.clear();
+        self.locks.clear();
+        log::debug!("map unloaded `{}`", self.map_path.display());
+    }
+
+    pub fn mark_dirty(&mut self) {
+        self.dirty = true;
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    pub fn recovery_path(&self) -> PathBuf {
+        let mut path = self.map_path.clone();
+        path.set_extension("map.recovery");
+        path
+    }
+
+    pub fn has_recovery(&self) -> bool {
+        self.recovery_path().is_file()
+    }
+
+    /// Unsaved edits are written to a recovery file when the map is unloaded.
+    fn write_recovery(&mut self) -> Result<(), twmap::Error> {
+        if let Some(map) = &mut self.map {
+            let mut buf = Vec::new();
+            map.save(&mut buf)?;
+            write_atomic(&self.recovery_path(), &buf)?;
+            log::info!("recovery file written `{}`", self.recovery_path().display());
+        }
+        Ok(())
+    }
+
+    /// Replaces the map, e.g. with a recovery file or a backup. The new map is unsaved.
+    fn replace_map(&mut self, map: twmap::TwMap) {
+        self.map = Some(map);
+        self.dirty = true;
+        self.history.clear();
+        self.revisions.reset();
+        self.locks.clear();
+    }
+
+    /// Puts back a copy of the map taken earlier, e.g. to roll back a failed batch.
+    pub fn restore_snapshot(&mut self, map: twmap::TwMap) {
+        self.map = Some(map);
+    }
+
+    pub fn restore_recovery(&mut self) -> Result<(), Error> {
+        let path = self.recovery_path();
+        if !path.is_file() {
+            return Err(Error::RecoveryNotFound);
         }
+        let map = load_map(&path).map_err(server_error)?;
+        std::fs::remove_file(&path).map_err(server_error)?;
+        self.replace_map(map);
+        log::info!("map recovered `{}`", self.map_path.display());
+        Ok(())
+    }
+
+    pub fn delete_recovery(&mut self) -> Result<(), Error> {
+        let path = self.recovery_path();
+        if !path.is_file() {
+            return Err(Error::RecoveryNotFound);
+        }
+        std::fs::remove_file(&path).map_err(server_error)
+    }
+
+    /// Backups are stored in the map directory, or in a backups folder next to the
+    /// maps folder for data directory maps.
+    pub fn backup_dir(&self) -> Option<PathBuf> {
+        match &self.dir_path {
+            Some(dir) => Some(dir.join(BACKUP_DIR_NAME)),
+            None => {
+                let maps_dir = self.map_path.parent()?;
+                let name = self.map_path.file_stem()?;
+                Some(maps_dir.parent()?.join(BACKUP_DIR_NAME).join(name))
+            }
+        }
+    }
+
+    /// Names of the backups of this map, oldest first.
+    pub fn backups(&self) -> Vec<String> {
+        let mut backups: Vec<_> = self
+            .backup_dir()
+            .and_then(|dir| std::fs::read_dir(dir).ok())
+            .into_iter()
+            .flatten()
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "map"))
+            .filter_map(|path| Some(path.file_stem()?.to_string_lossy().to_string()))
+            .collect();
+        backups.sort();
+        backups
+    }
+
+    /// Copies the saved map file to a new backup and removes the oldest backups.
+    pub fn write_backup(&self, max_backups: usize) -> Result<(), Error> {
+        let dir = match self.backup_dir() {
+            Some(dir) if max_backups > 0 => dir,
+            _ => return Ok(()),
+        };
+        std::fs::create_dir_all(&dir).map_err(server_error)?;
+        // backups are named after the time in microseconds, so that the names sort
+        // by age. A backup made in the same microsecond as another takes the next one.
+        let mut time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(server_error)?;
+        let path = loop {
+            let name = format!("{}.{:06}", time.as_secs(), time.subsec_micros());
+            let path = dir.join(format!("{name}.map"));
+            if !path.exists() {
+                break path;
+            }
+            time += Duration::from_micros(1);
+        };
+        std::fs::copy(&self.map_path, &path).map_err(server_error)?;
+
+        let backups = self.backups();
+        let excess = backups.len().saturating_sub(max_backups);
+        for name in &backups[..excess] {
+            std::fs::remove_file(dir.join(format!("{name}.map"))).ok();
+        }
+        Ok(())
+    }
+
+    pub fn restore_backup(&mut self, name: &str) -> Result<(), Error> {
+        if !self.backups().iter().any(|b| b == name) {
+            return Err(Error::BackupNotFound);
+        }
+        let dir = self.backup_dir().ok_or(Error::BackupNotFound)?;
+        let map = load_map(&dir.join(format!("{name}.map"))).map_err(server_error)?;
+        self.replace_map(map);
+        log::info!(
+            "map `{}` restored from backup {name}",
+            self.map_path.display()
+        );
+        Ok(())
+    }
+
+    /// Comments are stored in the map directory, or next to the map file for
+    /// data directory maps.
+    pub fn comments_path(&self) -> PathBuf {
+        match &self.dir_path {
+            Some(dir) => dir.join(COMMENTS_FILE_NAME),
+            None => {
+                let mut path = self.map_path.clone();
+                path.set_extension(COMMENTS_FILE_NAME);
+                path
+            }
+        }
+    }
+
+    pub fn save_comments(&self) -> Result<(), Error> {
+        let buf = serde_json::to_vec(&self.comments).map_err(server_error)?;
+        write_atomic(&self.comments_path(), &buf).map_err(server_error)
     }
 
     pub fn save_config(&mut self) -> Result<(), Error> {
         if let Some(cfg_path) = &self.cfg_path {
-            let file = File::create(cfg_path).map_err(server_error)?;
-            serde_json::to_writer(file, &self.config).map_err(server_error)?;
+            let buf = serde_json::to_vec(&self.config).map_err(server_error)?;
+            write_atomic(cfg_path, &buf).map_err(server_error)?;
         }
         Ok(())
     }
 
     pub fn save_map(&mut self, max_size: usize) -> Result<(), Error> {
-        let mut tmp_path = self.map_path.clone();
-        tmp_path.set_extension("map.tmp");
+        let mut buf = Vec::with_capacity(min(max_size, 1024 * 1024));
+        self.map().save(&mut buf).map_err(server_error)?;
 
-        (|| -> Result<(), Error> {
-            let mut buf = Vec::with_capacity(min(max_size, 1024 * 1024));
-            self.map().save(&mut buf).map_err(server_error)?;
+        if buf.len() > max_size {
+            return Err(Error::MapTooBig);
+        }
 
-            if buf.len() > max_size {
-                return Err(Error::MapTooBig);
-            }
+        // the map is written to map.map.tmp first, so that the original file is
+        // never left half-written.
+        write_atomic(&self.map_path, &buf).map_err(server_error)?;
 
-            let mut file = File::create(&self.map_path).map_err(server_error)?;
-            file.write_all(&buf).map_err(server_error)?;
-            Ok(())
-        })()?;
+        self.dirty = false;
 
         log::debug!("map saved `{}`", self.map_path.display());
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        protocol::*,
+        testing::{TestServer, MAP},
+    };
+
+    fn create_group() -> Request {
+        Request::Create(CreateReq::Group(Box::default()))
+    }
+
+    #[test]
+    fn backup_rotation() {
+        let server = TestServer::new("backup-rotation");
+        let room = server.room();
+        let room = room.read();
+
+        room.write_backup(0).unwrap();
+        assert!(room.backups().is_empty());
+
+        // backups made in quick succession do not overwrite each other.
+        let mut written = Vec::new();
+        for _ in 0..5 {
+            room.write_backup(3).unwrap();
+            written.push(room.backups().last().unwrap().clone());
+        }
+        assert!(written.windows(2).all(|w| w[0] < w[1]), "{written:?}");
+        assert_eq!(room.backups(), written[2..]);
+    }
+
+    #[test]
+    fn backups_sort_by_age() {
+        let server = TestServer::new("backups-sort-by-age");
+        let room = server.room();
+        let room = room.read();
+
+        // the names of the backups of previous versions have a one-second resolution.
+        let dir = room.backup_dir().unwrap();
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::copy(room.map_path(), dir.join("1700000000.map")).unwrap();
+
+        room.write_backup(10).unwrap();
+        let backups = room.backups();
+        assert_eq!(backups.len(), 2);
+        assert_eq!(backups[0], "1700000000");
+    }
+
+    #[test]
+    fn restore_backup() {
+        let server = TestServer::new("restore-backup");
+        let user = server.join(None).unwrap();
+        server.room().read().write_backup(10).unwrap();
+        let backup = server.room().read().backups()[0].clone();
+
+        server.request(&user, create_group()).unwrap();
+        let mut room = server.room().write_arc();
+        assert!(matches!(
+            room.restore_backup("nope"),
+            Err(Error::BackupNotFound)
+        ));
+        room.restore_backup(&backup).unwrap();
+        assert_eq!(room.map().groups.len(), 1);
+        assert!(room.is_dirty());
+    }
+
+    #[test]
+    fn recovery_written_on_unload() {
+        let server = TestServer::new("recovery-written-on-unload");
+
+        // a map without unsaved edits has nothing to recover.
+        let user = server.join(None).unwrap();
+        server.server.user_leave(&user, MAP).unwrap();
+        assert!(!server.room().read().has_recovery());
+
+        let user = server.join(None).unwrap();
+        server.request(&user, create_group()).unwrap();
+        server.server.user_leave(&user, MAP).unwrap();
+        let path = server.room().read().map_path().to_owned();
+        assert!(server.room().read().has_recovery());
+        assert_eq!(load_map(&path).unwrap().groups.len(), 1);
+
+        let user = server.join(None).unwrap();
+        let received = server.received(&user);
+        assert!(
+            received.iter().any(|packet| matches!(
+                packet.content,
+                Message::Broadcast(Broadcast::RecoveryAvailable)
+            )),
+            "{received:?}"
+        );
+        let mut room = server.room().write_arc();
+        room.restore_recovery().unwrap();
+        assert_eq!(room.map().groups.len(), 2);
+        assert!(room.is_dirty());
+        assert!(!room.has_recovery());
+        assert!(matches!(
+            room.restore_recovery(),
+            Err(Error::RecoveryNotFound)
+        ));
+    }
+}
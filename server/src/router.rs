@@ -1,14 +1,24 @@
-use std::{net::SocketAddr, sync::Arc};
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
 
 use axum::{
+    async_trait,
     body::Bytes,
-    extract::{ws, ConnectInfo, DefaultBodyLimit, Path, State, WebSocketUpgrade},
+    extract::{
+        ws, ConnectInfo, DefaultBodyLimit, FromRequestParts, Path, Query, State, WebSocketUpgrade,
+    },
     http::{
         header::{AUTHORIZATION, CONTENT_TYPE},
+        request::Parts,
         Method,
     },
     response::IntoResponse,
-    routing::{delete, get, post},
+    routing::{get, post, put},
     Json,
 };
 use axum_extra::{
@@ -18,6 +28,7 @@ use axum_extra::{
 use axum_server::tls_rustls::RustlsConfig;
 
 use rand::Rng;
+use serde::{Deserialize, Serialize};
 use tower_governor::{governor::GovernorConfigBuilder, GovernorLayer};
 use tower_http::{
     cors,
@@ -26,11 +37,12 @@ use tower_http::{
 use vek::num_traits::clamp;
 
 use crate::{base64::Base64, error::Error, protocol::*};
-use crate::{Cli, Server};
+use crate::{server::User, Cli, Server};
 
 pub struct Router {
     addr: SocketAddr,
-    router: axum::Router,
+    pub(crate) router: axum::Router,
+    server: Arc<Server>,
 }
 
 impl Router {
@@ -57,8 +69,37 @@ impl Router {
                 get(route_get_config).post(route_post_config),
             )
             .route("/maps/:map/info", get(route_get_info).post(route_post_info))
+            .route("/maps/:map/check", get(route_get_check))
+            .route("/maps/:map/lints", get(route_get_lints))
+            .route("/maps/:map/reachability", get(route_get_reachability))
+            .route("/maps/:map/users", get(route_get_users))
+            .route("/maps/:map/cursors", get(route_get_cursors))
+            .route("/maps/:map/backups", get(route_get_backups))
+            .route("/maps/:map/comments", get(route_get_comments))
+            .route("/maps/:map/locks", get(route_get_locks))
+            .route("/maps/:map/invites", get(route_get_invites))
+            .route("/maps/:map/move", post(route_move))
             .route("/maps/:map/images", get(route_get_images))
-            .route("/maps/:map/images/:image", get(route_get_image))
+            .route(
+                "/maps/:map/images/:image",
+                get(route_get_image)
+                    .put(route_put_image)
+                    .delete(route_delete_image),
+            )
+            .route("/maps/:map/sounds", get(route_get_sounds))
+            .route(
+                "/maps/:map/sounds/:sound",
+                get(route_get_sound)
+                    .put(route_put_sound)
+                    .delete(route_delete_sound),
+            )
+            .route("/maps/:map/automappers", get(route_get_automappers))
+            .route(
+                "/maps/:map/automappers/:automapper",
+                get(route_get_automapper)
+                    .put(route_put_automapper)
+                    .delete(route_delete_automapper),
+            )
             .route(
                 "/maps/:map/envelopes",
                 get(route_get_envelopes).put(route_put_envelope),
@@ -75,7 +116,9 @@ impl Router {
             )
             .route(
                 "/maps/:map/groups/:group",
-                post(route_post_group).delete(route_delete_group),
+                get(route_get_group)
+                    .post(route_post_group)
+                    .delete(route_delete_group),
             )
             .route(
                 "/maps/:map/groups/:group/layers",
@@ -83,16 +126,49 @@ impl Router {
             )
             .route(
                 "/maps/:map/groups/:group/layers/:layer",
-                delete(route_delete_layer),
+                get(route_get_layer)
+                    .post(route_post_layer)
+                    .delete(route_delete_layer),
+            )
+            .route(
+                "/maps/:map/groups/:group/layers/:layer/tiles",
+                get(route_get_tiles).post(route_post_tiles),
+            )
+            .route(
+                "/maps/:map/groups/:group/layers/:layer/automap",
+                post(route_post_automap),
+            )
+            .route(
+                "/maps/:map/groups/:group/layers/:layer/quads",
+                put(route_put_quad),
+            )
+            .route(
+                "/maps/:map/groups/:group/layers/:layer/quads/:quad",
+                get(route_get_quad)
+                    .post(route_post_quad)
+                    .delete(route_delete_quad),
+            )
+            .route(
+                "/maps/:map/groups/:group/layers/:layer/sources",
+                put(route_put_source),
+            )
+            .route(
+                "/maps/:map/groups/:group/layers/:layer/sources/:source",
+                get(route_get_source)
+                    .post(route_post_source)
+                    .delete(route_delete_source),
             );
-        // .route(
-        //     "/maps/:map/map/groups/:group/layers/:layer/tiles",
-        //     post(route_post_tiles),
-        // )
 
         let mut router = http_routes;
         router = router.route("/ws", get(route_websocket));
 
+        #[cfg(feature = "schema")]
+        {
+            router = router
+                .route("/schema.json", get(route_get_schema))
+                .route("/openapi.json", get(route_get_openapi));
+        }
+
         #[cfg(feature = "bridge_in")]
         {
             use crate::bridge_router::*;
@@ -125,7 +201,7 @@ impl Router {
                 clamp(args.max_map_size, 1024, 50 * 1024) * 1024,
             )) // allows uploading maps between 1MiB-50MiB
             .layer(cors)
-            .with_state(server);
+            .with_state(server.clone());
 
         // optional endpoint to serve static files
         if let Some(dir) = &args.static_dir {
@@ -136,10 +212,24 @@ impl Router {
                 .route_service("/edit/*_", ServeFile::new(index)); // index.html handles edit routes with svelte-router.
         }
 
-        Self { addr, router }
+        Self {
+            addr,
+            router,
+            server,
+        }
     }
 
     pub async fn run(self, args: &Cli) {
+        if args.autosave_interval > 0 {
+            let period = Duration::from_secs(args.autosave_interval);
+            tokio::spawn(self.server.clone().run_autosave(period));
+        }
+
+        if args.cursor_interval > 0 {
+            let period = Duration::from_millis(args.cursor_interval);
+            tokio::spawn(self.server.clone().run_cursors(period));
+        }
+
         log::info!("listening on {}", args.addr);
 
         match (&args.cert, &args.key) {
@@ -175,11 +265,20 @@ fn gen_token() -> String {
         .collect()
 }
 
+/// Query parameters of the websocket route, to resume the session of a previous
+/// connection after the last revision received.
+#[derive(Deserialize)]
+struct ResumeQuery {
+    resume: Option<String>,
+    revision: Option<u64>,
+}
+
 async fn route_websocket(
     State(server): State<Arc<Server>>,
     ws: WebSocketUpgrade,
     user_agent: Option<TypedHeader<UserAgent>>,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Query(query): Query<ResumeQuery>,
 ) -> impl IntoResponse {
     let user_agent = if let Some(TypedHeader(user_agent)) = user_agent {
         user_agent.to_string()
@@ -187,66 +286,128 @@ async fn route_websocket(
         String::from("Unknown browser")
     };
 
-    let token = gen_token();
+    let (token, resume) = match query.resume {
+        Some(token) if server.can_resume(&token) => {
+            (token, Some(query.revision.unwrap_or_default()))
+        }
+        _ => (gen_token(), None),
+    };
 
     log::info!("client {addr} connected as {token}");
     log::debug!("client user-agent: `{user_agent}`");
 
-    ws.on_upgrade(move |mut socket| async move {
-        socket
-            .send(ws::Message::Text(format!("{{\"token\":\"{token}\"}}")))
-            .await
-            .ok();
-        server.handle_websocket(token, socket).await;
-        log::info!("client {addr} disconnected");
-    })
+    ws.protocols([Encoding::MSGPACK_PROTOCOL])
+        .on_upgrade(move |mut socket| async move {
+            let encoding = Encoding::from_protocol(socket.protocol().and_then(|p| p.to_str().ok()));
+            let msg = match encoding {
+                Encoding::Json => ws::Message::Text(format!("{{\"token\":\"{token}\"}}")),
+                Encoding::MessagePack => {
+                    let msg = HashMap::from([("token", &token)]);
+                    ws::Message::Binary(rmp_serde::to_vec_named(&msg).unwrap()) // this must not fail
+                }
+            };
+            socket.send(msg).await.ok();
+            server
+                .handle_websocket(token, socket, encoding, addr.ip(), resume)
+                .await;
+            log::info!("client {addr} disconnected");
+        })
+}
+
+/// Credentials of a REST request: a bearer token, which is either a websocket user
+/// token or an invite token, and the address of the client to throttle guesses.
+struct BearerAuth {
+    token: Option<String>,
+    ip: Option<IpAddr>,
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for BearerAuth {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let auth =
+            Option::<TypedHeader<Authorization<Bearer>>>::from_request_parts(parts, state).await?;
+        let addr = Option::<ConnectInfo<SocketAddr>>::from_request_parts(parts, state).await?;
+        Ok(Self {
+            token: auth.map(|TypedHeader(auth)| auth.token().to_owned()),
+            ip: addr.map(|ConnectInfo(addr)| addr.ip()),
+        })
+    }
+}
+
+fn bearer_user(auth: &BearerAuth, server: &Server) -> Option<Arc<User>> {
+    auth.token
+        .as_deref()
+        .and_then(|token| server.user(token).ok())
+}
+
+/// The role granted on a map to the bearer of a REST request.
+fn access_role(auth: &BearerAuth, map: &str, server: &Server) -> Result<Role, Error> {
+    if auth
+        .token
+        .as_deref()
+        .is_some_and(|token| server.is_admin_password(token))
+    {
+        return Ok(Role::Owner);
+    }
+
+    let user = bearer_user(auth, server);
+
+    // a token that is neither a user token nor a valid invite is rejected, the
+    // role granted without a password only applies to requests without a token.
+    if let (None, Some(token)) = (&user, &auth.token) {
+        return server.invite_role(map, token, auth.ip);
+    }
+
+    server
+        .granted_role(user.as_deref(), map)?
+        .ok_or(Error::Unauthorized)
 }
 
 fn ensure_access_authorized(
-    auth: &Option<TypedHeader<Authorization<Bearer>>>,
+    auth: &BearerAuth,
     map: &str,
     server: &Server,
+    role: Role,
 ) -> Result<(), Error> {
-    let room = server.room(map)?;
-    let token = auth.as_ref().map(|auth| auth.token());
-    let user = token.and_then(|token| server.user(token).ok());
-    let authorized = room.read().config.password.is_none()
-        || user.is_some_and(|user| {
-            user.inner
-                .read()
-                .room
-                .as_ref()
-                .is_some_and(|r| Arc::ptr_eq(r, &room))
-        });
-    if !authorized {
-        log::debug!(
-            "unauthorized: `{map}` for {}",
-            token.unwrap_or("nameless tee")
-        );
-    }
-    authorized.then_some(()).ok_or(Error::Unauthorized)
+    let granted = access_role(auth, map, server)?;
+    (granted >= role).then_some(()).ok_or(Error::Forbidden)
+}
+
+/// Applies an edit of a REST route the same way as a websocket request: it is
+/// checked against the layer locks, recorded in the history and broadcast to the
+/// users of the map.
+fn apply_route_request(
+    auth: &BearerAuth,
+    map: &str,
+    server: &Server,
+    req: Request,
+) -> Result<Response, Error> {
+    let role = access_role(auth, map, server)?;
+    let user = bearer_user(auth, server);
+    server.apply_map_request(user, map, role, &RecvPacket::new(None, req))
 }
 
 async fn route_http(
     State(server): State<Arc<Server>>,
-    auth: Option<TypedHeader<Authorization<Bearer>>>,
+    auth: BearerAuth,
     Json(req_packet): Json<RecvPacket>,
 ) -> impl IntoResponse {
-    let user = auth
-        .as_ref()
-        .map(|auth| auth.token())
-        .and_then(|token| server.user(token).ok());
+    let user = bearer_user(&auth, &server);
 
-    let resp = server.do_request(user.clone(), req_packet.content.clone());
+    let resp_packet = server.apply_request(user, &req_packet);
+    Json(resp_packet)
+}
 
-    if let Some(user) = &user {
-        if resp.is_ok() {
-            server.do_broadcast(user, &req_packet);
-        }
-    }
+#[cfg(feature = "schema")]
+async fn route_get_schema() -> impl IntoResponse {
+    Json(crate::schema::json_schema())
+}
 
-    let resp_packet = SendPacket::new(req_packet.id, Message::Response(resp));
-    Json(resp_packet)
+#[cfg(feature = "schema")]
+async fn route_get_openapi() -> impl IntoResponse {
+    Json(crate::schema::openapi())
 }
 
 async fn route_get_maps(State(server): State<Arc<Server>>) -> impl IntoResponse {
@@ -255,10 +416,10 @@ async fn route_get_maps(State(server): State<Arc<Server>>) -> impl IntoResponse
 
 async fn route_get_map(
     State(server): State<Arc<Server>>,
-    auth: Option<TypedHeader<Authorization<Bearer>>>,
+    auth: BearerAuth,
     Path(map): Path<String>,
 ) -> impl IntoResponse {
-    ensure_access_authorized(&auth, &map, &server)?;
+    ensure_access_authorized(&auth, &map, &server, Role::Viewer)?;
     server.get_map(&map)
 }
 
@@ -286,31 +447,215 @@ async fn route_post_map(
 
 async fn route_delete_map(
     State(server): State<Arc<Server>>,
-    auth: Option<TypedHeader<Authorization<Bearer>>>,
+    auth: BearerAuth,
     Path(map): Path<String>,
 ) -> impl IntoResponse {
-    ensure_access_authorized(&auth, &map, &server)?;
+    ensure_access_authorized(&auth, &map, &server, Role::Owner)?;
     server.delete_map(&map)
 }
 
 async fn route_get_images(
     State(server): State<Arc<Server>>,
-    auth: Option<TypedHeader<Authorization<Bearer>>>,
+    auth: BearerAuth,
     Path(map): Path<String>,
 ) -> impl IntoResponse {
-    ensure_access_authorized(&auth, &map, &server)?;
+    ensure_access_authorized(&auth, &map, &server, Role::Viewer)?;
     server.get_images(&map).map(Json)
 }
 
 async fn route_get_image(
     State(server): State<Arc<Server>>,
-    auth: Option<TypedHeader<Authorization<Bearer>>>,
+    auth: BearerAuth,
     Path((map, image)): Path<(String, u16)>,
 ) -> impl IntoResponse {
-    ensure_access_authorized(&auth, &map, &server)?;
+    ensure_access_authorized(&auth, &map, &server, Role::Viewer)?;
     server.get_image(&map, image)
 }
 
+async fn route_put_image(
+    State(server): State<Arc<Server>>,
+    auth: BearerAuth,
+    Path((map, image_name)): Path<(String, String)>,
+    Json(image): Json<Image>,
+) -> impl IntoResponse {
+    apply_route_request(
+        &auth,
+        &map,
+        &server,
+        Request::Create(CreateReq::Image(image_name, image)),
+    )
+    .map(|_| ())
+}
+
+async fn route_delete_image(
+    State(server): State<Arc<Server>>,
+    auth: BearerAuth,
+    Path((map, image)): Path<(String, u16)>,
+) -> impl IntoResponse {
+    apply_route_request(
+        &auth,
+        &map,
+        &server,
+        Request::Delete(DeleteReq::Image(image)),
+    )
+    .map(|_| ())
+}
+
+async fn route_get_sounds(
+    State(server): State<Arc<Server>>,
+    auth: BearerAuth,
+    Path(map): Path<String>,
+) -> impl IntoResponse {
+    ensure_access_authorized(&auth, &map, &server, Role::Viewer)?;
+    server.get_sounds(&map).map(Json)
+}
+
+async fn route_get_sound(
+    State(server): State<Arc<Server>>,
+    auth: BearerAuth,
+    Path((map, sound)): Path<(String, u16)>,
+) -> impl IntoResponse {
+    ensure_access_authorized(&auth, &map, &server, Role::Viewer)?;
+    server.get_sound(&map, sound)
+}
+
+async fn route_put_sound(
+    State(server): State<Arc<Server>>,
+    auth: BearerAuth,
+    Path((map, sound_name)): Path<(String, String)>,
+    file: Bytes,
+) -> impl IntoResponse {
+    apply_route_request(
+        &auth,
+        &map,
+        &server,
+        Request::Create(CreateReq::Sound(sound_name, Base64(file.to_vec()))),
+    )
+    .map(|_| ())
+}
+
+async fn route_delete_sound(
+    State(server): State<Arc<Server>>,
+    auth: BearerAuth,
+    Path((map, sound)): Path<(String, u16)>,
+) -> impl IntoResponse {
+    apply_route_request(
+        &auth,
+        &map,
+        &server,
+        Request::Delete(DeleteReq::Sound(sound)),
+    )
+    .map(|_| ())
+}
+
+async fn route_get_automappers(
+    State(server): State<Arc<Server>>,
+    auth: BearerAuth,
+    Path(map): Path<String>,
+) -> impl IntoResponse {
+    ensure_access_authorized(&auth, &map, &server, Role::Viewer)?;
+    server.get_automappers(&map).map(Json)
+}
+
+async fn route_get_automapper(
+    State(server): State<Arc<Server>>,
+    auth: BearerAuth,
+    Path((map, am)): Path<(String, String)>,
+) -> impl IntoResponse {
+    ensure_access_authorized(&auth, &map, &server, Role::Viewer)?;
+    server.get_automapper(&map, &am)
+}
+
+async fn route_put_automapper(
+    State(server): State<Arc<Server>>,
+    auth: BearerAuth,
+    Path((map, am)): Path<(String, String)>,
+    file: String,
+) -> impl IntoResponse {
+    let req = Request::Create(CreateReq::Automapper(am, file));
+    match apply_route_request(&auth, &map, &server, req)? {
+        Response::AutomapperDiagnostics(diagnostics) => Ok(Json(diagnostics)),
+        _ => Err(Error::Internal("unexpected response".into())),
+    }
+}
+
+async fn route_delete_automapper(
+    State(server): State<Arc<Server>>,
+    auth: BearerAuth,
+    Path((map, am)): Path<(String, String)>,
+) -> impl IntoResponse {
+    apply_route_request(
+        &auth,
+        &map,
+        &server,
+        Request::Delete(DeleteReq::Automapper(am)),
+    )
+    .map(|_| ())
+}
+
+async fn route_get_users(
+    State(server): State<Arc<Server>>,
+    auth: BearerAuth,
+    Path(map): Path<String>,
+) -> impl IntoResponse {
+    ensure_access_authorized(&auth, &map, &server, Role::Viewer)?;
+    server.get_user_list(&map).map(Json)
+}
+
+async fn route_get_cursors(
+    State(server): State<Arc<Server>>,
+    auth: BearerAuth,
+    Path(map): Path<String>,
+) -> impl IntoResponse {
+    ensure_access_authorized(&auth, &map, &server, Role::Viewer)?;
+    server.get_cursors(&map, None).map(Json)
+}
+
+async fn route_get_backups(
+    State(server): State<Arc<Server>>,
+    auth: BearerAuth,
+    Path(map): Path<String>,
+) -> impl IntoResponse {
+    ensure_access_authorized(&auth, &map, &server, Role::Viewer)?;
+    server.get_backups(&map).map(Json)
+}
+
+async fn route_get_comments(
+    State(server): State<Arc<Server>>,
+    auth: BearerAuth,
+    Path(map): Path<String>,
+) -> impl IntoResponse {
+    ensure_access_authorized(&auth, &map, &server, Role::Viewer)?;
+    server.get_comments(&map).map(Json)
+}
+
+async fn route_get_locks(
+    State(server): State<Arc<Server>>,
+    auth: BearerAuth,
+    Path(map): Path<String>,
+) -> impl IntoResponse {
+    ensure_access_authorized(&auth, &map, &server, Role::Viewer)?;
+    server.get_locks(&map).map(Json)
+}
+
+async fn route_get_invites(
+    State(server): State<Arc<Server>>,
+    auth: BearerAuth,
+    Path(map): Path<String>,
+) -> impl IntoResponse {
+    ensure_access_authorized(&auth, &map, &server, Role::Owner)?;
+    server.get_invites(&map).map(Json)
+}
+
+async fn route_move(
+    State(server): State<Arc<Server>>,
+    auth: BearerAuth,
+    Path(map): Path<String>,
+    Json(req): Json<MoveReq>,
+) -> impl IntoResponse {
+    apply_route_request(&auth, &map, &server, Request::Move(req)).map(|_| ())
+}
+
 async fn route_get_config(
     State(server): State<Arc<Server>>,
     Path(map): Path<String>,
@@ -320,142 +665,644 @@ async fn route_get_config(
 
 async fn route_post_config(
     State(server): State<Arc<Server>>,
-    auth: Option<TypedHeader<Authorization<Bearer>>>,
+    auth: BearerAuth,
     Path(map): Path<String>,
     Json(part_config): Json<PartialConfig>,
 ) -> impl IntoResponse {
-    ensure_access_authorized(&auth, &map, &server)?;
-    server.edit_config(&map, part_config)
+    apply_route_request(
+        &auth,
+        &map,
+        &server,
+        Request::Edit(EditReq::Config(Box::new(part_config))),
+    )
+    .map(|_| ())
 }
 
 async fn route_get_info(
     State(server): State<Arc<Server>>,
-    auth: Option<TypedHeader<Authorization<Bearer>>>,
+    auth: BearerAuth,
     Path(map): Path<String>,
 ) -> impl IntoResponse {
-    ensure_access_authorized(&auth, &map, &server)?;
+    ensure_access_authorized(&auth, &map, &server, Role::Viewer)?;
     server.get_info(&map).map(Json)
 }
 
+async fn route_get_check(
+    State(server): State<Arc<Server>>,
+    auth: BearerAuth,
+    Path(map): Path<String>,
+) -> impl IntoResponse {
+    ensure_access_authorized(&auth, &map, &server, Role::Viewer)?;
+    server.get_check(&map).map(Json)
+}
+
+async fn route_get_lints(
+    State(server): State<Arc<Server>>,
+    auth: BearerAuth,
+    Path(map): Path<String>,
+) -> impl IntoResponse {
+    ensure_access_authorized(&auth, &map, &server, Role::Viewer)?;
+    server.get_lints(&map).map(Json)
+}
+
+async fn route_get_reachability(
+    State(server): State<Arc<Server>>,
+    auth: BearerAuth,
+    Path(map): Path<String>,
+) -> impl IntoResponse {
+    ensure_access_authorized(&auth, &map, &server, Role::Viewer)?;
+    server.get_reachability(&map).map(Json)
+}
+
 async fn route_post_info(
     State(server): State<Arc<Server>>,
-    auth: Option<TypedHeader<Authorization<Bearer>>>,
+    auth: BearerAuth,
     Path(map): Path<String>,
     Json(part_info): Json<PartialInfo>,
 ) -> impl IntoResponse {
-    ensure_access_authorized(&auth, &map, &server)?;
-    server.edit_info(&map, part_info)
+    apply_route_request(
+        &auth,
+        &map,
+        &server,
+        Request::Edit(EditReq::Info(Box::new(part_info))),
+    )
+    .map(|_| ())
 }
 
 async fn route_get_envelopes(
     State(server): State<Arc<Server>>,
-    auth: Option<TypedHeader<Authorization<Bearer>>>,
+    auth: BearerAuth,
     Path(map): Path<String>,
 ) -> impl IntoResponse {
-    ensure_access_authorized(&auth, &map, &server)?;
+    ensure_access_authorized(&auth, &map, &server, Role::Viewer)?;
     server.get_envelopes(&map).map(Json)
 }
 
 async fn route_put_envelope(
     State(server): State<Arc<Server>>,
-    auth: Option<TypedHeader<Authorization<Bearer>>>,
+    auth: BearerAuth,
     Path(map): Path<String>,
     Json(part_env): Json<PartialEnvelope>,
 ) -> impl IntoResponse {
-    ensure_access_authorized(&auth, &map, &server)?;
-    server.put_envelope(&map, part_env)
+    apply_route_request(
+        &auth,
+        &map,
+        &server,
+        Request::Create(CreateReq::Envelope(Box::new(part_env))),
+    )
+    .map(|_| ())
 }
 
 async fn route_get_envelope(
     State(server): State<Arc<Server>>,
-    auth: Option<TypedHeader<Authorization<Bearer>>>,
+    auth: BearerAuth,
     Path((map, env)): Path<(String, u16)>,
 ) -> impl IntoResponse {
-    ensure_access_authorized(&auth, &map, &server)?;
+    ensure_access_authorized(&auth, &map, &server, Role::Viewer)?;
     server.get_envelope(&map, env).map(Json)
 }
 
 async fn route_post_envelope(
     State(server): State<Arc<Server>>,
-    auth: Option<TypedHeader<Authorization<Bearer>>>,
+    auth: BearerAuth,
     Path((map, env)): Path<(String, u16)>,
     Json(part_env): Json<PartialEnvelope>,
 ) -> impl IntoResponse {
-    ensure_access_authorized(&auth, &map, &server)?;
-    server.edit_envelope(&map, env, part_env)
+    apply_route_request(
+        &auth,
+        &map,
+        &server,
+        Request::Edit(EditReq::Envelope(env, Box::new(part_env))),
+    )
+    .map(|_| ())
 }
 
 async fn route_delete_envelope(
     State(server): State<Arc<Server>>,
-    auth: Option<TypedHeader<Authorization<Bearer>>>,
+    auth: BearerAuth,
     Path((map, env)): Path<(String, u16)>,
 ) -> impl IntoResponse {
-    ensure_access_authorized(&auth, &map, &server)?;
-    server.delete_envelope(&map, env)
+    apply_route_request(
+        &auth,
+        &map,
+        &server,
+        Request::Delete(DeleteReq::Envelope(env)),
+    )
+    .map(|_| ())
 }
 
 async fn route_get_groups(
     State(server): State<Arc<Server>>,
-    auth: Option<TypedHeader<Authorization<Bearer>>>,
+    auth: BearerAuth,
     Path(map): Path<String>,
 ) -> impl IntoResponse {
-    ensure_access_authorized(&auth, &map, &server)?;
+    ensure_access_authorized(&auth, &map, &server, Role::Viewer)?;
     server.get_groups(&map).map(Json)
 }
 
+async fn route_get_group(
+    State(server): State<Arc<Server>>,
+    auth: BearerAuth,
+    Path((map, group)): Path<(String, u16)>,
+) -> impl IntoResponse {
+    ensure_access_authorized(&auth, &map, &server, Role::Viewer)?;
+    server.get_group(&map, group).map(Json)
+}
+
 async fn route_post_group(
     State(server): State<Arc<Server>>,
-    auth: Option<TypedHeader<Authorization<Bearer>>>,
+    auth: BearerAuth,
     Path((map, group)): Path<(String, u16)>,
     Json(part_group): Json<PartialGroup>,
 ) -> impl IntoResponse {
-    ensure_access_authorized(&auth, &map, &server)?;
-    server.edit_group(&map, group, part_group)
+    apply_route_request(
+        &auth,
+        &map,
+        &server,
+        Request::Edit(EditReq::Group(group, Box::new(part_group))),
+    )
+    .map(|_| ())
 }
 
 async fn route_put_group(
     State(server): State<Arc<Server>>,
-    auth: Option<TypedHeader<Authorization<Bearer>>>,
+    auth: BearerAuth,
     Path(map): Path<String>,
     Json(part_group): Json<PartialGroup>,
 ) -> impl IntoResponse {
-    ensure_access_authorized(&auth, &map, &server)?;
-    server.put_group(&map, part_group)
+    apply_route_request(
+        &auth,
+        &map,
+        &server,
+        Request::Create(CreateReq::Group(Box::new(part_group))),
+    )
+    .map(|_| ())
 }
 
 async fn route_delete_group(
     State(server): State<Arc<Server>>,
-    auth: Option<TypedHeader<Authorization<Bearer>>>,
+    auth: BearerAuth,
     Path((map, group)): Path<(String, u16)>,
 ) -> impl IntoResponse {
-    ensure_access_authorized(&auth, &map, &server)?;
-    server.delete_group(&map, group)
+    apply_route_request(
+        &auth,
+        &map,
+        &server,
+        Request::Delete(DeleteReq::Group(group)),
+    )
+    .map(|_| ())
 }
 
 async fn route_get_layers(
     State(server): State<Arc<Server>>,
-    auth: Option<TypedHeader<Authorization<Bearer>>>,
+    auth: BearerAuth,
     Path((map, group)): Path<(String, u16)>,
 ) -> impl IntoResponse {
-    ensure_access_authorized(&auth, &map, &server)?;
+    ensure_access_authorized(&auth, &map, &server, Role::Viewer)?;
     server.get_layers(&map, group).map(Json)
 }
 
+/// Query parameters of the tiles route. Without a rectangle, the whole layer is returned.
+#[derive(Deserialize)]
+struct TilesQuery {
+    x: Option<u32>,
+    y: Option<u32>,
+    w: Option<u32>,
+    h: Option<u32>,
+    #[serde(default)]
+    compression: Compression,
+}
+
+impl TilesQuery {
+    fn rect(&self) -> Result<Option<vek::Rect<u32, u32>>, Error> {
+        match (self.x, self.y, self.w, self.h) {
+            (Some(x), Some(y), Some(w), Some(h)) => Ok(Some(vek::Rect::new(x, y, w, h))),
+            (None, None, None, None) => Ok(None),
+            _ => Err(Error::BadRequest(
+                "expected all or none of x, y, w, h".to_owned(),
+            )),
+        }
+    }
+}
+
+async fn route_get_tiles(
+    State(server): State<Arc<Server>>,
+    auth: BearerAuth,
+    Path((map, group, layer)): Path<(String, u16, u16)>,
+    Query(query): Query<TilesQuery>,
+) -> impl IntoResponse {
+    ensure_access_authorized(&auth, &map, &server, Role::Viewer)?;
+    server
+        .get_tiles(&map, group, layer, query.rect()?, query.compression)
+        .map(Vec::from)
+}
+
+async fn route_post_tiles(
+    State(server): State<Arc<Server>>,
+    auth: BearerAuth,
+    Path((map, group, layer)): Path<(String, u16, u16)>,
+    Json(tiles): Json<Tiles>,
+) -> impl IntoResponse {
+    apply_route_request(
+        &auth,
+        &map,
+        &server,
+        Request::Edit(EditReq::Tiles(group, layer, Box::new(tiles))),
+    )
+    .map(|_| ())
+}
+
+async fn route_post_automap(
+    State(server): State<Arc<Server>>,
+    auth: BearerAuth,
+    Path((map, group, layer)): Path<(String, u16, u16)>,
+) -> impl IntoResponse {
+    apply_route_request(
+        &auth,
+        &map,
+        &server,
+        Request::Edit(EditReq::Automap(group, layer)),
+    )
+    .map(|_| ())
+}
+
+async fn route_get_layer(
+    State(server): State<Arc<Server>>,
+    auth: BearerAuth,
+    Path((map, group, layer)): Path<(String, u16, u16)>,
+) -> impl IntoResponse {
+    ensure_access_authorized(&auth, &map, &server, Role::Viewer)?;
+    server.get_layer(&map, group, layer).map(Json)
+}
+
+async fn route_post_layer(
+    State(server): State<Arc<Server>>,
+    auth: BearerAuth,
+    Path((map, group, layer)): Path<(String, u16, u16)>,
+    Json(part_layer): Json<PartialLayer>,
+) -> impl IntoResponse {
+    apply_route_request(
+        &auth,
+        &map,
+        &server,
+        Request::Edit(EditReq::Layer(group, layer, Box::new(part_layer))),
+    )
+    .map(|_| ())
+}
+
 async fn route_put_layer(
     State(server): State<Arc<Server>>,
-    auth: Option<TypedHeader<Authorization<Bearer>>>,
+    auth: BearerAuth,
     Path((map, group)): Path<(String, u16)>,
     Json(part_layer): Json<PartialLayer>,
 ) -> impl IntoResponse {
-    ensure_access_authorized(&auth, &map, &server)?;
-    server.put_layer(&map, group, part_layer)
+    apply_route_request(
+        &auth,
+        &map,
+        &server,
+        Request::Create(CreateReq::Layer(group, Box::new(part_layer))),
+    )
+    .map(|_| ())
 }
 
 async fn route_delete_layer(
     State(server): State<Arc<Server>>,
-    auth: Option<TypedHeader<Authorization<Bearer>>>,
+    auth: BearerAuth,
+    Path((map, group, layer)): Path<(String, u16, u16)>,
+) -> impl IntoResponse {
+    apply_route_request(
+        &auth,
+        &map,
+        &server,
+        Request::Delete(DeleteReq::Layer(group, layer)),
+    )
+    .map(|_| ())
+}
+
+// quads and sound sources are (de)serialized like in the websocket protocol.
+#[derive(Serialize, Deserialize)]
+#[serde(transparent)]
+struct QuadJson(#[serde(with = "SerdeQuad")] twmap::Quad);
+
+#[derive(Serialize, Deserialize)]
+#[serde(transparent)]
+struct SourceJson(#[serde(with = "SerdeSoundSource")] twmap::SoundSource);
+
+async fn route_get_quad(
+    State(server): State<Arc<Server>>,
+    auth: BearerAuth,
+    Path((map, group, layer, quad)): Path<(String, u16, u16, u16)>,
+) -> impl IntoResponse {
+    ensure_access_authorized(&auth, &map, &server, Role::Viewer)?;
+    server
+        .get_quad(&map, group, layer, quad)
+        .map(|quad| Json(QuadJson(quad)))
+}
+
+async fn route_put_quad(
+    State(server): State<Arc<Server>>,
+    auth: BearerAuth,
+    Path((map, group, layer)): Path<(String, u16, u16)>,
+    Json(QuadJson(quad)): Json<QuadJson>,
+) -> impl IntoResponse {
+    apply_route_request(
+        &auth,
+        &map,
+        &server,
+        Request::Create(CreateReq::Quad(group, layer, Box::new(quad))),
+    )
+    .map(|_| ())
+}
+
+async fn route_post_quad(
+    State(server): State<Arc<Server>>,
+    auth: BearerAuth,
+    Path((map, group, layer, quad_index)): Path<(String, u16, u16, u16)>,
+    Json(QuadJson(quad)): Json<QuadJson>,
+) -> impl IntoResponse {
+    apply_route_request(
+        &auth,
+        &map,
+        &server,
+        Request::Edit(EditReq::Quad(group, layer, quad_index, Box::new(quad))),
+    )
+    .map(|_| ())
+}
+
+async fn route_delete_quad(
+    State(server): State<Arc<Server>>,
+    auth: BearerAuth,
+    Path((map, group, layer, quad)): Path<(String, u16, u16, u16)>,
+) -> impl IntoResponse {
+    apply_route_request(
+        &auth,
+        &map,
+        &server,
+        Request::Delete(DeleteReq::Quad(group, layer, quad)),
+    )
+    .map(|_| ())
+}
+
+async fn route_get_source(
+    State(server): State<Arc<Server>>,
+    auth: BearerAuth,
+    Path((map, group, layer, source)): Path<(String, u16, u16, u16)>,
+) -> impl IntoResponse {
+    ensure_access_authorized(&auth, &map, &server, Role::Viewer)?;
+    server
+        .get_source(&map, group, layer, source)
+        .map(|source| Json(SourceJson(source)))
+}
+
+async fn route_put_source(
+    State(server): State<Arc<Server>>,
+    auth: BearerAuth,
     Path((map, group, layer)): Path<(String, u16, u16)>,
+    Json(SourceJson(source)): Json<SourceJson>,
+) -> impl IntoResponse {
+    apply_route_request(
+        &auth,
+        &map,
+        &server,
+        Request::Create(CreateReq::Source(group, layer, Box::new(source))),
+    )
+    .map(|_| ())
+}
+
+async fn route_post_source(
+    State(server): State<Arc<Server>>,
+    auth: BearerAuth,
+    Path((map, group, layer, source_index)): Path<(String, u16, u16, u16)>,
+    Json(SourceJson(source)): Json<SourceJson>,
 ) -> impl IntoResponse {
-    ensure_access_authorized(&auth, &map, &server)?;
-    server.delete_layer(&map, group, layer)
+    apply_route_request(
+        &auth,
+        &map,
+        &server,
+        Request::Edit(EditReq::Source(
+            group,
+            layer,
+            source_index,
+            Box::new(source),
+        )),
+    )
+    .map(|_| ())
+}
+
+async fn route_delete_source(
+    State(server): State<Arc<Server>>,
+    auth: BearerAuth,
+    Path((map, group, layer, source)): Path<(String, u16, u16, u16)>,
+) -> impl IntoResponse {
+    apply_route_request(
+        &auth,
+        &map,
+        &server,
+        Request::Delete(DeleteReq::Source(group, layer, source)),
+    )
+    .map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use serde_json::json;
+
+    use super::*;
+    use crate::testing::{TestServer, MAP};
+
+    fn group(name: &str) -> Option<serde_json::Value> {
+        Some(json!({ "name": name }))
+    }
+
+    #[tokio::test]
+    async fn rest_status_codes() {
+        let server = TestServer::new("rest-status-codes");
+        let get = |uri| server.rest(Method::GET, uri, None, None);
+        assert_eq!(get("/maps/test/groups/0").await.0, StatusCode::OK);
+        assert_eq!(get("/maps/nope/groups/0").await.0, StatusCode::NOT_FOUND);
+        assert_eq!(get("/maps/test/groups/9").await.0, StatusCode::NOT_FOUND);
+        assert_eq!(get("/maps/test/groups/x").await.0, StatusCode::BAD_REQUEST);
+        assert_eq!(get("/maps/test/nope").await.0, StatusCode::NOT_FOUND);
+
+        let (status, _) = server
+            .rest(Method::DELETE, "/maps/test/groups", None, None)
+            .await;
+        assert_eq!(status, StatusCode::METHOD_NOT_ALLOWED);
+
+        let (status, _) = server
+            .rest(Method::PUT, "/maps/test/groups", None, group("rest"))
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(server.map().groups[1].name, "rest");
+
+        let body = Some(json!({ "name": 5 }));
+        let (status, _) = server
+            .rest(Method::PUT, "/maps/test/groups", None, body)
+            .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+        let tiles = Some(json!({ "x": 7, "y": 7, "w": 2, "h": 2, "tiles": "" }));
+        let uri = "/maps/test/groups/0/layers/0/tiles";
+        let (status, _) = server.rest(Method::POST, uri, None, tiles).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, body) = server
+            .rest(Method::DELETE, "/maps/test/groups/0", None, None)
+            .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body, Error::DeletePhysicsGroup.to_string());
+        assert_eq!(server.map().groups.len(), 2);
+    }
+
+    async fn status(
+        server: &TestServer,
+        method: Method,
+        uri: &str,
+        token: Option<&str>,
+        body: Option<serde_json::Value>,
+    ) -> StatusCode {
+        server.rest(method, uri, token, body).await.0
+    }
+
+    #[tokio::test]
+    async fn rest_roles() {
+        let server = TestServer::with_args("rest-roles", &["--admin-password", "admin"]);
+        server.set_passwords("view", "edit", "own");
+        let viewer = server.join(Some("view")).unwrap();
+        let editor = server.join(Some("edit")).unwrap();
+        let owner = server.join(Some("own")).unwrap();
+
+        let groups = "/maps/test/groups";
+        let invites = "/maps/test/invites";
+
+        assert_eq!(
+            status(&server, Method::GET, groups, None, None).await,
+            StatusCode::UNAUTHORIZED
+        );
+        let token = Some(viewer.token.as_str());
+        assert_eq!(
+            status(&server, Method::GET, groups, token, None).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(&server, Method::PUT, groups, token, group("viewer")).await,
+            StatusCode::FORBIDDEN
+        );
+
+        let token = Some(editor.token.as_str());
+        assert_eq!(
+            status(&server, Method::PUT, groups, token, group("editor")).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(&server, Method::GET, invites, token, None).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            status(&server, Method::DELETE, "/maps/test", token, None).await,
+            StatusCode::FORBIDDEN
+        );
+
+        let token = Some(owner.token.as_str());
+        assert_eq!(
+            status(&server, Method::GET, invites, token, None).await,
+            StatusCode::OK
+        );
+
+        // invite tokens grant the role of the invite.
+        let req = Request::CreateInvite(InviteReq {
+            role: Role::Editor,
+            expires_in: None,
+            max_uses: None,
+        });
+        let Ok(Response::Invite(invite)) = server.request(&owner, req) else {
+            panic!("expected an invite");
+        };
+        let token = Some(invite.token.as_str());
+        assert_eq!(
+            status(&server, Method::PUT, groups, token, group("invite")).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(&server, Method::GET, invites, token, None).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            server
+                .map()
+                .groups
+                .iter()
+                .map(|g| &g.name[..])
+                .collect::<Vec<_>>(),
+            ["Game", "editor", "invite"]
+        );
+
+        let token = Some("admin");
+        assert_eq!(
+            status(&server, Method::DELETE, "/maps/test", token, None).await,
+            StatusCode::OK
+        );
+        assert!(server.server.room(MAP).is_err());
+    }
+
+    #[tokio::test]
+    async fn rest_rejects_invalid_tokens() {
+        let server = TestServer::new("rest-rejects-invalid-tokens");
+        let groups = "/maps/test/groups";
+
+        // the map is open, but a token must be valid.
+        let (status, _) = server.rest(Method::GET, groups, None, None).await;
+        assert_eq!(status, StatusCode::OK);
+        for token in ["invalid", "invalid.invite"] {
+            let (status, _) = server.rest(Method::GET, groups, Some(token), None).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED, "{token}");
+        }
+    }
+
+    #[tokio::test]
+    async fn rest_tiles_region() {
+        let server = TestServer::new("rest-tiles-region");
+        let uri = "/maps/test/groups/0/layers/0/tiles";
+
+        let tiles = Some(json!({ "x": 6, "y": 7, "w": 2, "h": 1, "tiles": "AQAAAAIAAAA=" }));
+        let (status, _) = server.rest(Method::POST, uri, None, tiles).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let region = format!("{uri}?x=5&y=7&w=3&h=1");
+        let (status, body) = server.rest(Method::GET, &region, None, None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(&body[..], [0, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0]);
+
+        let (status, body) = server.rest(Method::GET, uri, None, None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body.len(), 8 * 8 * 4);
+
+        for (query, err) in [
+            (
+                "x=5&y=7&w=3",
+                "bad request: expected all or none of x, y, w, h",
+            ),
+            ("x=6&y=7&w=3&h=1", "tiles out of layer bounds"),
+            ("x=0&y=8&w=1&h=1", "tiles out of layer bounds"),
+        ] {
+            let region = format!("{uri}?{query}");
+            let (status, body) = server.rest(Method::GET, &region, None, None).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{query}");
+            assert_eq!(body, err, "{query}");
+        }
+    }
+
+    #[test]
+    fn rest_rejects_batches() {
+        let server = TestServer::new("rest-rejects-batches");
+        let auth = BearerAuth {
+            token: None,
+            ip: None,
+        };
+        let batch = Request::Batch(vec![Request::Create(CreateReq::Group(Box::default()))]);
+        let res = apply_route_request(&auth, MAP, &server.server, batch);
+        assert!(matches!(res, Err(Error::BadRequest(_))), "{res:?}");
+        assert_eq!(server.map().groups.len(), 1);
+    }
 }
@@ -0,0 +1,1251 @@
+//! Machine-readable description of the protocol: a JSON Schema of the websocket
+//! packets and an OpenAPI document of the http routes. Both are generated from the
+//! types in protocol.rs. Types from twmap and vek do not implement `JsonSchema`,
+//! the structs in this module mirror how they are serialized.
+
+// The mirror types are never constructed, they only exist for their `JsonSchema`
+// implementation, hence their `dead_code` allowance. The `packets_match_json_schema`
+// test checks that they still match the serialization of twmap and vek.
+
+use std::borrow::Cow;
+
+use schemars::{generate::SchemaSettings, json_schema, JsonSchema, Schema, SchemaGenerator};
+use serde_json::{json, Map, Value};
+
+use crate::protocol::*;
+
+// FIXED-POINT NUMBERS
+
+/// Fixed-point numbers (positions, colors, angles...) are serialized as strings,
+/// e.g. `"1.5"`.
+#[derive(Clone, Copy, Default)]
+pub struct Fixed;
+
+impl JsonSchema for Fixed {
+    fn schema_name() -> Cow<'static, str> {
+        "Fixed".into()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        json_schema!({ "type": "string", "format": "decimal" })
+    }
+}
+
+// VEK
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, Default, JsonSchema)]
+#[schemars(rename = "Vec2_for_{T}")]
+pub struct Vec2<T> {
+    x: T,
+    y: T,
+}
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, JsonSchema)]
+#[schemars(rename = "Rgb_for_{T}")]
+pub struct Rgb<T> {
+    r: T,
+    g: T,
+    b: T,
+}
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, Default, JsonSchema)]
+#[schemars(rename = "Rgba_for_{T}")]
+pub struct Rgba<T> {
+    r: T,
+    g: T,
+    b: T,
+    a: T,
+}
+
+#[allow(dead_code)]
+#[derive(JsonSchema)]
+#[schemars(rename = "Uv_for_{T}")]
+pub struct Uv<T> {
+    u: T,
+    v: T,
+}
+
+#[allow(dead_code)]
+#[derive(JsonSchema)]
+#[schemars(rename = "Extent2_for_{T}")]
+pub struct Extent2<T> {
+    w: T,
+    h: T,
+}
+
+#[allow(dead_code)]
+#[derive(JsonSchema)]
+#[schemars(rename = "Rect_for_{P}_and_{E}")]
+pub struct Rect<P, E> {
+    x: P,
+    y: P,
+    w: E,
+    h: E,
+}
+
+#[allow(dead_code)]
+#[derive(JsonSchema)]
+#[schemars(rename = "Disk_for_{P}_and_{E}")]
+pub struct Disk<P, E> {
+    center: Vec2<P>,
+    radius: E,
+}
+
+// TWMAP
+
+#[allow(dead_code)]
+#[derive(JsonSchema)]
+pub struct Info {
+    author: String,
+    version: String,
+    credits: String,
+    license: String,
+    settings: Vec<String>,
+}
+
+#[allow(dead_code)]
+#[derive(JsonSchema)]
+pub struct AutomapperConfig {
+    config: Option<u16>,
+    seed: u32,
+    automatic: bool,
+}
+
+#[allow(dead_code)]
+#[derive(JsonSchema)]
+#[schemars(rename = "BezierCurve_for_{T}")]
+pub struct BezierCurve<T> {
+    handle_l: Vec2<T>,
+    handle_r: Vec2<T>,
+}
+
+// twmap also has an Unknown(i32) curve, but it fails the map checks.
+#[allow(dead_code)]
+#[derive(JsonSchema)]
+#[serde(rename_all = "snake_case", tag = "type")]
+#[schemars(rename = "CurveKind_for_{T}")]
+pub enum CurveKind<T> {
+    Step,
+    Linear,
+    Slow,
+    Fast,
+    Smooth,
+    Bezier(BezierCurve<T>),
+}
+
+#[allow(dead_code)]
+#[derive(JsonSchema)]
+#[schemars(rename = "EnvPoint_for_{T}")]
+pub struct EnvPoint<T> {
+    time: i32,
+    content: T,
+    #[serde(flatten)]
+    curve: CurveKind<T>,
+}
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, Default, JsonSchema)]
+pub struct Position {
+    #[serde(flatten)]
+    offset: Vec2<Fixed>,
+    rotation: Fixed,
+}
+
+#[derive(Clone, Copy, Default, JsonSchema)]
+pub struct Volume(Fixed);
+
+#[allow(dead_code)]
+#[derive(JsonSchema)]
+#[schemars(rename = "Env_for_{T}")]
+pub struct Env<T> {
+    name: String,
+    synchronized: bool,
+    points: Vec<EnvPoint<T>>,
+}
+
+#[allow(dead_code)]
+#[derive(JsonSchema)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum Envelope {
+    Position(Env<Position>),
+    Color(Env<Rgba<Fixed>>),
+    Sound(Env<Volume>),
+}
+
+#[allow(dead_code)]
+#[derive(JsonSchema)]
+pub struct Group {
+    name: String,
+    offset: Vec2<Fixed>,
+    parallax: Vec2<i32>,
+    clipping: bool,
+    clip: Rect<Fixed, Fixed>,
+}
+
+#[allow(dead_code)]
+#[derive(JsonSchema)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum SoundArea {
+    Rectangle(Rect<Fixed, Fixed>),
+    Circle(Disk<Fixed, Fixed>),
+}
+
+/// Tiles of a layer sent with get/layer. The tiles are always empty, they are
+/// fetched with get/tiles.
+#[allow(dead_code)]
+#[derive(JsonSchema)]
+pub struct LayerTiles {
+    width: usize,
+    height: usize,
+    tiles: Vec<Value>,
+}
+
+// in the layers, envelopes, images and sounds are referred to by name.
+
+#[allow(dead_code)]
+#[derive(JsonSchema)]
+pub struct TilesLayer {
+    name: String,
+    detail: bool,
+    color: Rgba<u8>,
+    color_env: Option<String>,
+    color_env_offset: i32,
+    image: Option<String>,
+    #[serde(flatten)]
+    tiles: LayerTiles,
+    automapper_config: AutomapperConfig,
+}
+
+#[allow(dead_code)]
+#[derive(JsonSchema)]
+pub struct LayerQuad {
+    corners: [Vec2<Fixed>; 4],
+    position: Vec2<Fixed>,
+    colors: [Rgba<u8>; 4],
+    texture_coords: [Uv<Fixed>; 4],
+    position_env: Option<String>,
+    position_env_offset: i32,
+    color_env: Option<String>,
+    color_env_offset: i32,
+}
+
+#[allow(dead_code)]
+#[derive(JsonSchema)]
+pub struct QuadsLayer {
+    name: String,
+    detail: bool,
+    quads: Vec<LayerQuad>,
+    image: Option<String>,
+}
+
+#[allow(dead_code)]
+#[derive(JsonSchema)]
+pub struct LayerSoundSource {
+    area: SoundArea,
+    looping: bool,
+    panning: bool,
+    delay: i32,
+    falloff: u8,
+    position_env: Option<String>,
+    position_env_offset: i32,
+    sound_env: Option<String>,
+    sound_env_offset: i32,
+}
+
+#[allow(dead_code)]
+#[derive(JsonSchema)]
+pub struct SoundsLayer {
+    name: String,
+    detail: bool,
+    sources: Vec<LayerSoundSource>,
+    sound: Option<String>,
+}
+
+#[allow(dead_code)]
+#[derive(JsonSchema)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum Layer {
+    Game(LayerTiles),
+    Tiles(TilesLayer),
+    Quads(QuadsLayer),
+    Front(LayerTiles),
+    Tele(LayerTiles),
+    Speedup(LayerTiles),
+    Switch(LayerTiles),
+    Tune(LayerTiles),
+    Sounds(SoundsLayer),
+}
+
+// JSON SCHEMA
+
+/// JSON Schema of the packets sent on the websocket and to the `/http` route.
+/// Clients send `Packet<Request>`, the server sends `Packet<Message>`.
+pub fn json_schema() -> Schema {
+    SchemaGenerator::default().into_root_schema_for::<SendPacket>()
+}
+
+// OPENAPI
+
+enum Content {
+    Empty,
+    Json(fn(&mut SchemaGenerator) -> Schema),
+    Bytes,
+    Text,
+}
+
+fn json<T: JsonSchema>() -> Content {
+    Content::Json(|gen| gen.subschema_for::<T>())
+}
+
+struct OpenApi {
+    gen: SchemaGenerator,
+    paths: Map<String, Value>,
+}
+
+impl OpenApi {
+    fn content(&mut self, content: Content) -> Option<Value> {
+        match content {
+            Content::Empty => None,
+            Content::Json(schema) => {
+                Some(json!({ "application/json": { "schema": schema(&mut self.gen) } }))
+            }
+            Content::Bytes => Some(json!({
+                "application/octet-stream": { "schema": { "type": "string", "format": "binary" } }
+            })),
+            Content::Text => Some(json!({ "text/plain": { "schema": { "type": "string" } } })),
+        }
+    }
+
+    fn route(
+        &mut self,
+        method: &str,
+        path: &str,
+        summary: &str,
+        role: Option<Role>,
+        body: Content,
+        response: Content,
+    ) {
+        let parameters = path
+            .split('/')
+            .filter_map(|s| s.strip_prefix('{')?.strip_suffix('}'))
+            .map(|name| {
+                let schema = match name {
+                    "map" | "automapper" => json!({ "type": "string" }),
+                    // images and sounds are uploaded by name, the other routes use an index.
+                    "image" | "sound" => {
+                        json!({ "type": "string", "description": "index or name" })
+                    }
+                    _ => json!({ "type": "integer", "minimum": 0, "maximum": u16::MAX }),
+                };
+                json!({ "name": name, "in": "path", "required": true, "schema": schema })
+            })
+            .collect::<Vec<_>>();
+
+        let mut op = json!({
+            "summary": summary,
+            "parameters": parameters,
+            "responses": {
+                "200": { "description": "success" },
+                "default": {
+                    "description": "error, the status code depends on the error",
+                    "content": self.content(Content::Text),
+                },
+            },
+        });
+
+        if let Some(role) = role {
+            op["description"] = json!(format!(
+                "Requires the {} role in the map.",
+                serde_json::to_value(role).unwrap().as_str().unwrap()
+            ));
+            op["security"] = json!([{ "bearer": [] }]);
+        }
+        if let Some(content) = self.content(body) {
+            op["requestBody"] = json!({ "required": true, "content": content });
+        }
+        if let Some(content) = self.content(response) {
+            op["responses"]["200"]["content"] = content;
+        }
+
+        let item = self
+            .paths
+            .entry(path.to_owned())
+            .or_insert_with(|| json!({}))
+            .as_object_mut()
+            .unwrap();
+        item.insert(method.to_owned(), op);
+    }
+}
+
+/// OpenAPI 3.1 description of the http routes of router.rs. The bridge routes are
+/// not described. OpenAPI 3.0 is not used because it cannot describe tuples.
+pub fn openapi() -> Value {
+    use Content::{Bytes, Empty, Text};
+    use Role::*;
+
+    let mut api = OpenApi {
+        gen: SchemaSettings::draft2020_12()
+            .with(|s| s.definitions_path = "/components/schemas".into())
+            .into_generator(),
+        paths: Map::new(),
+    };
+
+    let map = "/maps/{map}";
+    let group = "/maps/{map}/groups/{group}";
+    let layer = "/maps/{map}/groups/{group}/layers/{layer}";
+
+    api.route(
+        "post",
+        "/http",
+        "Send a websocket request over http",
+        None,
+        json::<RecvPacket>(),
+        json::<SendPacket>(),
+    );
+    api.route(
+        "get",
+        "/schema.json",
+        "JSON Schema of the websocket packets",
+        None,
+        Empty,
+        json::<Value>(),
+    );
+    api.route(
+        "get",
+        "/openapi.json",
+        "This document",
+        None,
+        Empty,
+        json::<Value>(),
+    );
+    api.route(
+        "get",
+        "/maps",
+        "List the public maps",
+        None,
+        Empty,
+        json::<Vec<MapDetail>>(),
+    );
+
+    api.route(
+        "get",
+        map,
+        "Download the map file",
+        Some(Viewer),
+        Empty,
+        Bytes,
+    );
+    api.route("put", map, "Upload a map file", None, Bytes, Empty);
+    api.route(
+        "post",
+        map,
+        "Create a map",
+        None,
+        json::<MapCreation>(),
+        Empty,
+    );
+    api.route("delete", map, "Delete a map", Some(Owner), Empty, Empty);
+    api.route(
+        "get",
+        &format!("{map}/config"),
+        "Get the map config",
+        None,
+        Empty,
+        json::<Config>(),
+    );
+    api.route(
+        "post",
+        &format!("{map}/config"),
+        "Edit the map config",
+        Some(Owner),
+        json::<PartialConfig>(),
+        Empty,
+    );
+    api.route(
+        "get",
+        &format!("{map}/info"),
+        "Get the map info",
+        Some(Viewer),
+        Empty,
+        json::<Info>(),
+    );
+    api.route(
+        "post",
+        &format!("{map}/info"),
+        "Edit the map info",
+        Some(Editor),
+        json::<PartialInfo>(),
+        Empty,
+    );
+    api.route(
+        "get",
+        &format!("{map}/check"),
+        "Check the map",
+        Some(Viewer),
+        Empty,
+        json::<Vec<MapDiagnostic>>(),
+    );
+    api.route(
+        "get",
+        &format!("{map}/lints"),
+        "Find gameplay problems",
+        Some(Viewer),
+        Empty,
+        json::<Vec<MapLint>>(),
+    );
+    api.route(
+        "get",
+        &format!("{map}/reachability"),
+        "Find the tiles reachable from the spawns",
+        Some(Viewer),
+        Empty,
+        json::<Reachability>(),
+    );
+    api.route(
+        "get",
+        &format!("{map}/users"),
+        "List the users in the map",
+        Some(Viewer),
+        Empty,
+        json::<Vec<Presence>>(),
+    );
+    api.route(
+        "get",
+        &format!("{map}/cursors"),
+        "Get the cursors of the users in the map, by user id",
+        Some(Viewer),
+        Empty,
+        json::<std::collections::HashMap<String, Cursor>>(),
+    );
+    api.route(
+        "get",
+        &format!("{map}/backups"),
+        "List the backups",
+        Some(Viewer),
+        Empty,
+        json::<Vec<String>>(),
+    );
+    api.route(
+        "get",
+        &format!("{map}/comments"),
+        "List the comments",
+        Some(Viewer),
+        Empty,
+        json::<Vec<Comment>>(),
+    );
+    api.route(
+        "get",
+        &format!("{map}/locks"),
+        "List the locked layers",
+        Some(Viewer),
+        Empty,
+        json::<Vec<LayerLock>>(),
+    );
+    api.route(
+        "get",
+        &format!("{map}/invites"),
+        "List the invites",
+        Some(Owner),
+        Empty,
+        json::<Vec<Invite>>(),
+    );
+    api.route(
+        "post",
+        &format!("{map}/move"),
+        "Move an item of the map",
+        Some(Editor),
+        json::<MoveReq>(),
+        Empty,
+    );
+
+    api.route(
+        "get",
+        &format!("{map}/images"),
+        "List the images",
+        Some(Viewer),
+        Empty,
+        json::<Vec<String>>(),
+    );
+    api.route(
+        "get",
+        &format!("{map}/images/{{image}}"),
+        "Download an image",
+        Some(Viewer),
+        Empty,
+        Bytes,
+    );
+    api.route(
+        "put",
+        &format!("{map}/images/{{image}}"),
+        "Add an image",
+        Some(Editor),
+        json::<Image>(),
+        Empty,
+    );
+    api.route(
+        "delete",
+        &format!("{map}/images/{{image}}"),
+        "Delete an image",
+        Some(Editor),
+        Empty,
+        Empty,
+    );
+    api.route(
+        "get",
+        &format!("{map}/sounds"),
+        "List the sounds",
+        Some(Viewer),
+        Empty,
+        json::<Vec<String>>(),
+    );
+    api.route(
+        "get",
+        &format!("{map}/sounds/{{sound}}"),
+        "Download a sound",
+        Some(Viewer),
+        Empty,
+        Bytes,
+    );
+    api.route(
+        "put",
+        &format!("{map}/sounds/{{sound}}"),
+        "Add a sound",
+        Some(Editor),
+        Bytes,
+        Empty,
+    );
+    api.route(
+        "delete",
+        &format!("{map}/sounds/{{sound}}"),
+        "Delete a sound",
+        Some(Editor),
+        Empty,
+        Empty,
+    );
+    api.route(
+        "get",
+        &format!("{map}/automappers"),
+        "List the automappers",
+        Some(Viewer),
+        Empty,
+        json::<Vec<AutomapperDetail>>(),
+    );
+    api.route(
+        "get",
+        &format!("{map}/automappers/{{automapper}}"),
+        "Get an automapper file",
+        Some(Viewer),
+        Empty,
+        Text,
+    );
+    api.route(
+        "put",
+        &format!("{map}/automappers/{{automapper}}"),
+        "Add or replace an automapper file",
+        Some(Editor),
+        Text,
+        json::<Vec<AutomapperDiagnostic>>(),
+    );
+    api.route(
+        "delete",
+        &format!("{map}/automappers/{{automapper}}"),
+        "Delete an automapper",
+        Some(Editor),
+        Empty,
+        Empty,
+    );
+
+    api.route(
+        "get",
+        &format!("{map}/envelopes"),
+        "List the envelopes",
+        Some(Viewer),
+        Empty,
+        json::<Vec<String>>(),
+    );
+    api.route(
+        "put",
+        &format!("{map}/envelopes"),
+        "Add an envelope",
+        Some(Editor),
+        json::<PartialEnvelope>(),
+        Empty,
+    );
+    api.route(
+        "get",
+        &format!("{map}/envelopes/{{envelope}}"),
+        "Get an envelope",
+        Some(Viewer),
+        Empty,
+        json::<Envelope>(),
+    );
+    api.route(
+        "post",
+        &format!("{map}/envelopes/{{envelope}}"),
+        "Edit an envelope",
+        Some(Editor),
+        json::<PartialEnvelope>(),
+        Empty,
+    );
+    api.route(
+        "delete",
+        &format!("{map}/envelopes/{{envelope}}"),
+        "Delete an envelope",
+        Some(Editor),
+        Empty,
+        Empty,
+    );
+
+    api.route(
+        "get",
+        &format!("{map}/groups"),
+        "List the groups",
+        Some(Viewer),
+        Empty,
+        json::<Vec<String>>(),
+    );
+    api.route(
+        "put",
+        &format!("{map}/groups"),
+        "Add a group",
+        Some(Editor),
+        json::<PartialGroup>(),
+        Empty,
+    );
+    api.route(
+        "get",
+        group,
+        "Get a group",
+        Some(Viewer),
+        Empty,
+        json::<Group>(),
+    );
+    api.route(
+        "post",
+        group,
+        "Edit a group",
+        Some(Editor),
+        json::<PartialGroup>(),
+        Empty,
+    );
+    api.route(
+        "delete",
+        group,
+        "Delete a group",
+        Some(Editor),
+        Empty,
+        Empty,
+    );
+
+    api.route(
+        "get",
+        &format!("{group}/layers"),
+        "List the layers of a group",
+        Some(Viewer),
+        Empty,
+        json::<Vec<String>>(),
+    );
+    api.route(
+        "put",
+        &format!("{group}/layers"),
+        "Add a layer",
+        Some(Editor),
+        json::<PartialLayer>(),
+        Empty,
+    );
+    api.route(
+        "get",
+        layer,
+        "Get a layer, without its tiles",
+        Some(Viewer),
+        Empty,
+        json::<Layer>(),
+    );
+    api.route(
+        "post",
+        layer,
+        "Edit a layer",
+        Some(Editor),
+        json::<PartialLayer>(),
+        Empty,
+    );
+    api.route(
+        "delete",
+        layer,
+        "Delete a layer",
+        Some(Editor),
+        Empty,
+        Empty,
+    );
+    api.route(
+        "get",
+        &format!("{layer}/tiles"),
+        "Download the tiles of a layer",
+        Some(Viewer),
+        Empty,
+        Bytes,
+    );
+    api.route(
+        "post",
+        &format!("{layer}/tiles"),
+        "Edit the tiles of a layer",
+        Some(Editor),
+        json::<Tiles>(),
+        Empty,
+    );
+    api.route(
+        "post",
+        &format!("{layer}/automap"),
+        "Run the automapper of a layer",
+        Some(Editor),
+        Empty,
+        Empty,
+    );
+    api.route(
+        "put",
+        &format!("{layer}/quads"),
+        "Add a quad",
+        Some(Editor),
+        json::<SerdeQuad>(),
+        Empty,
+    );
+    api.route(
+        "get",
+        &format!("{layer}/quads/{{quad}}"),
+        "Get a quad",
+        Some(Viewer),
+        Empty,
+        json::<SerdeQuad>(),
+    );
+    api.route(
+        "post",
+        &format!("{layer}/quads/{{quad}}"),
+        "Edit a quad",
+        Some(Editor),
+        json::<SerdeQuad>(),
+        Empty,
+    );
+    api.route(
+        "delete",
+        &format!("{layer}/quads/{{quad}}"),
+        "Delete a quad",
+        Some(Editor),
+        Empty,
+        Empty,
+    );
+    api.route(
+        "put",
+        &format!("{layer}/sources"),
+        "Add a sound source",
+        Some(Editor),
+        json::<SerdeSoundSource>(),
+        Empty,
+    );
+    api.route(
+        "get",
+        &format!("{layer}/sources/{{source}}"),
+        "Get a sound source",
+        Some(Viewer),
+        Empty,
+        json::<SerdeSoundSource>(),
+    );
+    api.route(
+        "post",
+        &format!("{layer}/sources/{{source}}"),
+        "Edit a sound source",
+        Some(Editor),
+        json::<SerdeSoundSource>(),
+        Empty,
+    );
+    api.route(
+        "delete",
+        &format!("{layer}/sources/{{source}}"),
+        "Delete a sound source",
+        Some(Editor),
+        Empty,
+        Empty,
+    );
+
+    // the query parameters of the tiles route, see router::TilesQuery.
+    let tiles = &mut api.paths[&format!("{layer}/tiles")]["get"]["parameters"];
+    for name in ["x", "y", "w", "h"] {
+        tiles.as_array_mut().unwrap().push(json!({
+            "name": name,
+            "in": "query",
+            "description": "region of the layer, all or none of x, y, w, h",
+            "schema": { "type": "integer", "minimum": 0 },
+        }));
+    }
+    let compression = api.gen.subschema_for::<Compression>();
+    tiles.as_array_mut().unwrap().push(json!({
+        "name": "compression",
+        "in": "query",
+        "schema": compression,
+    }));
+
+    json!({
+        "openapi": "3.1.0",
+        "info": {
+            "title": "twwe",
+            "description": env!("CARGO_PKG_DESCRIPTION"),
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": api.paths,
+        "components": {
+            "schemas": api.gen.take_definitions(true),
+            "securitySchemes": {
+                "bearer": {
+                    "type": "http",
+                    "scheme": "bearer",
+                    "description": "websocket user token or invite token",
+                },
+            },
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use axum::http::{Method, StatusCode};
+    use fixed::types::{I17F15, I22F10, I27F5};
+    use serde::Serialize;
+
+    use super::*;
+    use crate::{error::Error, testing::TestServer};
+
+    fn openapi_routes() -> BTreeSet<(String, String)> {
+        openapi()["paths"]
+            .as_object()
+            .unwrap()
+            .iter()
+            .flat_map(|(path, item)| {
+                item.as_object()
+                    .unwrap()
+                    .keys()
+                    .map(move |method| (method.clone(), path.clone()))
+            })
+            .collect()
+    }
+
+    /// Sends a request to the router for a route of the openapi document, with
+    /// the path parameters filled in. Unknown routes are reported as 405.
+    async fn route_status(server: &TestServer, method: &str, path: &str) -> StatusCode {
+        let uri = path
+            .split('/')
+            .map(|s| match s {
+                "{map}" => "test",
+                _ if s.starts_with('{') => "0",
+                _ => s,
+            })
+            .collect::<Vec<_>>()
+            .join("/");
+        let method = Method::from_bytes(method.to_uppercase().as_bytes()).unwrap();
+        let (status, body) = server.rest(method, &uri, None, None).await;
+        // the router responds with an empty body to unknown routes, the
+        // handlers always explain their errors.
+        if status == StatusCode::NOT_FOUND && body.is_empty() {
+            StatusCode::METHOD_NOT_ALLOWED
+        } else {
+            status
+        }
+    }
+
+    #[tokio::test]
+    async fn openapi_matches_router() {
+        let server = TestServer::new("openapi-matches-router");
+        let openapi = openapi_routes();
+        let paths = openapi
+            .iter()
+            .map(|(_, path)| path)
+            .collect::<BTreeSet<_>>();
+        assert!(openapi.len() > 50, "missing openapi routes");
+
+        for path in paths {
+            for method in ["get", "put", "post", "delete"] {
+                let route = (method.to_owned(), path.clone());
+                let status = route_status(&server, method, path).await;
+                if openapi.contains(&route) {
+                    assert_ne!(
+                        status,
+                        StatusCode::METHOD_NOT_ALLOWED,
+                        "openapi route missing in router: {route:?}"
+                    );
+                } else {
+                    assert_eq!(
+                        status,
+                        StatusCode::METHOD_NOT_ALLOWED,
+                        "route missing in openapi: {route:?}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[track_caller]
+    fn assert_valid(validator: &jsonschema::Validator, packet: &impl Serialize) {
+        let value = serde_json::to_value(packet).unwrap();
+        let errors: Vec<_> = validator
+            .iter_errors(&value)
+            .map(|e| format!("{e} at {}", e.instance_path))
+            .collect();
+        assert!(errors.is_empty(), "{value}\n{errors:#?}");
+    }
+
+    fn fixed<T: fixed::traits::Fixed>(n: f32) -> T {
+        T::from_num(n)
+    }
+
+    fn volume(v: f32) -> twmap::Volume {
+        twmap::Volume(fixed::<I22F10>(v))
+    }
+
+    fn env_points<T: Copy>(a: T, b: T) -> Option<Vec<twmap::EnvPoint<T>>> {
+        Some(vec![
+            twmap::EnvPoint {
+                time: 0,
+                content: a,
+                curve: twmap::CurveKind::Step,
+            },
+            twmap::EnvPoint {
+                time: 1000,
+                content: b,
+                curve: twmap::CurveKind::Smooth,
+            },
+        ])
+    }
+
+    fn quad() -> Box<twmap::Quad> {
+        let mut quad = twmap::Quad::new(
+            vek::Vec2::new(fixed(1.5), fixed(2.0)),
+            vek::Extent2::new(fixed(2.0), fixed(1.0)),
+        )
+        .unwrap();
+        quad.colors[2] = vek::Rgba::new(1, 2, 3, 4);
+        Box::new(quad)
+    }
+
+    fn source(area: twmap::SoundArea) -> Box<twmap::SoundSource> {
+        Box::new(twmap::SoundSource {
+            area,
+            looping: true,
+            panning: false,
+            delay: 10,
+            falloff: 20,
+            position_env: None,
+            position_env_offset: 5,
+            sound_env: None,
+            sound_env_offset: -5,
+        })
+    }
+
+    /// Requests that fill a blank map with every kind of item, so that the
+    /// responses below contain every twmap and vek type of the protocol.
+    /// Layers do not refer to envelopes: twmap can only serialize those references
+    /// when it saves a map directory.
+    fn edit_requests() -> Vec<Request> {
+        let position = |x: f32| twmap::Position {
+            offset: vek::Vec2::new(fixed(x), fixed(-x)),
+            rotation: fixed(x * 90.0),
+        };
+        let color = |c: f32| vek::Rgba::new(fixed(c), fixed(1.0), fixed(0.5), fixed(1.0));
+
+        let circle = twmap::SoundArea::Circle(vek::Disk::new(
+            vek::Vec2::new(fixed(1.0), fixed(2.0)),
+            fixed::<I27F5>(3.5),
+        ));
+        let rectangle = twmap::SoundArea::Rectangle(vek::Rect::new(
+            fixed(1.0),
+            fixed(2.0),
+            fixed(3.0),
+            fixed::<I17F15>(4.0),
+        ));
+
+        vec![
+            Request::Edit(EditReq::Info(Box::new(PartialInfo {
+                author: Some("author".to_owned()),
+                settings: Some(vec!["sv_gametype ddnet".to_owned()]),
+                ..Default::default()
+            }))),
+            Request::Create(CreateReq::Envelope(Box::new(PartialEnvelope::Position(
+                PartialEnv {
+                    name: Some("position".to_owned()),
+                    synchronized: Some(true),
+                    points: env_points(position(0.5), position(1.0)),
+                },
+            )))),
+            Request::Create(CreateReq::Envelope(Box::new(PartialEnvelope::Color(
+                PartialEnv {
+                    name: Some("color".to_owned()),
+                    synchronized: None,
+                    points: env_points(color(0.25), color(0.75)),
+                },
+            )))),
+            Request::Create(CreateReq::Envelope(Box::new(PartialEnvelope::Sound(
+                PartialEnv {
+                    name: Some("sound".to_owned()),
+                    synchronized: None,
+                    points: env_points(volume(0.0), volume(1.0)),
+                },
+            )))),
+            Request::Create(CreateReq::Group(Box::new(PartialGroup {
+                name: Some("design".to_owned()),
+                offset: Some(vek::Vec2::new(fixed(1.5), fixed(-2.0))),
+                parallax: Some(vek::Vec2::new(50, 100)),
+                clipping: Some(true),
+                clip: Some(vek::Rect::new(
+                    fixed(0.0),
+                    fixed(1.0),
+                    fixed(10.0),
+                    fixed(20.5),
+                )),
+            }))),
+            Request::Create(CreateReq::Layer(
+                1,
+                Box::new(PartialLayer::Tiles(PartialTilesLayer {
+                    name: Some("tiles".to_owned()),
+                    color: Some(vek::Rgba::new(255, 128, 0, 255)),
+                    ..Default::default()
+                })),
+            )),
+            Request::Edit(EditReq::Tiles(
+                1,
+                0,
+                Box::new(Tiles {
+                    rect: vek::Rect::new(1, 2, 2, 1),
+                    tiles: crate::base64::Base64(vec![1, 0, 0, 0, 2, 0, 0, 0]),
+                    compression: Compression::None,
+                }),
+            )),
+            Request::Create(CreateReq::Layer(
+                1,
+                Box::new(PartialLayer::Quads(PartialQuadsLayer {
+                    name: Some("quads".to_owned()),
+                    ..Default::default()
+                })),
+            )),
+            Request::Create(CreateReq::Quad(1, 1, quad())),
+            Request::Create(CreateReq::Layer(
+                1,
+                Box::new(PartialLayer::Sounds(PartialSoundsLayer {
+                    name: Some("sounds".to_owned()),
+                    ..Default::default()
+                })),
+            )),
+            Request::Create(CreateReq::Source(1, 2, source(circle))),
+            Request::Create(CreateReq::Source(1, 2, source(rectangle))),
+            Request::Cursor(Box::new(Cursor {
+                point: vek::Vec2::new(1.5, 2.0),
+                group: 1,
+                layer: 0,
+            })),
+            Request::Batch(vec![Request::Edit(EditReq::Group(
+                1,
+                Box::new(PartialGroup {
+                    clipping: Some(false),
+                    ..Default::default()
+                }),
+            ))]),
+        ]
+    }
+
+    #[test]
+    fn packets_match_json_schema() {
+        let schema = serde_json::to_value(json_schema()).unwrap();
+        let validator = jsonschema::validator_for(&schema).unwrap();
+        let server = TestServer::new("packets-match-json-schema");
+        let user = server.join(None).unwrap();
+
+        for req in edit_requests() {
+            assert_valid(&validator, &RecvPacket::new(Some(1), req.clone()));
+            let resp = server.request(&user, req.clone());
+            assert!(resp.is_ok(), "{req:?}: {resp:?}");
+            assert_valid(&validator, &SendPacket::new(Some(1), Message::Request(req)));
+        }
+
+        let gets = [
+            GetReq::Users,
+            GetReq::Cursors,
+            GetReq::Config,
+            GetReq::Info,
+            GetReq::Envelope(0),
+            GetReq::Envelope(1),
+            GetReq::Envelope(2),
+            GetReq::Group(0),
+            GetReq::Group(1),
+            GetReq::Layer(0, 0),
+            GetReq::Layer(1, 0),
+            GetReq::Layer(1, 1),
+            GetReq::Layer(1, 2),
+            GetReq::Tiles(1, 0, Compression::Zlib),
+            GetReq::Quad(1, 1, 0),
+            GetReq::Source(1, 2, 0),
+            GetReq::Source(1, 2, 1),
+            GetReq::Lints,
+            GetReq::Reachability,
+        ];
+        for get in gets {
+            let req = Request::Get(get);
+            assert_valid(&validator, &RecvPacket::new(Some(1), req.clone()));
+            let resp = server.request(&user, req.clone());
+            assert!(resp.is_ok(), "{req:?}: {resp:?}");
+            let packet = SendPacket::new(Some(1), Message::Response(resp)).with_revision(3);
+            assert_valid(&validator, &packet);
+        }
+
+        // envelope references of quads and sources are serialized as indices.
+        let mut quad = quad();
+        quad.position_env = Some(0);
+        quad.color_env = Some(1);
+        let mut source = source(twmap::SoundArea::Rectangle(Default::default()));
+        source.position_env = Some(0);
+        source.sound_env = Some(2);
+        let reqs = [
+            Request::Edit(EditReq::Quad(1, 1, 0, quad)),
+            Request::Edit(EditReq::Source(1, 2, 1, source)),
+            Request::Get(GetReq::Quad(1, 1, 0)),
+            Request::Get(GetReq::Source(1, 2, 1)),
+        ];
+        for req in reqs {
+            assert_valid(&validator, &RecvPacket::new(Some(1), req.clone()));
+            let resp = server.request(&user, req.clone());
+            assert!(resp.is_ok(), "{req:?}: {resp:?}");
+            assert_valid(
+                &validator,
+                &SendPacket::new(Some(1), Message::Response(resp)),
+            );
+        }
+
+        // bezier curves are not allowed in DDNet maps, the request is only serialized.
+        let bezier = twmap::BezierCurve {
+            handle_l: vek::Vec2::new(volume(0.5), volume(1.0)),
+            handle_r: vek::Vec2::new(volume(-1.0), volume(0.0)),
+        };
+        let mut points = env_points(volume(0.0), volume(1.0)).unwrap();
+        points[0].curve = twmap::CurveKind::Bezier(bezier);
+        let req = Request::Create(CreateReq::Envelope(Box::new(PartialEnvelope::Sound(
+            PartialEnv {
+                name: None,
+                synchronized: None,
+                points: Some(points),
+            },
+        ))));
+        assert_valid(&validator, &RecvPacket::new(Some(1), req));
+
+        let error = Message::Response(Err(Error::GroupNotFound));
+        assert_valid(&validator, &SendPacket::new(Some(1), error));
+        let broadcasts = [
+            Broadcast::UserJoined(user.presence()),
+            Broadcast::Users(1),
+            Broadcast::Saved,
+        ];
+        for broadcast in broadcasts {
+            let packet = SendPacket::new(None, Message::Broadcast(broadcast));
+            assert_valid(&validator, &packet);
+        }
+    }
+}
@@ -1,7 +1,13 @@
 use std::{
+    cell::OnceCell,
     collections::HashMap,
+    net::IpAddr,
     path::{Path, PathBuf},
-    sync::Arc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Instant,
 };
 
 use axum::extract::ws::{Message as WebSocketMessage, WebSocket};
@@ -19,9 +25,13 @@ use crate::{
     checks::PartialCheck,
     cli::Cli,
     error::Error,
+    history::{self, Operation},
+    lints::{lint_map, physics_group, reachability},
+    map_cfg::{hash_password, MapInvite},
     protocol::*,
     room::Room,
-    twmap_map_checks::InternalMapChecking,
+    throttle::LoginThrottle,
+    twmap_map_checks::{check_map_report, check_map_tiles, check_tiles, InternalMapChecking},
     util::{macros::apply_partial, *},
 };
 
@@ -37,15 +47,58 @@ type Tx = UnboundedSender<WebSocketMessage>;
 /// the mutable properties of a user are stored with a RwLock
 pub struct UserInner {
     pub name: Option<String>,
+    pub color: Option<vek::Rgb<u8>>,
     pub room: Option<Arc<RwLock<Room>>>,
+    pub role: Option<Role>, // role in the joined room
     pub cursor: Option<Cursor>,
 }
 
+/// An invite token that was verified and counted as a use by a REST request.
+#[derive(Clone)]
+pub struct RedeemedInvite {
+    pub map_name: String,
+    pub invite_id: String,
+}
+
 pub struct User {
     pub token: String,
     pub id: Uuid,
     pub tx: Tx,
+    pub encoding: Encoding,
+    pub ip: Option<IpAddr>,
     pub inner: RwLock<UserInner>,
+    pub cursor_changed: AtomicBool, // the cursor was not broadcasted yet
+    pub disconnected: Mutex<Option<Instant>>, // start of the resume grace period
+}
+
+/// Serializes a packet to be sent on a websocket with the given encoding.
+pub(crate) fn encode_packet(packet: &SendPacket, encoding: Encoding) -> WebSocketMessage {
+    // this must not fail
+    match encoding {
+        Encoding::Json => WebSocketMessage::Text(serde_json::to_string(packet).unwrap()),
+        Encoding::MessagePack => WebSocketMessage::Binary(rmp_serde::to_vec_named(packet).unwrap()),
+    }
+}
+
+/// A packet broadcasted to many users, serialized at most once per encoding.
+struct EncodedPacket<'a> {
+    packet: &'a SendPacket,
+    cache: [OnceCell<WebSocketMessage>; 2],
+}
+
+impl<'a> EncodedPacket<'a> {
+    fn new(packet: &'a SendPacket) -> Self {
+        Self {
+            packet,
+            cache: Default::default(),
+        }
+    }
+
+    fn get(&self, encoding: Encoding) -> WebSocketMessage {
+        self.cache[encoding as usize]
+            .get_or_init(|| encode_packet(self.packet, encoding))
+            .clone()
+    }
 }
 
 impl User {
@@ -54,11 +107,17 @@ impl User {
             token,
             id: Uuid::new_v4(),
             tx,
+            encoding: Encoding::Json,
+            ip: None,
             inner: RwLock::new(UserInner {
                 name: None,
+                color: None,
                 room: Default::default(),
+                role: None,
                 cursor: Default::default(),
             }),
+            cursor_changed: AtomicBool::new(false),
+            disconnected: Mutex::new(None),
         }
     }
 
@@ -70,10 +129,24 @@ impl User {
         self.inner.read().room.clone().map(|room| room.write_arc())
     }
 
+    pub fn presence(&self) -> Presence {
+        let inner = self.inner.read();
+        Presence {
+            id: self.id.to_string(),
+            name: inner.name.clone(),
+            color: inner.color,
+            role: inner.role,
+            cursor: inner.cursor.clone(),
+        }
+    }
+
     pub(crate) fn send(&self, id: Option<u32>, msg: Message) {
-        let packet = SendPacket::new(id, msg);
-        let str = serde_json::to_string(&packet).unwrap(); // this must not fail
-        self.tx.unbounded_send(WebSocketMessage::Text(str)).ok(); // this is ok to fail (user logout)
+        self.send_packet(&SendPacket::new(id, msg))
+    }
+
+    pub(crate) fn send_packet(&self, packet: &SendPacket) {
+        let msg = encode_packet(packet, self.encoding);
+        self.tx.unbounded_send(msg).ok(); // this is ok to fail (user logout)
     }
 }
 
@@ -86,6 +159,11 @@ pub struct Server {
     pub max_maps: usize,
     pub max_map_size: usize, // in bytes
     pub max_users: usize,
+    pub max_backups: usize,
+    pub login_throttle: LoginThrottle,
+    pub invite_tokens: Mutex<HashMap<String, RedeemedInvite>>, // REST invite tokens, by token
+    pub resume_grace: std::time::Duration,
+    pub admin_password: Option<String>,
     #[cfg(feature = "bridge_out")]
     pub bridge: Mutex<Option<JoinHandle<()>>>,
     #[cfg(feature = "bridge_in")]
@@ -103,6 +181,11 @@ impl Server {
             max_maps: cli.max_maps,
             max_map_size: cli.max_map_size * 1024,
             max_users: cli.max_connections,
+            max_backups: cli.max_backups,
+            login_throttle: Default::default(),
+            invite_tokens: Default::default(),
+            resume_grace: std::time::Duration::from_secs(cli.resume_grace),
+            admin_password: cli.admin_password.clone(),
             #[cfg(feature = "bridge_out")]
             bridge: Default::default(),
             #[cfg(feature = "bridge_in")]
@@ -110,7 +193,7 @@ impl Server {
         }
     }
 
-    pub fn users(&self) -> MutexGuard<HashMap<String, Arc<User>>> {
+    pub fn users(&self) -> MutexGuard<'_, HashMap<String, Arc<User>>> {
         self.users.lock()
     }
 
@@ -118,48 +201,91 @@ impl Server {
         self.users().get(token).cloned().ok_or(Error::Unauthorized)
     }
 
-    pub fn rooms(&self) -> MutexGuard<HashMap<String, Arc<RwLock<Room>>>> {
+    pub fn rooms(&self) -> MutexGuard<'_, HashMap<String, Arc<RwLock<Room>>>> {
         self.rooms.lock()
     }
 
     pub fn room(&self, name: &str) -> Result<Arc<RwLock<Room>>, Error> {
         self.rooms().get(name).cloned().ok_or(Error::MapNotFound)
     }
+
+    /// The name a room is listed under. It is not always the config name: the
+    /// config can be renamed and duplicate names get a suffix on startup.
+    pub fn room_name(&self, room: &Arc<RwLock<Room>>) -> Option<String> {
+        self.rooms()
+            .iter()
+            .find(|(_, r)| Arc::ptr_eq(r, room))
+            .map(|(name, _)| name.to_owned())
+    }
+
+    /// The admin password grants the owner role on every map.
+    pub fn is_admin_password(&self, password: &str) -> bool {
+        self.admin_password
+            .as_deref()
+            .is_some_and(|admin| !admin.is_empty() && admin == password)
+    }
 }
 
 impl Server {
+    /// Broadcasts to the connected users that are not in a room.
     pub(crate) fn broadcast_to_lobby(&self, msg: Message) {
         let packet = SendPacket::new(None, msg);
-        let str = serde_json::to_string(&packet).unwrap(); // this must not fail
-        let _msg = WebSocketMessage::Text(str);
+        let packet = EncodedPacket::new(&packet);
+
+        for user in self.users().values() {
+            if user.inner.read().room.is_none() {
+                user.tx.unbounded_send(packet.get(user.encoding)).ok();
+            }
+        }
+    }
+
+    /// Notifies the lobby when the number of users of a public map changes.
+    pub(crate) fn broadcast_map_users(&self, map_name: &str) {
+        let detail = match self.room(map_name) {
+            Ok(room) => {
+                let room = room.read();
+                room.config.public.then(|| MapDetail {
+                    name: map_name.to_owned(),
+                    users: room.user_count(),
+                })
+            }
+            Err(_) => None,
+        };
 
-        log::warn!("TODO: broadcast_to_lobby");
+        if let Some(detail) = detail {
+            self.broadcast_to_lobby(Message::Broadcast(Broadcast::MapUsers(detail)));
+        }
     }
 
     pub(crate) fn broadcast_to_room(&self, room: &Room, content: Message) {
-        let packet = SendPacket::new(None, content);
-        let str = serde_json::to_string(&packet).unwrap(); // this must not fail
-        let msg = WebSocketMessage::Text(str);
+        let packet = SendPacket::new(None, content).with_revision(room.revisions.current());
+        let packet = EncodedPacket::new(&packet);
 
         for (_addr, p) in room.users() {
-            p.tx.unbounded_send(msg.to_owned()).ok();
+            p.tx.unbounded_send(packet.get(p.encoding)).ok();
         }
     }
 
     pub(crate) fn broadcast_to_others(&self, user: &User, content: Message) {
-        let packet = SendPacket::new(None, content);
-        let str = serde_json::to_string(&packet).unwrap(); // this must not fail
-        let msg = WebSocketMessage::Text(str);
-
         if let Some(room) = user.room() {
+            let packet = SendPacket::new(None, content).with_revision(room.revisions.current());
+            let packet = EncodedPacket::new(&packet);
+
             for (addr, p) in room.users() {
                 if !addr.eq(&user.token) {
-                    p.tx.unbounded_send(msg.to_owned()).ok();
+                    p.tx.unbounded_send(packet.get(p.encoding)).ok();
                 }
             }
         }
     }
 
+    pub(crate) fn broadcast_locks(&self, user: &User) {
+        if let Some(room) = user.room() {
+            let locks = room.locks.list();
+            self.broadcast_to_room(&room, Message::Broadcast(Broadcast::Locks(locks)));
+        }
+    }
+
     pub(crate) fn broadcast_users(&self, user: &User) {
         if let Some(room) = user.room() {
             let users = room.user_count();
@@ -167,19 +293,42 @@ impl Server {
         }
     }
 
-    fn ensure_authorized(&self, user: &User, map_name: &str) -> Result<(), Error> {
+    /// Users that joined a map have the role they joined with. Other users have the
+    /// role granted without a password, if any.
+    pub(crate) fn granted_role(
+        &self,
+        user: Option<&User>,
+        map_name: &str,
+    ) -> Result<Option<Role>, Error> {
         let room = self.room(map_name)?;
-        let authorized = room.read().config.password.is_none()
-            || user
-                .inner
-                .read()
-                .room
-                .as_ref()
-                .is_some_and(|r| Arc::ptr_eq(r, &room));
-        if !authorized {
-            log::debug!("unauthorized: `{map_name}` for {}", user.token);
+        let joined_role = user.and_then(|user| {
+            let inner = user.inner.read();
+            let joined = inner.room.as_ref().is_some_and(|r| Arc::ptr_eq(r, &room));
+            joined.then_some(inner.role).flatten()
+        });
+        let granted = joined_role.or_else(|| room.read().config.open_role());
+        Ok(granted)
+    }
+
+    pub(crate) fn ensure_role(
+        &self,
+        user: Option<&User>,
+        map_name: &str,
+        role: Role,
+    ) -> Result<(), Error> {
+        match self.granted_role(user, map_name)? {
+            Some(granted) if granted >= role => Ok(()),
+            granted => {
+                log::debug!(
+                    "unauthorized: `{map_name}` for {}",
+                    user.map_or("nameless tee", |user| &user.token)
+                );
+                match granted {
+                    Some(_) => Err(Error::Forbidden),
+                    None => Err(Error::Unauthorized),
+                }
+            }
         }
-        authorized.then_some(()).ok_or(Error::Unauthorized)
     }
 
     pub(crate) fn do_request(
@@ -187,11 +336,39 @@ impl Server {
         user: Option<Arc<User>>,
         req: Request,
     ) -> Result<Response, Error> {
-        let map_name = user
+        let room = user
             .as_ref()
-            .and_then(|user| user.room())
-            .map(|room| room.name().to_string())
-            .ok_or(Error::MapNotFound);
+            .and_then(|user| user.inner.read().room.clone());
+        let map_name = room.and_then(|room| self.room_name(&room));
+        self.do_map_request(user, map_name, None, req)
+    }
+
+    /// Applies a request to a map. Websocket requests apply to the room joined by the
+    /// user. REST requests apply to the map of the route, with the `role` granted to
+    /// their bearer token instead of the role of the joined room.
+    fn do_map_request(
+        &self,
+        user: Option<Arc<User>>,
+        map_name: Option<String>,
+        role: Option<Role>,
+        req: Request,
+    ) -> Result<Response, Error> {
+        let map_name = map_name.ok_or(Error::MapNotFound);
+
+        match (required_role(&req), role) {
+            (Some(required), Some(granted)) if granted < required => {
+                return Err(Error::Forbidden);
+            }
+            (Some(_), Some(_)) | (None, _) => (),
+            (Some(required), None) => {
+                let map_name = match &req {
+                    Request::GetMap(name) | Request::DeleteMap(name) => Ok(name.clone()),
+                    _ => map_name.clone(),
+                };
+                let user = user.as_deref().ok_or(Error::Unauthorized)?;
+                self.ensure_role(Some(user), &map_name?, required)?;
+            }
+        }
 
         let user = user.ok_or(Error::Unauthorized);
 
@@ -200,26 +377,53 @@ impl Server {
             Request::Config(map_name) => self
                 .get_config(&map_name)
                 .map(|r| Response::Config(r.into())),
-            Request::JoinMap(join) => self.user_join(user?, &join).map(|()| Response::Ok),
+            Request::JoinMap(join) => self
+                .user_join(user?, &join)
+                .map(|r| Response::User(Box::new(r))),
+            Request::SetName(profile) => self.set_name(&*user?, profile).map(|()| Response::Ok),
             Request::LeaveMap(map_name) => {
                 self.user_leave(&*user?, &map_name).map(|()| Response::Ok)
             }
-            Request::GetMap(map_name) => {
-                self.ensure_authorized(&*user?, &map_name)?;
-                self.get_map(&map_name).map(|r| Response::Map(Base64(r)))
-            }
+            Request::GetMap(map_name) => self.get_map(&map_name).map(|r| Response::Map(Base64(r))),
             Request::CreateMap(map_name, content) => {
                 self.create_map(&map_name, *content).map(|()| Response::Ok)
             }
-            Request::DeleteMap(map_name) => {
-                self.ensure_authorized(&*user?, &map_name)?;
-                self.delete_map(&map_name).map(|()| Response::Ok)
-            }
+            Request::DeleteMap(map_name) => self.delete_map(&map_name).map(|()| Response::Ok),
             Request::Save => self.save_map(&map_name?).map(|()| Response::Ok),
             Request::Cursor(req) => self.set_cursor(&*user?, *req).map(|()| Response::Ok),
+            Request::Undo => self.undo(&user?).map(|()| Response::Ok),
+            Request::Redo => self.redo(&user?).map(|()| Response::Ok),
+            Request::Batch(reqs) => self.do_batch(&user?, reqs).map(|_| Response::Ok),
+            Request::RestoreRecovery => self.restore_recovery(&map_name?).map(|()| Response::Ok),
+            Request::DeleteRecovery => self.delete_recovery(&map_name?).map(|()| Response::Ok),
+            Request::Chat(text) => self.chat(&text).map(|()| Response::Ok),
+            Request::CreateComment(req) => self
+                .create_comment(&map_name?, &*user?, *req)
+                .map(|r| Response::Comment(Box::new(r))),
+            Request::ResolveComment(id) => {
+                self.resolve_comment(&map_name?, id).map(|()| Response::Ok)
+            }
+            Request::DeleteComment(id) => {
+                self.delete_comment(&map_name?, id).map(|()| Response::Ok)
+            }
+            Request::CreateInvite(req) => self
+                .create_invite(&map_name?, req)
+                .map(|r| Response::Invite(Box::new(r))),
+            Request::RevokeInvite(id) => self.revoke_invite(&map_name?, &id).map(|()| Response::Ok),
+            Request::LockLayer(g, l) => self
+                .lock_layer(&map_name?, &*user?, g, l)
+                .map(|()| Response::Ok),
+            Request::UnlockLayer(g, l) => self
+                .unlock_layer(&map_name?, &*user?, g, l)
+                .map(|()| Response::Ok),
+            Request::RestoreBackup(name) => self
+                .restore_backup(&map_name?, &name)
+                .map(|()| Response::Ok),
             Request::Get(req) => match req {
-                GetReq::Users => self.get_users(&map_name?).map(Response::Users),
-                GetReq::Cursors => self.get_cursors(&map_name?, &*user?).map(Response::Cursors),
+                GetReq::Users => self.get_user_list(&map_name?).map(Response::Users),
+                GetReq::Cursors => self
+                    .get_cursors(&map_name?, Some(&*user?))
+                    .map(Response::Cursors),
                 GetReq::Map => self.get_map(&map_name?).map(|r| Response::Map(Base64(r))),
                 GetReq::Config => self
                     .get_config(&map_name?)
@@ -227,6 +431,7 @@ impl Server {
                 GetReq::Info => self
                     .get_info(&map_name?)
                     .map(|r| Response::Info(Box::new(r))),
+                GetReq::Backups => self.get_backups(&map_name?).map(Response::Backups),
                 GetReq::Images => self.get_images(&map_name?).map(Response::Images),
                 GetReq::Image(i) => self
                     .get_image(&map_name?, i)
@@ -243,12 +448,30 @@ impl Server {
                 GetReq::Layer(g, l) => self
                     .get_layer(&map_name?, g, l)
                     .map(|r| Response::Layer(Box::new(r))),
-                GetReq::Tiles(g, l) => self
-                    .get_tiles(&map_name?, g, l)
+                GetReq::Tiles(g, l, c) => self
+                    .get_tiles(&map_name?, g, l, None, c)
+                    .map(|r| Response::Tiles(Base64(r.into()))),
+                GetReq::TilesRegion(g, l, rect, c) => self
+                    .get_tiles(&map_name?, g, l, Some(rect), c)
                     .map(|r| Response::Tiles(Base64(r.into()))),
                 GetReq::Quad(g, l, q) => self
                     .get_quad(&map_name?, g, l, q)
                     .map(|r| Response::Quad(Box::new(r))),
+                GetReq::Sounds => self.get_sounds(&map_name?).map(Response::Sounds),
+                GetReq::Comments => self.get_comments(&map_name?).map(Response::Comments),
+                GetReq::Locks => self.get_locks(&map_name?).map(Response::Locks),
+                GetReq::Check => self.get_check(&map_name?).map(Response::MapDiagnostics),
+                GetReq::Lints => self.get_lints(&map_name?).map(Response::Lints),
+                GetReq::Reachability => self
+                    .get_reachability(&map_name?)
+                    .map(|r| Response::Reachability(Box::new(r))),
+                GetReq::Invites => self.get_invites(&map_name?).map(Response::Invites),
+                GetReq::Sound(s) => self
+                    .get_sound(&map_name?, s)
+                    .map(|r| Response::Sound(Base64(r))),
+                GetReq::Source(g, l, s) => self
+                    .get_source(&map_name?, g, l, s)
+                    .map(|r| Response::Source(Box::new(r))),
                 GetReq::Automappers => self.get_automappers(&map_name?).map(Response::Automappers),
                 GetReq::Automapper(am) => self
                     .get_automapper(&map_name?, &am)
@@ -268,6 +491,12 @@ impl Server {
                 CreateReq::Quad(g, l, req) => {
                     self.put_quad(&map_name?, g, l, *req).map(|()| Response::Ok)
                 }
+                CreateReq::Sound(sound_name, file) => self
+                    .put_sound(&map_name?, &sound_name, file.0)
+                    .map(|()| Response::Ok),
+                CreateReq::Source(g, l, req) => self
+                    .put_source(&map_name?, g, l, *req)
+                    .map(|()| Response::Ok),
                 CreateReq::Automapper(am, file) => self
                     .put_automapper(&map_name?, &am, &file)
                     .map(Response::AutomapperDiagnostics),
@@ -280,6 +509,7 @@ impl Server {
                 EditReq::Layer(g, l, req) => self.edit_layer(&map_name?, g, l, *req),
                 EditReq::Tiles(g, l, req) => self.edit_tiles(&map_name?, g, l, *req),
                 EditReq::Quad(g, l, q, req) => self.edit_quad(&map_name?, g, l, q, *req),
+                EditReq::Source(g, l, s, req) => self.edit_source(&map_name?, g, l, s, *req),
                 EditReq::Automap(g, l) => self.apply_automapper(&map_name?, g, l),
             }
             .map(|()| Response::Ok),
@@ -289,6 +519,8 @@ impl Server {
                 DeleteReq::Group(g) => self.delete_group(&map_name?, g),
                 DeleteReq::Layer(g, l) => self.delete_layer(&map_name?, g, l),
                 DeleteReq::Quad(g, l, q) => self.delete_quad(&map_name?, g, l, q),
+                DeleteReq::Sound(s) => self.delete_sound(&map_name?, s),
+                DeleteReq::Source(g, l, s) => self.delete_source(&map_name?, g, l, s),
                 DeleteReq::Automapper(am) => self.delete_automapper(&map_name?, &am),
             }
             .map(|()| Response::Ok),
@@ -298,6 +530,8 @@ impl Server {
                 MoveReq::Group(src, tgt) => self.move_group(&map_name?, src, tgt),
                 MoveReq::Layer(src, tgt) => self.move_layer(&map_name?, src, tgt),
                 MoveReq::Quad(src, tgt) => self.move_quad(&map_name?, src, tgt),
+                MoveReq::Sound(src, tgt) => self.move_sound(&map_name?, src, tgt),
+                MoveReq::Source(src, tgt) => self.move_source(&map_name?, src, tgt),
             }
             .map(|()| Response::Ok),
         }
@@ -305,11 +539,31 @@ impl Server {
 
     pub(crate) fn do_broadcast(&self, user: &User, packet: &RecvPacket) {
         match &packet.content {
-            Request::LeaveMap(name) | Request::JoinMap(JoinReq { name, .. }) => self
-                .broadcast_to_others(
+            Request::JoinMap(JoinReq { name, .. }) => {
+                let users = self.get_users(name).unwrap_or(0);
+                self.broadcast_to_others(user, Message::Broadcast(Broadcast::Users(users)));
+                self.broadcast_map_users(name);
+                self.broadcast_to_others(
                     user,
-                    Message::Broadcast(Broadcast::Users(self.get_users(name).unwrap_or(0))),
-                ),
+                    Message::Broadcast(Broadcast::UserJoined(user.presence())),
+                );
+            }
+            // the user is not in the room anymore.
+            Request::LeaveMap(name) => {
+                if let Ok(room) = self.room(name) {
+                    let room = room.read();
+                    let users = room.user_count();
+                    self.broadcast_to_room(&room, Message::Broadcast(Broadcast::Users(users)));
+                    self.broadcast_to_room(
+                        &room,
+                        Message::Broadcast(Broadcast::UserLeft(user.presence())),
+                    );
+                    // the locks of the user were released.
+                    let locks = room.locks.list();
+                    self.broadcast_to_room(&room, Message::Broadcast(Broadcast::Locks(locks)));
+                }
+                self.broadcast_map_users(name);
+            }
             Request::CreateMap(map_name, _) => {
                 self.broadcast_to_lobby(Message::Broadcast(Broadcast::MapCreated(
                     map_name.clone(),
@@ -320,11 +574,54 @@ impl Server {
                     map_name.clone(),
                 )));
             }
+            Request::SetName(_) => self.broadcast_to_others(
+                user,
+                Message::Broadcast(Broadcast::UserUpdated(user.presence())),
+            ),
             Request::Save => self.broadcast_to_others(user, Message::Broadcast(Broadcast::Saved)),
-            Request::Create(_) | Request::Edit(_) | Request::Delete(_) | Request::Move(_) => {
+            Request::Chat(text) => {
+                let inner = user.inner.read();
+                let msg = ChatMessage {
+                    id: user.id.to_string(),
+                    name: inner.name.clone(),
+                    text: text.clone(),
+                };
+                drop(inner);
+                self.broadcast_to_others(user, Message::Broadcast(Broadcast::Chat(msg)))
+            }
+            Request::CreateComment(_) | Request::ResolveComment(_) | Request::DeleteComment(_) => {
+                if let Some(room) = user.room() {
+                    let comments = room.comments.clone();
+                    self.broadcast_to_room(
+                        &room,
+                        Message::Broadcast(Broadcast::Comments(comments)),
+                    );
+                }
+            }
+            Request::LockLayer(..) | Request::UnlockLayer(..) => self.broadcast_locks(user),
+            Request::RestoreRecovery | Request::RestoreBackup(_) => {
+                self.broadcast_to_others(user, Message::Broadcast(Broadcast::MapReloaded))
+            }
+            Request::Edit(EditReq::Config(part)) => {
+                self.broadcast_to_others(user, Message::Request(packet.content.clone()));
+                let room = user.inner.read().room.clone();
+                if let Some(room) = room {
+                    self.broadcast_map_config(&room, part);
+                }
+            }
+            Request::Create(_)
+            | Request::Edit(_)
+            | Request::Delete(_)
+            | Request::Move(_)
+            | Request::Batch(_) => {
                 self.broadcast_to_others(user, Message::Request(packet.content.clone()))
             }
-            Request::Config(_)
+            // undo and redo broadcast the replayed requests themselves.
+            Request::Undo | Request::Redo => (),
+            Request::DeleteRecovery
+            | Request::CreateInvite(_)
+            | Request::RevokeInvite(_)
+            | Request::Config(_)
             | Request::ListMaps
             | Request::GetMap(_)
             | Request::Cursor(_)
@@ -332,53 +629,321 @@ impl Server {
         }
     }
 
+    /// Announces the config of a map to the lobby after an edit.
+    fn broadcast_map_config(&self, room: &Arc<RwLock<Room>>, part: &PartialConfig) {
+        let map_name = self.room_name(room);
+        let config = map_name.and_then(|name| Some((self.get_config(&name).ok()?, name)));
+        // maps that turned private are announced once, so that the lobby hides them.
+        if let Some((config, name)) = config {
+            if config.public || part.public.is_some() {
+                self.broadcast_to_lobby(Message::Broadcast(Broadcast::MapConfig(
+                    name,
+                    Box::new(config),
+                )));
+            }
+        }
+    }
+
+    /// Broadcasts an edit made with a REST route to all users of the room.
+    fn broadcast_map_edit(&self, room: &Arc<RwLock<Room>>, req: &Request) {
+        self.broadcast_to_room(&room.read(), Message::Request(req.clone()));
+        if let Request::Edit(EditReq::Config(part)) = req {
+            self.broadcast_map_config(room, part);
+        }
+    }
+
+    /// Applies the requests of a batch in order. If one of them fails, the map is
+    /// rolled back to its state before the batch. Returns the requests that revert
+    /// the whole batch, if all of them could be reverted.
+    ///
+    /// The caller must hold the room edit lock, so that no other request is
+    /// applied in-between.
+    fn do_batch(
+        &self,
+        user: &Arc<User>,
+        reqs: Vec<Request>,
+    ) -> Result<Option<Vec<Request>>, Error> {
+        let room = user.inner.read().room.clone().ok_or(Error::NotJoined)?;
+
+        // those have side effects outside of the map that cannot be rolled back.
+        let valid = |req: &Request| match req {
+            Request::Create(CreateReq::Automapper(..))
+            | Request::Edit(EditReq::Config(_))
+            | Request::Delete(DeleteReq::Automapper(_)) => false,
+            Request::Create(_) | Request::Edit(_) | Request::Delete(_) | Request::Move(_) => true,
+            _ => false,
+        };
+        if !reqs.iter().all(valid) {
+            return Err(Error::BadRequest(
+                "batch can only contain map edition requests".into(),
+            ));
+        }
+
+        let snapshot = room.write().map().clone();
+        let mut reverse = Some(Vec::new());
+
+        for req in reqs {
+            let rev = history::reverse(room.write().map(), &req);
+            if let Err(e) = self.do_request(Some(user.clone()), req) {
+                room.write().restore_snapshot(snapshot);
+                return Err(e);
+            }
+            // the batch is reverted in reverse order.
+            reverse = reverse.zip(rev).map(|(mut reverse, rev)| {
+                reverse.splice(0..0, rev);
+                reverse
+            });
+        }
+
+        Ok(reverse)
+    }
+
+    /// Applies a request, records it in the room history and broadcasts it.
+    /// The request is first rebased if it was made on an older room revision.
+    pub(crate) fn apply_request(&self, user: Option<Arc<User>>, packet: &RecvPacket) -> SendPacket {
+        let room = user
+            .as_ref()
+            .and_then(|user| user.inner.read().room.clone());
+        let map_name = room.as_ref().and_then(|room| self.room_name(room));
+
+        let resp = self.apply_room_request(user, room.as_ref(), map_name, None, packet);
+
+        let resp_packet = SendPacket::new(packet.id, Message::Response(resp));
+        match &room {
+            Some(room) => resp_packet.with_revision(room.read().revisions.current()),
+            None => resp_packet,
+        }
+    }
+
+    /// Applies a request of a REST route to a map, like [`Self::apply_request`] does
+    /// for websocket requests. `role` is the role granted to the bearer of the request.
+    /// The edit is broadcast to all users of the room.
+    pub(crate) fn apply_map_request(
+        &self,
+        user: Option<Arc<User>>,
+        map_name: &str,
+        role: Role,
+        packet: &RecvPacket,
+    ) -> Result<Response, Error> {
+        let room = self.room(map_name)?;
+        let map_name = Some(map_name.to_owned());
+        self.apply_room_request(user, Some(&room), map_name, Some(role), packet)
+    }
+
+    fn apply_room_request(
+        &self,
+        user: Option<Arc<User>>,
+        room: Option<&Arc<RwLock<Room>>>,
+        map_name: Option<String>,
+        role: Option<Role>,
+        packet: &RecvPacket,
+    ) -> Result<Response, Error> {
+        let edit_lock = room.map(|room| room.read().edit_lock());
+        let _guard = edit_lock.as_ref().map(|lock| lock.lock());
+
+        let is_edit = matches!(
+            packet.content,
+            Request::Create(_)
+                | Request::Edit(_)
+                | Request::Delete(_)
+                | Request::Move(_)
+                | Request::Batch(_)
+        );
+
+        let content = match (room, packet.base_revision) {
+            (Some(room), Some(base)) if is_edit => {
+                room.read().revisions.rebase(base, packet.content.clone())
+            }
+            _ => Ok(packet.content.clone()),
+        };
+
+        // REST requests without a user token hold no lock.
+        let user_id = user.as_ref().map_or(Uuid::nil(), |user| user.id);
+        let content = content.and_then(|content| match room {
+            Some(room) if is_edit => {
+                room.read().locks.check(&content, user_id)?;
+                Ok(content)
+            }
+            _ => Ok(content),
+        });
+
+        let (resp, content, reverse) = content.and_then(|content| match &content {
+            Request::Batch(reqs) if role.is_none() => {
+                let user = user.as_ref().ok_or(Error::Unauthorized)?;
+                let reverse = self.do_batch(user, reqs.clone())?;
+                Ok((Response::Ok, content, reverse))
+            }
+            Request::Batch(_) => Err(Error::BadRequest(
+                "batches can only be sent over the websocket".into(),
+            )),
+            _ => {
+                let reverse = match room {
+                    Some(room) if is_edit => history::reverse(room.write().map(), &content),
+                    _ => None,
+                };
+                let resp = self.do_map_request(user.clone(), map_name, role, content.clone())?;
+                Ok((resp, content, reverse))
+            }
+        })?;
+
+        if let Some(room) = room.filter(|_| is_edit) {
+            let mut room = room.write();
+            room.revisions.push(content.clone());
+            room.locks.rebase(&content);
+            if modifies_map(&content) {
+                room.mark_dirty();
+            }
+            match reverse {
+                Some(reverse) => room.history.push(Operation {
+                    forward: vec![content.clone()],
+                    reverse,
+                }),
+                // the operations in the history were recorded on a map that this
+                // edit, or a batch containing it, changed in a way that cannot be
+                // reverted.
+                None if modifies_map(&content) => room.history.clear(),
+                None => (),
+            }
+        }
+
+        match (&user, role, room) {
+            (_, Some(_), Some(room)) if is_edit => self.broadcast_map_edit(room, &content),
+            (Some(user), None, _) => {
+                self.do_broadcast(user, &RecvPacket::new(packet.id, content));
+            }
+            _ => (),
+        }
+
+        Ok(resp)
+    }
+
     pub(crate) fn handle_request(&self, user: Arc<User>, packet: RecvPacket) {
-        let resp = self.do_request(Some(user.clone()), packet.content.clone());
-        if resp.is_ok() {
-            self.do_broadcast(&user, &packet);
+        let resp_packet = self.apply_request(Some(user.clone()), &packet);
+        user.send_packet(&resp_packet);
+    }
+
+    /// A session can be resumed during the grace period after its connection
+    /// closed, while the user is kept in its room. Live sessions cannot be taken over.
+    pub(crate) fn can_resume(&self, token: &str) -> bool {
+        self.users().get(token).is_some_and(|user| {
+            let disconnected = *user.disconnected.lock();
+            user.inner.read().room.is_some()
+                && disconnected.is_some_and(|t| t.elapsed() < self.resume_grace)
+        })
+    }
+
+    /// Takes over the session of a previous connection with the same token.
+    fn resume_session(&self, user: &mut User) -> Option<Arc<User>> {
+        let old = self.users().get(&user.token).cloned()?;
+        let inner = old.inner.read();
+        user.id = old.id;
+        *user.inner.get_mut() = UserInner {
+            name: inner.name.clone(),
+            color: inner.color,
+            room: inner.room.clone(),
+            role: inner.role,
+            cursor: inner.cursor.clone(),
+        };
+        drop(inner);
+        Some(old)
+    }
+
+    /// Sends the edits applied since a revision, or asks to reload the map if they
+    /// are not in the revision log anymore.
+    fn replay_missed(&self, user: &User, base_revision: u64) {
+        let Some(room) = user.room() else {
+            return;
+        };
+        let missed = room.revisions.since(base_revision);
+        match missed {
+            Some(missed) => {
+                for (revision, req) in missed {
+                    let packet = SendPacket::new(None, Message::Request(req.clone()));
+                    user.send_packet(&packet.with_revision(*revision));
+                }
+            }
+            None => user.send(None, Message::Broadcast(Broadcast::MapReloaded)),
+        };
+    }
+
+    /// Registers the connection of a user. `resume` is the last revision received by
+    /// the client, if it resumes the session of the token. If the connection is
+    /// refused, the error is sent to the client.
+    fn connect(&self, mut user: User, resume: Option<u64>) -> Result<Arc<User>, Error> {
+        // sessions in their grace period are not connected anymore.
+        let live_count = || self.users().values().filter(|u| !u.tx.is_closed()).count();
+        let res = match resume {
+            // the session may have expired since the route checked it.
+            Some(_) if !self.can_resume(&user.token) => Err(Error::Unauthorized),
+            _ if live_count() >= self.max_users => Err(Error::MaxUsers),
+            _ => Ok(()),
+        };
+        if let Err(e) = res {
+            log::info!("refused connection of {}: {e}", user.token);
+            user.send(None, Message::Response(Err(e.clone())));
+            return Err(e);
         }
-        user.send(packet.id, Message::Response(resp));
+
+        let resumed = resume.and_then(|_| self.resume_session(&mut user));
+        let user = Arc::new(user);
+        let token = user.token.clone();
+        user.send(None, Message::Response(Ok(Response::Token(token.clone()))));
+        if let Some(mut room) = resumed.as_ref().and_then(|old| old.room_mut()) {
+            room.add_user(user.clone()); // replaces the previous connection
+        }
+        self.users().insert(token.clone(), user.clone());
+        if resumed.is_some() {
+            self.replay_missed(&user, resume.unwrap_or_default());
+            log::info!("{token} resumed its session");
+        }
+        log::debug!(
+            "simultaneous connections: {}/{}",
+            live_count(),
+            self.max_users
+        );
+        Ok(user)
     }
 
-    pub(crate) async fn handle_websocket(&self, token: String, socket: WebSocket) {
+    /// `resume` is the last revision received by the client, if it resumes the
+    /// session of the token.
+    pub(crate) async fn handle_websocket(
+        &self,
+        token: String,
+        socket: WebSocket,
+        encoding: Encoding,
+        ip: IpAddr,
+        resume: Option<u64>,
+    ) {
         let (tx, ws_recv) = socket.split();
         let (ws_send, rx) = unbounded();
         let fut_send = rx.map(Ok).forward(tx);
 
-        let user = Arc::new(User::new(token.clone(), ws_send));
-        {
-            let user_count = self.users().len();
-            log::debug!(
-                "simultaneous connections: {}/{}",
-                user_count + 1,
-                self.max_users
-            );
-            if user_count >= self.max_users {
-                user.send(None, Message::Response(Err(Error::MaxUsers)));
-                fut_send.await.ok();
-                log::info!("kicked {}, too many connections", user.token);
-                return;
-            } else {
-                user.send(None, Message::Response(Ok(Response::Token(token.clone()))));
-                self.users().insert(token.clone(), user.clone());
-            }
-        }
+        let mut user = User::new(token.clone(), ws_send);
+        user.encoding = encoding;
+        user.ip = Some(ip);
+        let Ok(user) = self.connect(user, resume) else {
+            fut_send.await.ok();
+            return;
+        };
 
         let fut_recv = ws_recv.try_for_each(|msg| {
-            if let WebSocketMessage::Text(msg) = msg {
+            let req = match &msg {
                 // log::debug!("text message received from {}: {}", addr, text);
-                match serde_json::from_str(&msg) {
-                    Ok(req) => {
-                        self.handle_request(user.clone(), req);
-                    }
-                    Err(e) => {
-                        log::error!("failed to parse message: {e} in {msg}");
-                        user.send(
-                            None,
-                            Message::Response(Err(Error::BadRequest(e.to_string()))),
-                        )
-                    }
-                };
+                WebSocketMessage::Text(msg) => Some(serde_json::from_str(msg).map_err(|e| {
+                    log::error!("failed to parse message: {e} in {msg}");
+                    e.to_string()
+                })),
+                WebSocketMessage::Binary(msg) => Some(rmp_serde::from_slice(msg).map_err(|e| {
+                    log::error!("failed to parse binary message: {e}");
+                    e.to_string()
+                })),
+                _ => None,
+            };
+
+            match req {
+                Some(Ok(req)) => self.handle_request(user.clone(), req),
+                Some(Err(e)) => user.send(None, Message::Response(Err(Error::BadRequest(e)))),
+                None => (),
             }
 
             futures::future::ok(())
@@ -386,14 +951,47 @@ impl Server {
 
         // wait for either sender or receiver to complete: this means the connection is closed.
         futures::future::select(fut_send, fut_recv).await;
+        *user.disconnected.lock() = Some(Instant::now());
+
+        // the user stays in the room for a while, in case it reconnects.
+        let in_room = user.inner.read().room.is_some();
+        if in_room && !self.resume_grace.is_zero() {
+            tokio::time::sleep(self.resume_grace).await;
+        }
 
-        if let Some(mut room) = user.room_mut() {
-            room.remove_user(&user);
+        let is_resumed = self
+            .users()
+            .get(&token)
+            .is_some_and(|u| !Arc::ptr_eq(u, &user));
+        if is_resumed {
+            return;
         }
 
+        let released = match user.room_mut() {
+            Some(mut room) => {
+                room.remove_user(&user);
+                room.locks.release(user.id)
+            }
+            None => false,
+        };
+
         self.broadcast_users(&user);
+        if released {
+            self.broadcast_locks(&user);
+        }
+        self.broadcast_to_others(
+            &user,
+            Message::Broadcast(Broadcast::UserLeft(user.presence())),
+        );
+        let room = user.inner.read().room.clone();
+        if let Some(map_name) = room.and_then(|room| self.room_name(&room)) {
+            self.broadcast_map_users(&map_name);
+        }
 
-        self.users().retain(|t, u| t != &token && !u.tx.is_closed());
+        // users in a room that are closed are in their grace period.
+        self.users().retain(|_, u| {
+            !Arc::ptr_eq(u, &user) && (!u.tx.is_closed() || u.inner.read().room.is_some())
+        });
 
         log::debug!(
             "simultaneous connections: {}/{}",
@@ -445,7 +1043,11 @@ impl Server {
                 if file.0.len() > self.max_map_size {
                     return Err(Error::MapTooBig);
                 }
-                twmap::TwMap::parse(&file.0).map_err(|e| Error::Map(e.to_string()))?
+                let mut map =
+                    twmap::TwMap::parse(&file.0).map_err(|e| Error::Map(e.to_string()))?;
+                map.load().map_err(|e| Error::Map(e.to_string()))?;
+                check_map_tiles(&map).map_err(|e| Error::Map(e.to_string()))?;
+                map
             }
             CreationMethod::Clone(clone_name) => {
                 let room = self.room(&clone_name)?;
@@ -473,15 +1075,16 @@ impl Server {
             }
         };
 
+        let mut map_buf = Vec::new();
+        map.save(&mut map_buf)
+            .map_err(|e| Error::Map(e.to_string()))?;
+
         let mut room = if let Some(maps_dir) = self.maps_dir.as_ref() {
             let path = maps_dir.join(map_name);
             let map_path = path.join("map.map");
 
             std::fs::create_dir(&path).map_err(|e| Error::Internal(e.to_string().into()))?;
-            let mut map_file = std::fs::File::create(&map_path)
-                .map_err(|e| Error::Internal(e.to_string().into()))?;
-            map.save(&mut map_file)
-                .map_err(|e| Error::Map(e.to_string()))?;
+            write_atomic(&map_path, &map_buf).map_err(|e| Error::Internal(e.to_string().into()))?;
 
             Room::new_from_dir(path).ok_or(Error::Internal("map creation failed".into()))?
         } else if let Some(data_dir) = self.data_dir.as_ref() {
@@ -493,10 +1096,7 @@ impl Server {
                 return Err(Error::MapNameTaken);
             }
 
-            let mut map_file = std::fs::File::create(&map_path)
-                .map_err(|e| Error::Internal(e.to_string().into()))?;
-            map.save(&mut map_file)
-                .map_err(|e| Error::Map(e.to_string()))?;
+            write_atomic(&map_path, &map_buf).map_err(|e| Error::Internal(e.to_string().into()))?;
 
             Room::new_from_files(map_path, None, Some(am_path))
                 .ok_or(Error::Internal("map creation failed".into()))?
@@ -506,10 +1106,7 @@ impl Server {
 
         room.config.public = creation.public.unwrap_or(true);
         if let Some(pwd) = &creation.password {
-            if !pwd.is_empty() {
-                room.config.password =
-                    Some(bcrypt::hash(pwd, bcrypt::DEFAULT_COST).map_err(|_| Error::Password)?);
-            }
+            room.config.password = hash_password(pwd)?;
         }
         room.save_config()?;
 
@@ -543,6 +1140,23 @@ impl Server {
         Ok(self.room(map_name)?.write().map().info.clone())
     }
 
+    pub fn get_check(&self, map_name: &str) -> Result<Vec<MapDiagnostic>, Error> {
+        Ok(check_map_report(self.room(map_name)?.write().map()))
+    }
+
+    pub fn get_lints(&self, map_name: &str) -> Result<Vec<MapLint>, Error> {
+        Ok(lint_map(self.room(map_name)?.write().map()))
+    }
+
+    pub fn get_reachability(&self, map_name: &str) -> Result<Reachability, Error> {
+        // the flood fill is slow on large maps, it runs on a copy of the physics group.
+        let group = physics_group(self.room(map_name)?.write().map());
+        group
+            .as_ref()
+            .and_then(reachability)
+            .ok_or(Error::NotFound("game layer"))
+    }
+
     pub fn edit_info(&self, map_name: &str, part_info: PartialInfo) -> Result<(), Error> {
         let room = self.room(map_name)?;
         let mut room = room.write();
@@ -560,24 +1174,21 @@ impl Server {
             name: map_cfg.name,
             public: map_cfg.public,
             password: map_cfg.password.is_some(),
+            viewer_password: map_cfg.viewer_password.is_some(),
+            owner_password: map_cfg.owner_password.is_some(),
             version: map_cfg.version,
         })
     }
 
     pub fn edit_config(&self, map_name: &str, part_conf: PartialConfig) -> Result<(), Error> {
-        let room = self.room(map_name)?.clone();
-        let mut room = room.write();
+        // hashing is costly, we don't want to lock the room mutex.
+        let hash = |pwd: &Option<String>| pwd.as_deref().map(hash_password).transpose();
+        let password = hash(&part_conf.password)?;
+        let viewer_password = hash(&part_conf.viewer_password)?;
+        let owner_password = hash(&part_conf.owner_password)?;
 
-        apply_partial!(part_conf => room.config, name, public);
-
-        if let Some(pwd) = &part_conf.password {
-            if !pwd.is_empty() {
-                room.config.password =
-                    Some(bcrypt::hash(pwd, bcrypt::DEFAULT_COST).map_err(|_| Error::Password)?);
-            } else {
-                room.config.password = None;
-            }
-        }
+        let room = self.room(map_name)?;
+        let mut room = room.write();
 
         if let Some(version) = part_conf.version {
             if version != room.map().version {
@@ -585,10 +1196,149 @@ impl Server {
             }
         }
 
-        room.save_config()?;
+        let mut config = room.config.clone();
+        apply_partial!(part_conf => config, name, public);
+        if let Some(password) = password {
+            config.password = password;
+        }
+        if let Some(password) = viewer_password {
+            config.viewer_password = password;
+        }
+        if let Some(password) = owner_password {
+            config.owner_password = password;
+        }
+
+        // the config in memory is only replaced once it was saved.
+        let prev_config = std::mem::replace(&mut room.config, config);
+        if let Err(e) = room.save_config() {
+            room.config = prev_config;
+            return Err(e);
+        }
         Ok(())
     }
 
+    pub fn get_invites(&self, map_name: &str) -> Result<Vec<Invite>, Error> {
+        let room = self.room(map_name)?;
+        let room = room.read();
+        Ok(room
+            .config
+            .invites
+            .iter()
+            .map(|inv| inv.invite.clone())
+            .collect())
+    }
+
+    pub fn create_invite(&self, map_name: &str, req: InviteReq) -> Result<InviteToken, Error> {
+        let invite = Invite {
+            id: random_token(8),
+            role: req.role,
+            expires: req.expires_in.map(|secs| timestamp_now() + secs),
+            max_uses: req.max_uses,
+            uses: 0,
+        };
+        let secret = random_token(24);
+        // hashing is costly, we don't want to lock the room mutex.
+        let hash = bcrypt::hash(&secret, bcrypt::DEFAULT_COST)
+            .map_err(|e| Error::Internal(e.to_string().into()))?;
+
+        let room = self.room(map_name)?;
+        let mut room = room.write();
+        room.config.invites.retain(|inv| inv.is_valid());
+        room.config.invites.push(MapInvite {
+            hash,
+            invite: invite.clone(),
+        });
+        room.save_config()?;
+
+        Ok(InviteToken {
+            token: format!("{}.{secret}", invite.id),
+            invite,
+        })
+    }
+
+    pub fn revoke_invite(&self, map_name: &str, invite_id: &str) -> Result<(), Error> {
+        let room = self.room(map_name)?;
+        let mut room = room.write();
+        let len = room.config.invites.len();
+        room.config.invites.retain(|inv| inv.invite.id != invite_id);
+        if room.config.invites.len() == len {
+            return Err(Error::NotFound("invite"));
+        }
+        self.invite_tokens
+            .lock()
+            .retain(|_, r| r.map_name != map_name || r.invite_id != invite_id);
+        room.save_config()
+    }
+
+    /// Counts a use of an invite, which must still be valid.
+    fn redeem_invite(&self, room: &RwLock<Room>, invite_id: &str) -> Result<(), Error> {
+        let mut room = room.write();
+        let invite = room
+            .config
+            .invites
+            .iter_mut()
+            .find(|inv| inv.invite.id == invite_id)
+            .filter(|inv| inv.is_valid())
+            .ok_or(Error::InvalidInvite)?;
+        invite.invite.uses += 1;
+        room.save_config()
+    }
+
+    /// Role granted by an invite token, used to access maps with the REST api.
+    /// The first request with a token redeems the invite, which counts as one use.
+    /// The token is then remembered, so that later requests skip bcrypt and keep
+    /// working until the invite expires or is revoked.
+    pub fn invite_role(
+        &self,
+        map_name: &str,
+        token: &str,
+        ip: Option<IpAddr>,
+    ) -> Result<Role, Error> {
+        let room = self.room(map_name)?;
+
+        let redeemed = self.invite_tokens.lock().get(token).cloned();
+        if let Some(redeemed) = redeemed.filter(|r| r.map_name == map_name) {
+            return room
+                .read()
+                .config
+                .invites
+                .iter()
+                .find(|inv| inv.invite.id == redeemed.invite_id)
+                .filter(|inv| !inv.is_expired())
+                .map(|inv| inv.invite.role)
+                .ok_or(Error::InvalidInvite);
+        }
+
+        // other tokens are rejected without calling bcrypt, there is nothing to throttle.
+        if !token.contains('.') {
+            return Err(Error::InvalidInvite);
+        }
+
+        // we clone the config because bcrypt::verify() is costly and we don't want to
+        // lock the room mutex.
+        let config = room.read().config.clone();
+        self.login_throttle.attempt(token, ip)?;
+        let invite = match config.invite(token) {
+            Ok(invite) => {
+                self.login_throttle.succeed(token, ip);
+                invite
+            }
+            Err(e) => {
+                log::info!("failed invite attempt on `{map_name}` from {ip:?}");
+                return Err(e);
+            }
+        };
+        self.redeem_invite(&room, &invite.id)?;
+        self.invite_tokens.lock().insert(
+            token.to_owned(),
+            RedeemedInvite {
+                map_name: map_name.to_owned(),
+                invite_id: invite.id.clone(),
+            },
+        );
+        Ok(invite.role)
+    }
+
     pub fn get_images(&self, map_name: &str) -> Result<Vec<String>, Error> {
         Ok(self
             .room(map_name)?
@@ -687,24 +1437,98 @@ impl Server {
         Ok(())
     }
 
-    pub fn get_envelopes(&self, map_name: &str) -> Result<Vec<String>, Error> {
+    pub fn get_sounds(&self, map_name: &str) -> Result<Vec<String>, Error> {
         Ok(self
             .room(map_name)?
             .write()
             .map()
-            .envelopes
+            .sounds
             .iter()
-            .map(|env| env.name().to_owned())
+            .map(|sound| sound.name.clone())
             .collect())
     }
 
-    pub fn get_envelope(&self, map_name: &str, env_index: u16) -> Result<twmap::Envelope, Error> {
-        Ok(self
-            .room(map_name)?
-            .write()
+    pub fn get_sound(&self, map_name: &str, sound_index: u16) -> Result<Vec<u8>, Error> {
+        let room = self.room(map_name)?;
+        let mut room = room.write();
+
+        let sound = room
             .map()
-            .envelopes
-            .get(env_index as usize)
+            .sounds
+            .get(sound_index as usize)
+            .ok_or(Error::SoundNotFound)?;
+
+        Ok(sound.data.unwrap_ref().clone())
+    }
+
+    pub fn put_sound(&self, map_name: &str, sound_name: &str, file: Vec<u8>) -> Result<(), Error> {
+        let room = self.room(map_name)?;
+        let mut room = room.write();
+
+        if sound_name.len() > twmap::Sound::MAX_NAME_LENGTH {
+            return Err(Error::InvalidFileName);
+        }
+
+        if !check_file_name(sound_name) {
+            return Err(Error::InvalidFileName);
+        }
+
+        if room.map().sounds.len() == 64usize {
+            return Err(Error::MaxSounds);
+        }
+
+        opus_headers::parse_from_read(&file[..]).map_err(|_| Error::InvalidSound)?;
+
+        let sound = twmap::Sound {
+            name: sound_name.to_owned(),
+            data: twmap::CompressedData::Loaded(file),
+        };
+
+        sound
+            .check(room.map(), &mut ())
+            .map_err(|e| Error::Map(e.to_string()))?;
+
+        room.map().sounds.push(sound);
+        Ok(())
+    }
+
+    pub fn delete_sound(&self, map_name: &str, sound_index: u16) -> Result<(), Error> {
+        let room = self.room(map_name)?;
+        let mut room = room.write();
+        let map = room.map();
+
+        if sound_index as usize >= map.sounds.len() {
+            return Err(Error::SoundNotFound);
+        }
+
+        if map.is_sound_in_use(sound_index) {
+            return Err(Error::SoundInUse);
+        }
+
+        map.sounds.remove(sound_index as usize);
+        map.edit_sound_indices(|i| i.map(|i| if i > sound_index { i - 1 } else { i }));
+
+        Ok(())
+    }
+
+    pub fn get_envelopes(&self, map_name: &str) -> Result<Vec<String>, Error> {
+        Ok(self
+            .room(map_name)?
+            .write()
+            .map()
+            .envelopes
+            .iter()
+            .map(|env| env.name().to_owned())
+            .collect())
+    }
+
+    pub fn get_envelope(&self, map_name: &str, env_index: u16) -> Result<twmap::Envelope, Error> {
+        Ok(self
+            .room(map_name)?
+            .write()
+            .map()
+            .envelopes
+            .get(env_index as usize)
             .ok_or(Error::EnvelopeNotFound)?
             .to_owned())
     }
@@ -1015,6 +1839,11 @@ impl Server {
                 apply_partial!(part_layer => layer, name, detail, image);
                 twmap::Layer::Quads(layer)
             }
+            PartialLayer::Sounds(part_layer) => {
+                let mut layer = twmap::SoundsLayer::default();
+                apply_partial!(part_layer => layer, name, detail, sound);
+                twmap::Layer::Sounds(layer)
+            }
             PartialLayer::Front(_) => create_physics_layer!(Front, FrontLayer),
             PartialLayer::Tele(_) => create_physics_layer!(Tele, TeleLayer),
             PartialLayer::Speedup(_) => create_physics_layer!(Speedup, SpeedupLayer),
@@ -1089,6 +1918,9 @@ impl Server {
                 (twmap::Layer::Quads(layer), PartialLayer::Quads(part_layer)) => {
                     apply_partial!(part_layer => layer, name, detail, image);
                 }
+                (twmap::Layer::Sounds(layer), PartialLayer::Sounds(part_layer)) => {
+                    apply_partial!(part_layer => layer, name, detail, sound);
+                }
                 (twmap::Layer::Front(_), PartialLayer::Front(part_layer)) => {
                     apply_physics_dimensions!(part_layer);
                 }
@@ -1139,11 +1971,14 @@ impl Server {
         Ok(())
     }
 
+    /// Returns the tiles of a tilemap layer, or only the tiles inside `rect` if given.
     pub fn get_tiles(
         &self,
         map_name: &str,
         group_index: u16,
         layer_index: u16,
+        rect: Option<vek::Rect<u32, u32>>,
+        compression: Compression,
     ) -> Result<Box<[u8]>, Error> {
         let room = self.room(map_name)?;
         let mut room = room.write();
@@ -1159,14 +1994,16 @@ impl Server {
 
         macro_rules! layer_data {
             ($layer: ident) => {{
-                let data = $layer
-                    .tiles
-                    .unwrap_ref()
-                    .to_owned()
-                    .into_raw_vec()
-                    .into_boxed_slice();
-                let buf = ViewAsBytes::into_boxed_bytes(data);
-                Ok(buf)
+                let shape = twmap::TilemapLayer::tiles($layer).shape();
+                let rect = rect.unwrap_or(vek::Rect::new(0, 0, shape.w as u32, shape.h as u32));
+
+                if rect.x as usize + rect.w as usize > shape.w
+                    || rect.y as usize + rect.h as usize > shape.h
+                {
+                    return Err(Error::TilesOutOfBounds);
+                }
+
+                compress(tiles_region_bytes($layer, rect), compression)
             }};
         }
 
@@ -1201,6 +2038,7 @@ impl Server {
             .layers
             .get_mut(layer_index as usize)
             .ok_or(Error::LayerNotFound)?;
+        let kind = layer.kind();
 
         macro_rules! apply_tiles {
             ($layer:ident) => {{
@@ -1214,15 +2052,17 @@ impl Server {
                     return Err(Error::TilesOutOfBounds);
                 }
 
-                let tiles = structview::View::view_slice(&part_tiles.tiles.0)
-                    .map_err(|_| Error::InvalidTiles)?;
+                let tile_size = std::mem::size_of_val(&$layer.tiles.unwrap_ref()[(0, 0)]);
+                let data = decompress(
+                    &part_tiles.tiles.0,
+                    part_tiles.compression,
+                    w * h * tile_size,
+                )?;
+                let tiles = structview::View::view_slice(&data).map_err(|_| Error::InvalidTiles)?;
                 let tiles = ndarray::ArrayView::from_shape((h, w), tiles)
                     .map_err(|_| Error::InvalidTiles)?;
 
-                // TODO
-                // for tile in tiles.iter() {
-                //     tile.check()
-                // }
+                check_tiles(tiles, kind, (x, y)).map_err(|e| Error::Map(e.to_string()))?;
 
                 let mut view = twmap::TilemapLayer::tiles_mut($layer)
                     .unwrap_mut()
@@ -1372,6 +2212,130 @@ impl Server {
         }
     }
 
+    pub fn get_source(
+        &self,
+        map_name: &str,
+        group_index: u16,
+        layer_index: u16,
+        source_index: u16,
+    ) -> Result<twmap::SoundSource, Error> {
+        let room = self.room(map_name)?;
+        let mut room = room.write();
+        let map = room.map();
+        let layer = map
+            .groups
+            .get(group_index as usize)
+            .ok_or(Error::GroupNotFound)?
+            .layers
+            .get(layer_index as usize)
+            .ok_or(Error::LayerNotFound)?;
+
+        if let twmap::Layer::Sounds(layer) = layer {
+            Ok(layer
+                .sources
+                .get(source_index as usize)
+                .ok_or(Error::SourceNotFound)?
+                .clone())
+        } else {
+            Err(Error::WrongLayerType)
+        }
+    }
+
+    pub fn put_source(
+        &self,
+        map_name: &str,
+        group_index: u16,
+        layer_index: u16,
+        source: twmap::SoundSource,
+    ) -> Result<(), Error> {
+        source.check_self()?;
+        let room = self.room(map_name)?;
+        let mut room = room.write();
+        let map = room.map();
+        source.check_map(map)?;
+        let layer = map
+            .groups
+            .get_mut(group_index as usize)
+            .ok_or(Error::GroupNotFound)?
+            .layers
+            .get_mut(layer_index as usize)
+            .ok_or(Error::LayerNotFound)?;
+
+        if let twmap::Layer::Sounds(layer) = layer {
+            if layer.sources.len() == u16::MAX as usize {
+                Err(Error::MaxSources)
+            } else {
+                layer.sources.push(source);
+                Ok(())
+            }
+        } else {
+            Err(Error::WrongLayerType)
+        }
+    }
+
+    pub fn edit_source(
+        &self,
+        map_name: &str,
+        group_index: u16,
+        layer_index: u16,
+        source_index: u16,
+        source: twmap::SoundSource,
+    ) -> Result<(), Error> {
+        source.check_self()?;
+        let room = self.room(map_name)?;
+        let mut room = room.write();
+        let map = room.map();
+        source.check_map(map)?;
+        let layer = map
+            .groups
+            .get_mut(group_index as usize)
+            .ok_or(Error::GroupNotFound)?
+            .layers
+            .get_mut(layer_index as usize)
+            .ok_or(Error::LayerNotFound)?;
+
+        if let twmap::Layer::Sounds(layer) = layer {
+            let cur_source = layer
+                .sources
+                .get_mut(source_index as usize)
+                .ok_or(Error::SourceNotFound)?;
+            *cur_source = source;
+            Ok(())
+        } else {
+            Err(Error::WrongLayerType)
+        }
+    }
+
+    pub fn delete_source(
+        &self,
+        map_name: &str,
+        group_index: u16,
+        layer_index: u16,
+        source_index: u16,
+    ) -> Result<(), Error> {
+        let room = self.room(map_name)?;
+        let mut room = room.write();
+        let map = room.map();
+        let layer = map
+            .groups
+            .get_mut(group_index as usize)
+            .ok_or(Error::GroupNotFound)?
+            .layers
+            .get_mut(layer_index as usize)
+            .ok_or(Error::LayerNotFound)?;
+
+        if let twmap::Layer::Sounds(layer) = layer {
+            if source_index as usize >= layer.sources.len() {
+                Err(Error::SourceNotFound)
+            } else {
+                layer.sources.remove(source_index as usize);
+                Ok(())
+            }
+        } else {
+            Err(Error::WrongLayerType)
+        }
+    }
+
     pub fn get_automappers(&self, map_name: &str) -> Result<Vec<AutomapperDetail>, Error> {
         let room = self.room(map_name)?;
         let room = room.read();
@@ -1519,7 +2483,7 @@ impl Server {
         let kind = automapper_kind(&path).ok_or(Error::InvalidFileName)?;
 
         std::fs::create_dir_all(room.automapper_path().unwrap()).ok();
-        std::fs::write(&path, file).map_err(|e| Error::Internal(e.to_string().into()))?;
+        write_atomic(&path, file.as_bytes()).map_err(|e| Error::Internal(e.to_string().into()))?;
         log::info!("automapper write");
 
         if kind == AutomapperKind::RulesPP {
@@ -1654,17 +2618,7 @@ impl Server {
             return Err(Error::ImageNotFound);
         }
 
-        map.edit_image_indices(|i| {
-            i.map(|i| {
-                if i == src {
-                    tgt
-                } else if src < i && i <= tgt {
-                    i - 1
-                } else {
-                    i
-                }
-            })
-        });
+        map.edit_image_indices(|i| i.map(|i| move_index(i, src, tgt)));
 
         let env = map.images.remove(src as usize);
         map.images.insert(tgt as usize, env);
@@ -1685,17 +2639,7 @@ impl Server {
             return Err(Error::EnvelopeNotFound);
         }
 
-        map.edit_env_indices(|i| {
-            i.map(|i| {
-                if i == src {
-                    tgt
-                } else if src < i && i <= tgt {
-                    i - 1
-                } else {
-                    i
-                }
-            })
-        });
+        map.edit_env_indices(|i| i.map(|i| move_index(i, src, tgt)));
 
         let env = map.envelopes.remove(src as usize);
         map.envelopes.insert(tgt as usize, env);
@@ -1778,7 +2722,7 @@ impl Server {
             if src.2 as usize >= layer.quads.len() || tgt as usize >= layer.quads.len() {
                 Err(Error::QuadNotFound)
             } else {
-                let quad = layer.quads.remove(src.0 as usize);
+                let quad = layer.quads.remove(src.2 as usize);
                 layer.quads.insert(tgt as usize, quad);
                 Ok(())
             }
@@ -1787,88 +2731,465 @@ impl Server {
         }
     }
 
+    pub fn move_sound(&self, map_name: &str, src: u16, tgt: u16) -> Result<(), Error> {
+        let room = self.room(map_name)?;
+        let mut room = room.write();
+        let map = room.map();
+
+        if src as usize >= map.sounds.len() || tgt as usize >= map.sounds.len() {
+            return Err(Error::SoundNotFound);
+        }
+
+        map.edit_sound_indices(|i| i.map(|i| move_index(i, src, tgt)));
+
+        let sound = map.sounds.remove(src as usize);
+        map.sounds.insert(tgt as usize, sound);
+
+        Ok(())
+    }
+
+    pub fn move_source(&self, map_name: &str, src: (u16, u16, u16), tgt: u16) -> Result<(), Error> {
+        let room = self.room(map_name)?;
+        let mut room = room.write();
+        let map = room.map();
+
+        let layer = map
+            .groups
+            .get_mut(src.0 as usize)
+            .ok_or(Error::GroupNotFound)?
+            .layers
+            .get_mut(src.1 as usize)
+            .ok_or(Error::LayerNotFound)?;
+
+        if let twmap::Layer::Sounds(layer) = layer {
+            if src.2 as usize >= layer.sources.len() || tgt as usize >= layer.sources.len() {
+                Err(Error::SourceNotFound)
+            } else {
+                let source = layer.sources.remove(src.2 as usize);
+                layer.sources.insert(tgt as usize, source);
+                Ok(())
+            }
+        } else {
+            Err(Error::WrongLayerType)
+        }
+    }
+
     pub fn get_users(&self, map_name: &str) -> Result<usize, Error> {
         let room = self.room(map_name)?;
         let room = room.read();
         Ok(room.user_count())
     }
 
-    pub fn get_cursors(
+    pub fn get_user_list(&self, map_name: &str) -> Result<Vec<Presence>, Error> {
+        let room = self.room(map_name)?;
+        let room = room.read();
+        Ok(room.users().map(|(_, user)| user.presence()).collect())
+    }
+
+    pub fn set_name(&self, user: &User, profile: UserProfile) -> Result<(), Error> {
+        profile.check_self()?;
+        let mut inner = user.inner.write();
+        inner.name = Some(profile.name);
+        inner.color = profile.color;
+        Ok(())
+    }
+
+    pub fn chat(&self, text: &str) -> Result<(), Error> {
+        if text.chars().count() > ChatMessage::MAX_TEXT_LENGTH {
+            return Err(Error::FieldTooLong("text"));
+        }
+        if text.trim().is_empty() {
+            return Err(Error::Invalid("text"));
+        }
+        Ok(())
+    }
+
+    pub fn get_comments(&self, map_name: &str) -> Result<Vec<Comment>, Error> {
+        Ok(self.room(map_name)?.read().comments.clone())
+    }
+
+    pub fn create_comment(
         &self,
         map_name: &str,
         user: &User,
-    ) -> Result<HashMap<String, Cursor>, Error> {
+        req: CommentReq,
+    ) -> Result<Comment, Error> {
+        if req.text.chars().count() > Comment::MAX_TEXT_LENGTH {
+            return Err(Error::FieldTooLong("text"));
+        }
+        if req.text.trim().is_empty() {
+            return Err(Error::Invalid("text"));
+        }
+
         let room = self.room(map_name)?;
-        let room = room.read();
+        let mut room = room.write();
 
-        let user_id = room.user(&user.token).ok_or(Error::NotJoined)?.id;
+        room.map()
+            .groups
+            .get(req.group as usize)
+            .ok_or(Error::GroupNotFound)?
+            .layers
+            .get(req.layer as usize)
+            .ok_or(Error::LayerNotFound)?;
 
-        let cursors = room
-            .users()
-            .filter(|(_, v)| v.id != user_id)
-            .filter_map(|(_, v)| {
-                v.inner
-                    .read()
-                    .cursor
-                    .as_ref()
-                    .map(|c| (v.id.to_string(), c.clone()))
-            })
-            .collect();
+        let comment = Comment {
+            id: room.comments.iter().map(|c| c.id + 1).max().unwrap_or(0),
+            author: user.inner.read().name.clone(),
+            timestamp: timestamp_now(),
+            resolved: false,
+            content: req,
+        };
+        room.comments.push(comment.clone());
+        room.save_comments()?;
 
-        Ok(cursors)
+        Ok(comment)
     }
 
-    pub fn set_cursor(&self, user: &User, cursor: Cursor) -> Result<(), Error> {
-        let room = user.room().ok_or(Error::MapNotFound)?;
-        let room_user = room
-            .user(&user.token)
-            .ok_or(Error::Internal("server error".into()))?;
-        room_user.inner.write().cursor = Some(cursor);
+    pub fn resolve_comment(&self, map_name: &str, comment_id: u32) -> Result<(), Error> {
+        let room = self.room(map_name)?;
+        let mut room = room.write();
 
-        Ok(())
+        let comment = room
+            .comments
+            .iter_mut()
+            .find(|c| c.id == comment_id)
+            .ok_or(Error::CommentNotFound)?;
+        comment.resolved = true;
+
+        room.save_comments()
     }
 
-    pub fn save_map(&self, map_name: &str) -> Result<(), Error> {
+    pub fn delete_comment(&self, map_name: &str, comment_id: u32) -> Result<(), Error> {
         let room = self.room(map_name)?;
         let mut room = room.write();
-        room.save_map(self.max_map_size)
-    }
 
-    pub fn user_join(&self, user: Arc<User>, join: &JoinReq) -> Result<(), Error> {
-        if user.room().is_some() {
-            return Err(Error::AlreadyJoined);
+        let len = room.comments.len();
+        room.comments.retain(|c| c.id != comment_id);
+        if room.comments.len() == len {
+            return Err(Error::CommentNotFound);
         }
 
-        // we clone the pwd hash because bcrypt::verify() is costly and we don't want to
-        // lock the room mutex.
-        let pwd_hash = self.room(&join.name)?.read().config.password.clone();
-        match (&join.password, &pwd_hash) {
-            (Some(pwd), Some(hash)) => {
-                if !bcrypt::verify(pwd, hash).map_err(|_| Error::Password)? {
-                    return Err(Error::Password);
-                }
-            }
-            (Some(pwd), None) if pwd.is_empty() => (),
-            (Some(_), None) | (None, Some(_)) => {
-                return Err(Error::Password);
-            }
-            (None, None) => (),
-        };
+        room.save_comments()
+    }
 
-        let room = self.room(&join.name)?;
-        room.write().add_user(user.clone());
-        user.inner.write().room = Some(room);
+    pub fn get_locks(&self, map_name: &str) -> Result<Vec<LayerLock>, Error> {
+        Ok(self.room(map_name)?.read().locks.list())
+    }
 
-        log::info!("{} joined `{}`", user.token, join.name);
+    pub fn lock_layer(
+        &self,
+        map_name: &str,
+        user: &User,
+        group_index: u16,
+        layer_index: u16,
+    ) -> Result<(), Error> {
+        let room = self.room(map_name)?;
+        let mut room = room.write();
+
+        room.map()
+            .groups
+            .get(group_index as usize)
+            .ok_or(Error::GroupNotFound)?
+            .layers
+            .get(layer_index as usize)
+            .ok_or(Error::LayerNotFound)?;
+
+        room.locks.lock((group_index, layer_index), user.id)
+    }
+
+    pub fn unlock_layer(
+        &self,
+        map_name: &str,
+        user: &User,
+        group_index: u16,
+        layer_index: u16,
+    ) -> Result<(), Error> {
+        let room = self.room(map_name)?;
+        let mut room = room.write();
+        room.locks.unlock((group_index, layer_index), user.id)
+    }
+
+    pub fn get_cursors(
+        &self,
+        map_name: &str,
+        user: Option<&User>,
+    ) -> Result<HashMap<String, Cursor>, Error> {
+        let room = self.room(map_name)?;
+        let room = room.read();
+
+        // the cursor of the user asking is not included, REST callers get all cursors.
+        let user_id = match user {
+            Some(user) => Some(room.user(&user.token).ok_or(Error::NotJoined)?.id),
+            None => None,
+        };
+
+        let cursors = room
+            .users()
+            .filter(|(_, v)| Some(v.id) != user_id)
+            .filter_map(|(_, v)| {
+                v.inner
+                    .read()
+                    .cursor
+                    .as_ref()
+                    .map(|c| (v.id.to_string(), c.clone()))
+            })
+            .collect();
+
+        Ok(cursors)
+    }
+
+    pub fn set_cursor(&self, user: &User, cursor: Cursor) -> Result<(), Error> {
+        let room = user.room().ok_or(Error::MapNotFound)?;
+        let room_user = room
+            .user(&user.token)
+            .ok_or(Error::Internal("server error".into()))?;
+        room_user.inner.write().cursor = Some(cursor);
+        room_user.cursor_changed.store(true, Ordering::Relaxed);
 
         Ok(())
     }
 
+    /// Broadcasts the cursors that changed since the last call to the other users of their room.
+    pub fn broadcast_cursors(&self) {
+        let rooms: Vec<_> = self.rooms().values().cloned().collect();
+
+        for room in rooms {
+            let changed: Vec<_> = {
+                let room = room.read();
+                // nobody would see the cursor, it stays pending until another user joins.
+                if room.user_count() < 2 {
+                    continue;
+                }
+                room.users()
+                    .map(|(_, user)| user)
+                    .filter(|user| user.cursor_changed.swap(false, Ordering::Relaxed))
+                    .collect()
+            };
+
+            for user in changed {
+                let msg = Message::Broadcast(Broadcast::Cursor(user.presence()));
+                self.broadcast_to_others(&user, msg);
+            }
+        }
+    }
+
+    pub async fn run_cursors(self: Arc<Self>, period: std::time::Duration) {
+        let mut interval = tokio::time::interval(period);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+        loop {
+            interval.tick().await;
+            self.broadcast_cursors();
+        }
+    }
+
+    /// Replays requests from the history and broadcasts them to the whole room.
+    /// If one of them fails, the map is rolled back and the history is cleared.
+    fn replay_history(
+        &self,
+        user: &Arc<User>,
+        room: &Arc<RwLock<Room>>,
+        reqs: Vec<Request>,
+    ) -> Result<(), Error> {
+        let snapshot = room.write().map().clone();
+
+        for req in &reqs {
+            if let Err(e) = self.do_request(Some(user.clone()), req.clone()) {
+                // the history does not match the map anymore.
+                let mut room = room.write();
+                room.restore_snapshot(snapshot);
+                room.history.clear();
+                return Err(e);
+            }
+        }
+
+        let mut room = room.write();
+        room.mark_dirty();
+        for req in reqs {
+            room.revisions.push(req.clone());
+            room.locks.rebase(&req);
+            self.broadcast_to_room(&room, Message::Request(req));
+        }
+        Ok(())
+    }
+
+    pub fn undo(&self, user: &Arc<User>) -> Result<(), Error> {
+        let room = user.inner.read().room.clone().ok_or(Error::NotJoined)?;
+        let op = {
+            let mut room = room.write();
+            let op = room.history.undo().ok_or(Error::NothingToUndo)?;
+            if let Err(e) = op
+                .reverse
+                .iter()
+                .try_for_each(|r| room.locks.check(r, user.id))
+            {
+                room.history.redo();
+                return Err(e);
+            }
+            op
+        };
+        self.replay_history(user, &room, op.reverse)
+    }
+
+    pub fn redo(&self, user: &Arc<User>) -> Result<(), Error> {
+        let room = user.inner.read().room.clone().ok_or(Error::NotJoined)?;
+        let op = {
+            let mut room = room.write();
+            let op = room.history.redo().ok_or(Error::NothingToRedo)?;
+            if let Err(e) = op
+                .forward
+                .iter()
+                .try_for_each(|r| room.locks.check(r, user.id))
+            {
+                room.history.undo();
+                return Err(e);
+            }
+            op
+        };
+        self.replay_history(user, &room, op.forward)
+    }
+
+    pub fn save_map(&self, map_name: &str) -> Result<(), Error> {
+        let room = self.room(map_name)?;
+        let mut room = room.write();
+        room.save_map(self.max_map_size)
+    }
+
+    pub fn restore_recovery(&self, map_name: &str) -> Result<(), Error> {
+        let room = self.room(map_name)?;
+        let mut room = room.write();
+        room.restore_recovery()
+    }
+
+    pub fn delete_recovery(&self, map_name: &str) -> Result<(), Error> {
+        let room = self.room(map_name)?;
+        let mut room = room.write();
+        room.delete_recovery()
+    }
+
+    pub fn get_backups(&self, map_name: &str) -> Result<Vec<String>, Error> {
+        let room = self.room(map_name)?;
+        let room = room.read();
+        Ok(room.backups())
+    }
+
+    pub fn restore_backup(&self, map_name: &str, name: &str) -> Result<(), Error> {
+        let room = self.room(map_name)?;
+        let mut room = room.write();
+        room.restore_backup(name)
+    }
+
+    /// Saves every map with unsaved edits and makes a backup of it.
+    pub fn autosave(&self) {
+        let rooms: Vec<_> = self.rooms().values().cloned().collect();
+
+        for room in rooms {
+            let edit_lock = room.read().edit_lock();
+            let _guard = edit_lock.lock();
+            let mut room = room.write();
+
+            if !room.is_dirty() {
+                continue;
+            }
+
+            let res = room
+                .save_map(self.max_map_size)
+                .and_then(|()| room.write_backup(self.max_backups));
+
+            match res {
+                Ok(()) => {
+                    log::debug!("map autosaved `{}`", room.name());
+                    self.broadcast_to_room(&room, Message::Broadcast(Broadcast::Saved));
+                }
+                Err(e) => log::error!("failed to autosave `{}`: {e}", room.name()),
+            }
+        }
+    }
+
+    pub async fn run_autosave(self: Arc<Self>, period: std::time::Duration) {
+        let mut interval = tokio::time::interval(period);
+        interval.tick().await; // the first tick completes immediately
+        loop {
+            interval.tick().await;
+            self.autosave();
+        }
+    }
+
+    pub fn user_join(&self, user: Arc<User>, join: &JoinReq) -> Result<Presence, Error> {
+        if user.room().is_some() {
+            return Err(Error::AlreadyJoined);
+        }
+
+        if let Some(profile) = &join.user {
+            profile.check_self()?;
+        }
+
+        // we clone the config because bcrypt::verify() is costly and we don't want to
+        // lock the room mutex.
+        let config = self.room(&join.name)?.read().config.clone();
+        self.login_throttle.attempt(&user.token, user.ip)?;
+        let res = match &join.invite {
+            Some(token) => config
+                .invite(token)
+                .map(|invite| (invite.role, Some(invite.id.clone()))),
+            None if join
+                .password
+                .as_deref()
+                .is_some_and(|pwd| self.is_admin_password(pwd)) =>
+            {
+                Ok((Role::Owner, None))
+            }
+            None => config
+                .role(join.password.as_deref())
+                .map(|role| (role, None)),
+        };
+        let (role, invite_id) = match res {
+            Ok(res) => {
+                self.login_throttle.succeed(&user.token, user.ip);
+                res
+            }
+            Err(e) => {
+                if matches!(e, Error::Password | Error::InvalidInvite) {
+                    log::info!("failed join attempt on `{}` by {}", join.name, user.token);
+                } else {
+                    self.login_throttle.cancel(&user.token, user.ip);
+                }
+                return Err(e);
+            }
+        };
+
+        if let Some(profile) = &join.user {
+            self.set_name(&user, profile.clone())?;
+        }
+
+        let room = self.room(&join.name)?;
+        if let Some(id) = invite_id {
+            self.redeem_invite(&room, &id)?;
+        }
+        room.write().add_user(user.clone());
+        let has_recovery = room.read().has_recovery();
+        {
+            let mut inner = user.inner.write();
+            inner.room = Some(room);
+            inner.role = Some(role);
+        }
+
+        log::info!("{} joined `{}` as {role:?}", user.token, join.name);
+
+        if has_recovery {
+            user.send(None, Message::Broadcast(Broadcast::RecoveryAvailable));
+        }
+
+        Ok(user.presence())
+    }
+
     pub fn user_leave(&self, user: &User, map_name: &str) -> Result<(), Error> {
-        let res = match user.room_mut() {
-            Some(mut room) => {
-                if room.name() == map_name {
+        let joined = user.inner.read().room.clone();
+        let res = match joined {
+            Some(room) => {
+                if self.room_name(&room).as_deref() == Some(map_name) {
+                    let mut room = room.write();
                     room.remove_user(user);
+                    room.locks.release(user.id);
                     Ok(())
                 } else {
                     Err(Error::NotJoined)
@@ -1876,8 +3197,1067 @@ impl Server {
             }
             None => Err(Error::NotJoined),
         };
-        user.inner.write().room.take();
+        {
+            let mut inner = user.inner.write();
+            inner.room.take();
+            inner.role = None;
+            inner.cursor = None;
+        }
+        user.cursor_changed.store(false, Ordering::Relaxed);
         log::info!("{} left `{}`", user.token, map_name);
         res
     }
 }
+
+/// Role needed in the map to do a request, or None if the request does not act on a map.
+fn required_role(req: &Request) -> Option<Role> {
+    match req {
+        Request::ListMaps
+        | Request::Config(_)
+        | Request::JoinMap(_)
+        | Request::SetName(_)
+        | Request::LeaveMap(_)
+        | Request::CreateMap(..) => None,
+        Request::DeleteMap(_)
+        | Request::Edit(EditReq::Config(_))
+        | Request::Get(GetReq::Invites)
+        | Request::CreateInvite(_)
+        | Request::RevokeInvite(_) => Some(Role::Owner),
+        Request::GetMap(_)
+        | Request::Get(_)
+        | Request::Cursor(_)
+        | Request::Chat(_)
+        | Request::CreateComment(_) => Some(Role::Viewer),
+        Request::Save
+        | Request::Undo
+        | Request::Redo
+        | Request::Batch(_)
+        | Request::Create(_)
+        | Request::Edit(_)
+        | Request::Delete(_)
+        | Request::Move(_)
+        | Request::RestoreRecovery
+        | Request::DeleteRecovery
+        | Request::RestoreBackup(_)
+        | Request::ResolveComment(_)
+        | Request::DeleteComment(_)
+        | Request::LockLayer(..)
+        | Request::UnlockLayer(..) => Some(Role::Editor),
+    }
+}
+
+/// Whether an edit request changes the map itself, i.e. leaves it with unsaved edits.
+/// The config and the automappers are stored next to the map.
+fn modifies_map(req: &Request) -> bool {
+    !matches!(
+        req,
+        Request::Edit(EditReq::Config(_))
+            | Request::Create(CreateReq::Automapper(..))
+            | Request::Delete(DeleteReq::Automapper(_))
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures::channel::mpsc::UnboundedReceiver;
+
+    use super::*;
+    use crate::testing::{TestServer, MAP};
+
+    fn group() -> Box<PartialGroup> {
+        Box::new(PartialGroup {
+            name: Some("group".to_owned()),
+            ..Default::default()
+        })
+    }
+
+    fn private() -> Box<PartialConfig> {
+        Box::new(PartialConfig {
+            public: Some(false),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn viewer_cannot_edit() {
+        let server = TestServer::new("viewer-cannot-edit");
+        server.set_passwords("view", "edit", "own");
+        let owner = server.join(Some("own")).unwrap();
+        server
+            .request(&owner, Request::Create(CreateReq::Group(group())))
+            .unwrap();
+        let map = server.map();
+
+        let viewer = server.join(Some("view")).unwrap();
+        assert_eq!(viewer.inner.read().role, Some(Role::Viewer));
+        assert!(server
+            .request(&viewer, Request::Get(GetReq::Groups))
+            .is_ok());
+
+        let reqs = [
+            Request::Create(CreateReq::Group(group())),
+            Request::Edit(EditReq::Group(1, group())),
+            Request::Delete(DeleteReq::Group(1)),
+            Request::Move(MoveReq::Group(1, 0)),
+            Request::Batch(vec![Request::Delete(DeleteReq::Group(1))]),
+            Request::Save,
+            Request::Edit(EditReq::Config(private())),
+            Request::DeleteMap(MAP.to_owned()),
+        ];
+        for req in reqs {
+            let res = server.request(&viewer, req.clone());
+            assert!(matches!(res, Err(Error::Forbidden)), "{req:?}: {res:?}");
+        }
+        assert_eq!(server.map(), map);
+        assert!(server.server.room(MAP).is_ok());
+    }
+
+    #[test]
+    fn editor_cannot_administrate() {
+        let server = TestServer::new("editor-cannot-administrate");
+        server.set_passwords("view", "edit", "own");
+
+        let editor = server.join(Some("edit")).unwrap();
+        assert_eq!(editor.inner.read().role, Some(Role::Editor));
+        server
+            .request(&editor, Request::Create(CreateReq::Group(group())))
+            .unwrap();
+        server.request(&editor, Request::Save).unwrap();
+
+        let reqs = [
+            Request::Edit(EditReq::Config(private())),
+            Request::DeleteMap(MAP.to_owned()),
+        ];
+        for req in reqs {
+            let res = server.request(&editor, req.clone());
+            assert!(matches!(res, Err(Error::Forbidden)), "{req:?}: {res:?}");
+        }
+        assert!(server.room().read().config.public);
+
+        let owner = server.join(Some("own")).unwrap();
+        server
+            .request(&owner, Request::Edit(EditReq::Config(private())))
+            .unwrap();
+        assert!(!server.room().read().config.public);
+        server
+            .request(&owner, Request::DeleteMap(MAP.to_owned()))
+            .unwrap();
+        assert!(server.server.room(MAP).is_err());
+    }
+
+    #[test]
+    fn editor_password_without_owner() {
+        let server = TestServer::with_args(
+            "editor-password-without-owner",
+            &["--admin-password", "admin"],
+        );
+        let hash = Some(bcrypt::hash("edit", 4).unwrap());
+        server.room().write().config.password = hash;
+
+        let editor = server.join(Some("edit")).unwrap();
+        assert_eq!(editor.inner.read().role, Some(Role::Editor));
+        let reqs = [
+            Request::Edit(EditReq::Config(private())),
+            Request::DeleteMap(MAP.to_owned()),
+        ];
+        for req in reqs {
+            let res = server.request(&editor, req.clone());
+            assert!(matches!(res, Err(Error::Forbidden)), "{req:?}: {res:?}");
+        }
+
+        let admin = server.join(Some("admin")).unwrap();
+        assert_eq!(admin.inner.read().role, Some(Role::Owner));
+        server
+            .request(&admin, Request::DeleteMap(MAP.to_owned()))
+            .unwrap();
+        assert!(server.server.room(MAP).is_err());
+    }
+
+    #[test]
+    fn closed_map_needs_password() {
+        let server = TestServer::new("closed-map-needs-password");
+        let hash = Some(bcrypt::hash("view", 4).unwrap());
+        server.room().write().config.viewer_password = hash;
+
+        assert!(matches!(server.join(None), Err(Error::Password)));
+        let user = server.user();
+        let res = server.request(&user, Request::GetMap(MAP.to_owned()));
+        assert!(matches!(res, Err(Error::Unauthorized)), "{res:?}");
+    }
+
+    #[test]
+    fn put_sound_rejects_non_opus() {
+        let server = TestServer::new("put-sound-rejects-non-opus");
+        let user = server.join(None).unwrap();
+
+        let file = Base64(b"OggS not really an opus file".to_vec());
+        let req = Request::Create(CreateReq::Sound("sound".to_owned(), file));
+        let res = server.request(&user, req);
+        assert!(matches!(res, Err(Error::InvalidSound)), "{res:?}");
+        assert!(server.map().sounds.is_empty());
+    }
+
+    fn game_tiles(server: &TestServer) -> Box<[u8]> {
+        let map = server.map();
+        let layer = map.find_physics_layer::<twmap::GameLayer>().unwrap();
+        tiles_region_bytes(layer, vek::Rect::new(0, 0, 8, 8))
+    }
+
+    #[test]
+    fn get_tiles_zlib() {
+        let server = TestServer::new("get-tiles-zlib");
+        let user = server.join(None).unwrap();
+        let tiles = Tiles {
+            rect: vek::Rect::new(2, 3, 4, 2),
+            tiles: Base64([1, 0, 0, 0].repeat(8)),
+            compression: Compression::None,
+        };
+        let req = Request::Edit(EditReq::Tiles(0, 0, Box::new(tiles)));
+        server.request(&user, req).unwrap();
+        let expected = game_tiles(&server);
+
+        let req = Request::Get(GetReq::Tiles(0, 0, Compression::Zlib));
+        let Ok(Response::Tiles(Base64(data))) = server.request(&user, req) else {
+            panic!("expected tiles");
+        };
+        assert_ne!(data, *expected);
+        let data = decompress(&data, Compression::Zlib, expected.len()).unwrap();
+        assert_eq!(*data, *expected);
+
+        // clients that do not ask for compression get raw tiles.
+        let req = r#"{ "type": "get/tiles", "content": [0, 0] }"#;
+        let req: Request = serde_json::from_str(req).unwrap();
+        let Ok(Response::Tiles(Base64(data))) = server.request(&user, req) else {
+            panic!("expected tiles");
+        };
+        assert_eq!(*data, *expected);
+    }
+
+    #[test]
+    fn edit_tiles_rejects_oversized_payload() {
+        let server = TestServer::new("edit-tiles-rejects-oversized-payload");
+        let user = server.join(None).unwrap();
+        let expected = game_tiles(&server);
+
+        // 2x2 tiles of 4 bytes, one byte and a few megabytes too many.
+        for len in [17, 16 << 20] {
+            let data = compress(vec![0; len].into(), Compression::Zlib).unwrap();
+            let tiles = Tiles {
+                rect: vek::Rect::new(0, 0, 2, 2),
+                tiles: Base64(data.into()),
+                compression: Compression::Zlib,
+            };
+            let req = Request::Edit(EditReq::Tiles(0, 0, Box::new(tiles)));
+            let res = server.request(&user, req);
+            assert!(matches!(res, Err(Error::InvalidTiles)), "{res:?}");
+        }
+        assert_eq!(game_tiles(&server), expected);
+    }
+
+    #[test]
+    fn only_map_edits_mark_dirty() {
+        let server = TestServer::new("only-map-edits-mark-dirty");
+        let user = server.join(None).unwrap();
+
+        let reqs = [
+            Request::Edit(EditReq::Config(private())),
+            Request::Create(CreateReq::Automapper(
+                "grass.rules".to_owned(),
+                String::new(),
+            )),
+            Request::Delete(DeleteReq::Automapper("grass.rules".to_owned())),
+        ];
+        for req in reqs {
+            server.request(&user, req.clone()).unwrap();
+            assert!(!server.room().read().is_dirty(), "{req:?}");
+        }
+
+        server
+            .request(&user, Request::Create(CreateReq::Group(group())))
+            .unwrap();
+        assert!(server.room().read().is_dirty());
+    }
+
+    /// A new connection with the token of a user.
+    fn connection(token: &str) -> (User, UnboundedReceiver<WebSocketMessage>) {
+        let (tx, rx) = unbounded();
+        (User::new(token.to_owned(), tx), rx)
+    }
+
+    /// Closes the connection of a user, which starts its resume grace period.
+    fn disconnect(user: &User, since: std::time::Duration) {
+        user.tx.close_channel();
+        *user.disconnected.lock() = Some(Instant::now() - since);
+    }
+
+    #[test]
+    fn resume_within_grace_period() {
+        let server = TestServer::new("resume-within-grace-period");
+        let user = server.join(None).unwrap();
+        server.request(&user, Request::LockLayer(0, 0)).unwrap();
+        disconnect(&user, std::time::Duration::from_secs(1));
+        assert!(server.server.can_resume(&user.token));
+
+        let (conn, _rx) = connection(&user.token);
+        let resumed = server.server.connect(conn, Some(0)).unwrap();
+        assert_eq!(resumed.id, user.id);
+        assert_eq!(resumed.inner.read().role, user.inner.read().role);
+        assert!(Arc::ptr_eq(
+            &server.server.user(&user.token).unwrap(),
+            &resumed
+        ));
+        assert_eq!(server.room().read().user_count(), 1);
+        // the locks of the session are kept.
+        let other = server.join(None).unwrap();
+        let res = server.request(&other, Request::Edit(EditReq::Automap(0, 0)));
+        assert!(matches!(res, Err(Error::LayerLocked)), "{res:?}");
+    }
+
+    #[test]
+    fn resume_after_grace_period() {
+        let server = TestServer::new("resume-after-grace-period");
+        let user = server.join(None).unwrap();
+        disconnect(&user, server.server.resume_grace);
+        assert!(!server.server.can_resume(&user.token));
+
+        let (conn, _rx) = connection(&user.token);
+        let res = server.server.connect(conn, Some(0));
+        assert!(matches!(res, Err(Error::Unauthorized)));
+        assert!(Arc::ptr_eq(
+            &server.server.user(&user.token).unwrap(),
+            &user
+        ));
+    }
+
+    #[test]
+    fn resume_live_session() {
+        let server = TestServer::new("resume-live-session");
+        let user = server.join(None).unwrap();
+        assert!(!server.server.can_resume(&user.token));
+
+        let (conn, _rx) = connection(&user.token);
+        let res = server.server.connect(conn, Some(0));
+        assert!(matches!(res, Err(Error::Unauthorized)));
+        assert!(!user.tx.is_closed());
+        assert!(Arc::ptr_eq(
+            &server.server.user(&user.token).unwrap(),
+            &user
+        ));
+    }
+
+    #[test]
+    fn max_users_counts_resumed_connections() {
+        let server = TestServer::with_args(
+            "max-users-counts-resumed-connections",
+            &["--max-connections", "1"],
+        );
+        let user = server.join(None).unwrap();
+
+        let (conn, _rx) = connection("other");
+        let res = server.server.connect(conn, None);
+        assert!(matches!(res, Err(Error::MaxUsers)));
+
+        // a session in its grace period does not hold a connection.
+        disconnect(&user, std::time::Duration::ZERO);
+        let (conn, _rx) = connection("other");
+        server.server.connect(conn, None).unwrap();
+
+        let (conn, _rx) = connection(&user.token);
+        let res = server.server.connect(conn, Some(0));
+        assert!(matches!(res, Err(Error::MaxUsers)));
+        assert!(server.server.can_resume(&user.token));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn autosave_loop() {
+        let server = TestServer::with_args("autosave-loop", &["--max-backups", "2"]);
+        let user = server.join(None).unwrap();
+        let period = Duration::from_secs(60);
+        let autosave = tokio::spawn(server.server.clone().run_autosave(period));
+
+        // maps without unsaved edits are not saved.
+        tokio::time::sleep(period + Duration::from_secs(1)).await;
+        assert!(server.room().read().backups().is_empty());
+        assert!(server.received(&user).is_empty());
+
+        for i in 1..=3 {
+            let req = Request::Create(CreateReq::Group(Box::default()));
+            server.request(&user, req).unwrap();
+            assert!(server.room().read().is_dirty());
+
+            tokio::time::sleep(period).await;
+            let room = server.room();
+            let room = room.read();
+            assert!(!room.is_dirty());
+            assert_eq!(room.backups().len(), i.min(2));
+            let saved = twmap::TwMap::parse(&std::fs::read(room.map_path()).unwrap()).unwrap();
+            assert_eq!(saved.groups.len(), 1 + i);
+
+            let received = server.received(&user);
+            assert!(
+                matches!(
+                    &received[..],
+                    [SendPacket {
+                        content: Message::Broadcast(Broadcast::Saved),
+                        ..
+                    }]
+                ),
+                "{received:?}"
+            );
+        }
+        autosave.abort();
+    }
+
+    /// The broadcasts received by a user since the last call.
+    fn broadcasts(server: &TestServer, user: &User) -> Vec<Broadcast> {
+        server
+            .received(user)
+            .into_iter()
+            .filter_map(|packet| match packet.content {
+                Message::Broadcast(broadcast) => Some(broadcast),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn cursor(x: f32) -> Request {
+        Request::Cursor(Box::new(Cursor {
+            point: vek::Vec2::new(x, 0.0),
+            group: 0,
+            layer: 1,
+        }))
+    }
+
+    fn is_cursor_of(broadcast: &Broadcast, user: &User, x: f32) -> bool {
+        matches!(
+            broadcast,
+            Broadcast::Cursor(Presence { id, cursor: Some(cursor), .. })
+                if *id == user.id.to_string() && cursor.point.x == x && cursor.layer == 1
+        )
+    }
+
+    #[test]
+    fn cursor_broadcast() {
+        let server = TestServer::new("cursor-broadcast");
+        let user = server.join(None).unwrap();
+
+        // the cursor is held until another user can see it.
+        server.request(&user, cursor(1.0)).unwrap();
+        server.server.broadcast_cursors();
+        let other = server.join(None).unwrap();
+        let lobby = server.user();
+        server.received(&user);
+
+        server.server.broadcast_cursors();
+        let received = broadcasts(&server, &other);
+        assert!(
+            matches!(&received[..], [b] if is_cursor_of(b, &user, 1.0)),
+            "{received:?}"
+        );
+        assert!(broadcasts(&server, &user).is_empty());
+        assert!(broadcasts(&server, &lobby).is_empty());
+
+        // the last cursor of an interval is sent once, unchanged cursors are not sent.
+        server.request(&user, cursor(2.0)).unwrap();
+        server.request(&user, cursor(3.0)).unwrap();
+        server.server.broadcast_cursors();
+        server.server.broadcast_cursors();
+        let received = broadcasts(&server, &other);
+        assert!(
+            matches!(&received[..], [b] if is_cursor_of(b, &user, 3.0)),
+            "{received:?}"
+        );
+    }
+
+    #[test]
+    fn cursor_needs_a_room() {
+        let server = TestServer::new("cursor-needs-a-room");
+        server.set_passwords("view", "edit", "own");
+        let viewer = server.join(Some("view")).unwrap();
+        let other = server.join(Some("view")).unwrap();
+        server.request(&viewer, cursor(1.0)).unwrap();
+
+        let res = server.request(&server.user(), cursor(1.0));
+        assert!(matches!(res, Err(Error::MapNotFound)), "{res:?}");
+
+        // get/cursors lists the cursors of the other users.
+        let res = server.request(&other, Request::Get(GetReq::Cursors));
+        let Ok(Response::Cursors(cursors)) = res else {
+            panic!("{res:?}");
+        };
+        assert_eq!(cursors.keys().collect::<Vec<_>>(), [&viewer.id.to_string()]);
+        let res = server.request(&viewer, Request::Get(GetReq::Cursors));
+        assert!(matches!(res, Ok(Response::Cursors(c)) if c.is_empty()));
+    }
+
+    #[test]
+    fn join_and_leave_presence() {
+        let server = TestServer::new("join-and-leave-presence");
+        let user = server.join(None).unwrap();
+        let other = server.join(None).unwrap();
+        let other_id = other.id.to_string();
+
+        let received = broadcasts(&server, &user);
+        assert!(
+            matches!(
+                &received[..],
+                [Broadcast::Users(2), Broadcast::UserJoined(p)]
+                    if p.id == other_id && p.role == Some(Role::Owner)
+            ),
+            "{received:?}"
+        );
+        assert!(broadcasts(&server, &other).is_empty());
+
+        server
+            .request(&other, Request::LeaveMap(MAP.to_owned()))
+            .unwrap();
+        let received = broadcasts(&server, &user);
+        assert!(
+            matches!(
+                &received[..],
+                [Broadcast::Users(1), Broadcast::UserLeft(p), Broadcast::Locks(locks)]
+                    if p.id == other_id && locks.is_empty()
+            ),
+            "{received:?}"
+        );
+        // the user is back in the lobby.
+        let received = broadcasts(&server, &other);
+        assert!(
+            matches!(&received[..], [Broadcast::MapUsers(d)] if d.users == 1),
+            "{received:?}"
+        );
+    }
+
+    fn profile(name: &str) -> UserProfile {
+        UserProfile {
+            name: name.to_owned(),
+            color: Some(vek::Rgb::new(255, 0, 0)),
+        }
+    }
+
+    #[test]
+    fn set_name_broadcast() {
+        let server = TestServer::new("set-name-broadcast");
+        let user = server.join(None).unwrap();
+        let other = server.join(None).unwrap();
+        let lobby = server.user();
+        server.received(&user);
+
+        server
+            .request(&user, Request::SetName(profile("alice")))
+            .unwrap();
+        let received = broadcasts(&server, &other);
+        assert!(
+            matches!(
+                &received[..],
+                [Broadcast::UserUpdated(p)] if p.id == user.id.to_string()
+                    && p.name.as_deref() == Some("alice")
+                    && p.color == Some(vek::Rgb::new(255, 0, 0))
+            ),
+            "{received:?}"
+        );
+        assert!(broadcasts(&server, &user).is_empty());
+        assert!(broadcasts(&server, &lobby).is_empty());
+
+        for (name, err) in [
+            ("  ", Error::Invalid("name")),
+            (&"a".repeat(33), Error::FieldTooLong("name")),
+        ] {
+            let res = server.request(&user, Request::SetName(profile(name)));
+            assert_eq!(res.unwrap_err().to_string(), err.to_string());
+        }
+        assert_eq!(user.presence().name.as_deref(), Some("alice"));
+        assert!(broadcasts(&server, &other).is_empty());
+
+        // users in the lobby pick their name before joining.
+        server
+            .request(&lobby, Request::SetName(profile("bob")))
+            .unwrap();
+        assert_eq!(lobby.presence().name.as_deref(), Some("bob"));
+        assert!(broadcasts(&server, &user).is_empty());
+    }
+
+    #[test]
+    fn get_users() {
+        let server = TestServer::new("get-users");
+        server.set_passwords("view", "edit", "own");
+        let join = |password: &str, user| JoinReq {
+            name: MAP.to_owned(),
+            password: Some(password.to_owned()),
+            user,
+            invite: None,
+        };
+        let viewer = server
+            .join_with(join("view", Some(profile("viewer"))))
+            .unwrap();
+        let editor = server.join(Some("edit")).unwrap();
+
+        let res = server.request(&viewer, Request::Get(GetReq::Users));
+        let Ok(Response::Users(mut users)) = res else {
+            panic!("{res:?}");
+        };
+        users.sort_by_key(|p| p.role);
+        let users: Vec<_> = users
+            .iter()
+            .map(|p| (&p.id[..], p.name.as_deref(), p.role))
+            .collect();
+        assert_eq!(
+            users,
+            [
+                (
+                    &viewer.id.to_string()[..],
+                    Some("viewer"),
+                    Some(Role::Viewer)
+                ),
+                (&editor.id.to_string()[..], None, Some(Role::Editor)),
+            ]
+        );
+
+        let res = server.request(&server.user(), Request::Get(GetReq::Users));
+        assert!(matches!(res, Err(Error::MapNotFound)), "{res:?}");
+
+        // the profile of a join is checked before the user enters the room.
+        let res = server.join_with(join("view", Some(profile(&"a".repeat(33)))));
+        assert!(matches!(res, Err(Error::FieldTooLong("name"))));
+        let res = server.request(&viewer, Request::Get(GetReq::Users));
+        assert!(matches!(res, Ok(Response::Users(u)) if u.len() == 2));
+    }
+
+    fn blank_map() -> Box<MapCreation> {
+        Box::new(MapCreation {
+            version: None,
+            public: None,
+            password: None,
+            method: CreationMethod::Blank { w: 8, h: 8 },
+        })
+    }
+
+    #[test]
+    fn lobby_map_list_broadcasts() {
+        let server = TestServer::new("lobby-map-list-broadcasts");
+        let user = server.join(None).unwrap();
+        let lobby = server.user();
+        let creator = server.user();
+
+        server
+            .request(&creator, Request::CreateMap("new".to_owned(), blank_map()))
+            .unwrap();
+        for user in [&lobby, &creator] {
+            let received = broadcasts(&server, user);
+            assert!(
+                matches!(&received[..], [Broadcast::MapCreated(name)] if name == "new"),
+                "{received:?}"
+            );
+        }
+
+        server
+            .request(&creator, Request::DeleteMap("new".to_owned()))
+            .unwrap();
+        let received = broadcasts(&server, &lobby);
+        assert!(
+            matches!(&received[..], [Broadcast::MapDeleted(name)] if name == "new"),
+            "{received:?}"
+        );
+        // users in a room do not follow the map list.
+        assert!(broadcasts(&server, &user).is_empty());
+    }
+
+    #[test]
+    fn lobby_map_users_broadcasts() {
+        let server = TestServer::new("lobby-map-users-broadcasts");
+        let lobby = server.user();
+        let user = server.join(None).unwrap();
+        let received = broadcasts(&server, &lobby);
+        assert!(
+            matches!(&received[..], [Broadcast::MapUsers(d)] if d.name == MAP && d.users == 1),
+            "{received:?}"
+        );
+
+        // maps turning private are announced once, then their users are hidden.
+        server
+            .request(&user, Request::Edit(EditReq::Config(private())))
+            .unwrap();
+        let received = broadcasts(&server, &lobby);
+        assert!(
+            matches!(
+                &received[..],
+                [Broadcast::MapConfig(name, config)] if name == MAP && !config.public
+            ),
+            "{received:?}"
+        );
+        server.join(None).unwrap();
+        let rename = Box::new(PartialConfig {
+            name: Some("renamed".to_owned()),
+            ..Default::default()
+        });
+        server
+            .request(&user, Request::Edit(EditReq::Config(rename)))
+            .unwrap();
+        assert!(broadcasts(&server, &lobby).is_empty());
+
+        let public = Box::new(PartialConfig {
+            public: Some(true),
+            ..Default::default()
+        });
+        server
+            .request(&user, Request::Edit(EditReq::Config(public)))
+            .unwrap();
+        server
+            .request(&user, Request::LeaveMap(MAP.to_owned()))
+            .unwrap();
+        let received = broadcasts(&server, &lobby);
+        assert!(
+            matches!(
+                &received[..],
+                [Broadcast::MapConfig(_, config), Broadcast::MapUsers(d)]
+                    if config.public && config.name == "renamed" && d.users == 1
+            ),
+            "{received:?}"
+        );
+    }
+
+    #[test]
+    fn chat_broadcast() {
+        let server = TestServer::new("chat-broadcast");
+        let user = server.join(None).unwrap();
+        let other = server.join(None).unwrap();
+        let lobby = server.user();
+        server
+            .request(&user, Request::SetName(profile("alice")))
+            .unwrap();
+        server.received(&other);
+        server.received(&user);
+
+        server
+            .request(&user, Request::Chat("hello".to_owned()))
+            .unwrap();
+        let received = broadcasts(&server, &other);
+        assert!(
+            matches!(
+                &received[..],
+                [Broadcast::Chat(msg)] if msg.id == user.id.to_string()
+                    && msg.name.as_deref() == Some("alice")
+                    && msg.text == "hello"
+            ),
+            "{received:?}"
+        );
+        assert!(broadcasts(&server, &user).is_empty());
+        assert!(broadcasts(&server, &lobby).is_empty());
+
+        for (text, err) in [
+            (" ", Error::Invalid("text")),
+            (&"a".repeat(501), Error::FieldTooLong("text")),
+        ] {
+            let res = server.request(&user, Request::Chat(text.to_owned()));
+            assert_eq!(res.unwrap_err().to_string(), err.to_string());
+        }
+        assert!(broadcasts(&server, &other).is_empty());
+
+        // the lobby has no room to chat in.
+        let res = server.request(&lobby, Request::Chat("hello".to_owned()));
+        assert!(res.is_err());
+    }
+
+    fn comment(text: &str) -> Request {
+        Request::CreateComment(Box::new(CommentReq {
+            group: 0,
+            layer: 0,
+            x: 1.0,
+            y: 2.0,
+            text: text.to_owned(),
+        }))
+    }
+
+    #[test]
+    fn comments_broadcast() {
+        let server = TestServer::new("comments-broadcast");
+        server.set_passwords("view", "edit", "own");
+        let viewer = server.join(Some("view")).unwrap();
+        let editor = server.join(Some("edit")).unwrap();
+        let lobby = server.user();
+        server.received(&viewer);
+
+        // viewers can leave feedback, the whole room sees it.
+        let res = server.request(&viewer, comment("too hard"));
+        assert!(matches!(res, Ok(Response::Comment(c)) if c.id == 0 && !c.resolved));
+        for user in [&viewer, &editor] {
+            let received = broadcasts(&server, user);
+            assert!(
+                matches!(
+                    &received[..],
+                    [Broadcast::Comments(c)] if c.len() == 1 && c[0].content.text == "too hard"
+                ),
+                "{received:?}"
+            );
+        }
+        assert!(broadcasts(&server, &lobby).is_empty());
+
+        let res = server.request(&viewer, comment(" "));
+        assert!(matches!(res, Err(Error::Invalid("text"))), "{res:?}");
+        let res = server.request(
+            &viewer,
+            Request::CreateComment(Box::new(CommentReq {
+                group: 0,
+                layer: 9,
+                x: 0.0,
+                y: 0.0,
+                text: "lost".to_owned(),
+            })),
+        );
+        assert!(matches!(res, Err(Error::LayerNotFound)), "{res:?}");
+
+        // only editors resolve and delete comments.
+        for req in [Request::ResolveComment(0), Request::DeleteComment(0)] {
+            let res = server.request(&viewer, req);
+            assert!(matches!(res, Err(Error::Forbidden)), "{res:?}");
+        }
+        assert!(broadcasts(&server, &editor).is_empty());
+
+        server.request(&editor, Request::ResolveComment(0)).unwrap();
+        for user in [&viewer, &editor] {
+            let received = broadcasts(&server, user);
+            assert!(
+                matches!(&received[..], [Broadcast::Comments(c)] if c[0].resolved),
+                "{received:?}"
+            );
+        }
+        server.request(&editor, comment("fixed")).unwrap();
+        server.request(&editor, Request::DeleteComment(0)).unwrap();
+        let res = server.request(&editor, Request::DeleteComment(0));
+        assert!(matches!(res, Err(Error::CommentNotFound)), "{res:?}");
+
+        // comments are stored next to the map.
+        let dir = server.room().read().dir_path().unwrap().to_owned();
+        let room = Room::new_from_dir(dir).unwrap();
+        assert_eq!(room.comments.len(), 1);
+        assert_eq!(room.comments[0].id, 1);
+        assert_eq!(room.comments[0].content.text, "fixed");
+    }
+
+    fn invite(server: &TestServer, owner: &Arc<User>, req: InviteReq) -> InviteToken {
+        match server.request(owner, Request::CreateInvite(req)) {
+            Ok(Response::Invite(invite)) => *invite,
+            _ => panic!("expected an invite"),
+        }
+    }
+
+    fn join_invite(server: &TestServer, token: &str) -> Result<Arc<User>, Error> {
+        server.join_with(JoinReq {
+            name: MAP.to_owned(),
+            password: None,
+            user: None,
+            invite: Some(token.to_owned()),
+        })
+    }
+
+    #[test]
+    fn invites() {
+        let server = TestServer::new("invites");
+        server.set_passwords("view", "edit", "own");
+        let owner = server.join(Some("own")).unwrap();
+        let editor = server.join(Some("edit")).unwrap();
+
+        let req = InviteReq {
+            role: Role::Viewer,
+            expires_in: None,
+            max_uses: Some(1),
+        };
+        for req in [
+            Request::CreateInvite(req.clone()),
+            Request::Get(GetReq::Invites),
+            Request::RevokeInvite("id".to_owned()),
+        ] {
+            let res = server.request(&editor, req);
+            assert!(matches!(res, Err(Error::Forbidden)), "{res:?}");
+        }
+
+        // invites grant their role until they are used up.
+        let once = invite(&server, &owner, req);
+        let viewer = join_invite(&server, &once.token).unwrap();
+        assert_eq!(viewer.inner.read().role, Some(Role::Viewer));
+        let res = join_invite(&server, &once.token);
+        assert!(matches!(res, Err(Error::InvalidInvite)));
+        let res = server.request(&owner, Request::Get(GetReq::Invites));
+        assert!(
+            matches!(&res, Ok(Response::Invites(i)) if i.len() == 1 && i[0].uses == 1),
+            "{res:?}"
+        );
+
+        let expired = invite(
+            &server,
+            &owner,
+            InviteReq {
+                role: Role::Editor,
+                expires_in: Some(0),
+                max_uses: None,
+            },
+        );
+        let res = join_invite(&server, &expired.token);
+        assert!(matches!(res, Err(Error::InvalidInvite)));
+
+        // the secret part of the token is checked, not only the invite id.
+        let revoked = invite(
+            &server,
+            &owner,
+            InviteReq {
+                role: Role::Editor,
+                expires_in: None,
+                max_uses: None,
+            },
+        );
+        let forged = format!("{}.secret", revoked.invite.id);
+        assert!(matches!(
+            join_invite(&server, &forged),
+            Err(Error::InvalidInvite)
+        ));
+        let user = join_invite(&server, &revoked.token).unwrap();
+        assert_eq!(user.inner.read().role, Some(Role::Editor));
+
+        // invites are stored in the map config, invalid ones are dropped when a new
+        // invite is created.
+        let dir = server.room().read().dir_path().unwrap().to_owned();
+        let room = Room::new_from_dir(dir).unwrap();
+        let stored: Vec<_> = room.config.invites.iter().map(|i| &i.invite).collect();
+        assert!(
+            matches!(&stored[..], [i] if i.id == revoked.invite.id && i.uses == 1),
+            "{stored:?}"
+        );
+
+        server
+            .request(&owner, Request::RevokeInvite(revoked.invite.id.clone()))
+            .unwrap();
+        let res = join_invite(&server, &revoked.token);
+        assert!(matches!(res, Err(Error::InvalidInvite)));
+        let res = server.request(&owner, Request::RevokeInvite(revoked.invite.id));
+        assert!(matches!(res, Err(Error::NotFound("invite"))), "{res:?}");
+    }
+
+    #[test]
+    fn msgpack_round_trip() {
+        let server = TestServer::new("msgpack-round-trip");
+        let user = server.user_with(Encoding::MessagePack);
+        let join = JoinReq {
+            name: MAP.to_owned(),
+            password: None,
+            user: None,
+            invite: None,
+        };
+        server.request(&user, Request::JoinMap(join)).unwrap();
+        let other = server.join(None).unwrap();
+        server.received(&user);
+
+        // tile bytes are sent raw, without base64.
+        let data = [1, 0, 0, 0].repeat(4);
+        let tiles = Tiles {
+            rect: vek::Rect::new(1, 1, 2, 2),
+            tiles: Base64(data.clone()),
+            compression: Compression::None,
+        };
+        let req = Request::Edit(EditReq::Tiles(0, 0, Box::new(tiles)));
+        let bytes = rmp_serde::to_vec_named(&RecvPacket::new(Some(1), req)).unwrap();
+        assert!(bytes.windows(data.len()).any(|w| w == data));
+        let packet: RecvPacket = rmp_serde::from_slice(&bytes).unwrap();
+        let resp = server.server.apply_request(Some(user.clone()), &packet);
+        assert!(matches!(resp.content, Message::Response(Ok(Response::Ok))));
+
+        // the edit is sent to the other users, each with their own encoding.
+        assert!(server.received(&user).is_empty());
+        {
+            let received = server.received(&other);
+            assert!(
+                matches!(
+                    &received[..],
+                    [SendPacket {
+                        content: Message::Request(Request::Edit(EditReq::Tiles(0, 0, t))),
+                        ..
+                    }] if t.rect == vek::Rect::new(1, 1, 2, 2) && t.tiles.0 == data
+                ),
+                "{received:?}"
+            );
+        }
+
+        let expected = game_tiles(&server);
+        let req = Request::Get(GetReq::Tiles(0, 0, Compression::None));
+        let bytes = rmp_serde::to_vec_named(&RecvPacket::new(Some(2), req)).unwrap();
+        let packet: RecvPacket = rmp_serde::from_slice(&bytes).unwrap();
+        let resp = server.server.apply_request(Some(user.clone()), &packet);
+        let WebSocketMessage::Binary(bytes) = encode_packet(&resp, user.encoding) else {
+            panic!("expected a binary message");
+        };
+        assert!(bytes.windows(expected.len()).any(|w| w == &*expected));
+        // responses are untagged, clients match them with their request id. Raw
+        // bytes are read back as the first variant holding bytes.
+        let resp: SendPacket = rmp_serde::from_slice(&bytes).unwrap();
+        assert!(
+            matches!(
+                &resp.content,
+                Message::Response(Ok(Response::Map(Base64(d)))) if **d == *expected
+            ),
+            "{resp:?}"
+        );
+    }
+
+    #[test]
+    fn tiles_region() {
+        let server = TestServer::new("tiles-region");
+        let user = server.join(None).unwrap();
+        let get = |rect| {
+            let req = Request::Get(GetReq::TilesRegion(0, 0, rect, Compression::None));
+            match server.request(&user, req) {
+                Ok(Response::Tiles(Base64(data))) => Ok(data),
+                Ok(resp) => panic!("expected tiles: {resp:?}"),
+                Err(e) => Err(e),
+            }
+        };
+
+        // a 3x2 rectangle of distinct tiles in the bottom right corner.
+        let ids = [1, 2, 3, 9, 11, 12];
+        let data: Vec<u8> = ids.iter().flat_map(|&id| [id, 0, 0, 0]).collect();
+        let rect = vek::Rect::new(5, 6, 3, 2);
+        let tiles = Tiles {
+            rect,
+            tiles: Base64(data.clone()),
+            compression: Compression::None,
+        };
+        let req = Request::Edit(EditReq::Tiles(0, 0, Box::new(tiles)));
+        server.request(&user, req).unwrap();
+        assert_eq!(get(rect).unwrap(), data);
+
+        // rows of a smaller region are taken from the rows of the layer.
+        let part: Vec<u8> = [2, 3, 11, 12]
+            .iter()
+            .flat_map(|&id| [id, 0, 0, 0])
+            .collect();
+        assert_eq!(get(vek::Rect::new(6, 6, 2, 2)).unwrap(), part);
+        assert_eq!(
+            get(vek::Rect::new(0, 0, 8, 8)).unwrap(),
+            *game_tiles(&server)
+        );
+        assert!(get(vek::Rect::new(8, 8, 0, 0)).unwrap().is_empty());
+
+        let map = server.map();
+        for rect in [
+            vek::Rect::new(7, 7, 2, 2),
+            vek::Rect::new(8, 0, 1, 1),
+            vek::Rect::new(0, 0, 8, 9),
+            vek::Rect::new(u32::MAX, 0, 1, 1),
+            vek::Rect::new(0, 1, 1, u32::MAX),
+        ] {
+            let res = get(rect);
+            assert!(matches!(res, Err(Error::TilesOutOfBounds)), "{rect:?}");
+
+            let len = (rect.w as usize * rect.h as usize).min(64) * 4;
+            let tiles = Tiles {
+                rect,
+                tiles: Base64(vec![1; len]),
+                compression: Compression::None,
+            };
+            let req = Request::Edit(EditReq::Tiles(0, 0, Box::new(tiles)));
+            let res = server.request(&user, req);
+            assert!(matches!(res, Err(Error::TilesOutOfBounds)), "{rect:?}");
+        }
+        assert_eq!(server.map(), map);
+    }
+}
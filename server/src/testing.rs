@@ -0,0 +1,212 @@
+// Helpers shared by the unit tests of the server modules.
+
+use std::{collections::HashMap, path::PathBuf, sync::Arc};
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{ws::Message as WebSocketMessage, ConnectInfo},
+    http::{self, header, Method, StatusCode},
+};
+use clap::Parser;
+use futures::channel::mpsc::{unbounded, UnboundedReceiver};
+use parking_lot::{Mutex, RwLock};
+use tower::ServiceExt;
+use uuid::Uuid;
+
+use crate::{
+    cli::Cli,
+    error::Error,
+    protocol::*,
+    room::Room,
+    router::Router,
+    server::{Server, User},
+};
+
+/// An empty directory for a single test, removed at the end of the test.
+pub struct TestDir(pub PathBuf);
+
+impl TestDir {
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("twwe-test-{}-{name}", std::process::id()));
+        std::fs::remove_dir_all(&path).ok();
+        std::fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+
+    pub fn file_names(&self) -> Vec<String> {
+        let mut names: Vec<_> = std::fs::read_dir(&self.0)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        std::fs::remove_dir_all(&self.0).ok();
+    }
+}
+
+pub const MAP: &str = "test";
+
+/// A server with a single blank map named [`MAP`], stored in a test directory.
+pub struct TestServer {
+    pub server: Arc<Server>,
+    cli: Cli,
+    receivers: Mutex<HashMap<Uuid, UnboundedReceiver<WebSocketMessage>>>, // by user id
+    _dir: TestDir,
+}
+
+impl TestServer {
+    pub fn new(name: &str) -> Self {
+        Self::with_args(name, &[])
+    }
+
+    /// A server started with extra command line arguments.
+    pub fn with_args(name: &str, args: &[&str]) -> Self {
+        let dir = TestDir::new(name);
+        let maps = dir.0.to_string_lossy().into_owned();
+        let cli = Cli::parse_from(["twwe-server", "--maps", &maps].iter().chain(args));
+        let server = Arc::new(Server::new(&cli));
+        let creation = MapCreation {
+            version: None,
+            public: None,
+            password: None,
+            method: CreationMethod::Blank { w: 8, h: 8 },
+        };
+        server.create_map(MAP, creation).unwrap();
+
+        Self {
+            server,
+            cli,
+            receivers: Default::default(),
+            _dir: dir,
+        }
+    }
+
+    pub fn room(&self) -> Arc<RwLock<Room>> {
+        self.server.room(MAP).unwrap()
+    }
+
+    pub fn map(&self) -> twmap::TwMap {
+        self.room().write().map().clone()
+    }
+
+    /// Sets the viewer, editor and owner passwords of the map.
+    pub fn set_passwords(&self, viewer: &str, editor: &str, owner: &str) {
+        // the lowest cost keeps the tests fast.
+        let hash = |pwd| Some(bcrypt::hash(pwd, 4).unwrap());
+        let room = self.room();
+        let mut room = room.write();
+        room.config.viewer_password = hash(viewer);
+        room.config.password = hash(editor);
+        room.config.owner_password = hash(owner);
+    }
+
+    pub fn user(&self) -> Arc<User> {
+        self.user_with(Encoding::Json)
+    }
+
+    /// A new user whose packets are sent with the given encoding.
+    pub fn user_with(&self, encoding: Encoding) -> Arc<User> {
+        let (tx, rx) = unbounded();
+        let token = Uuid::new_v4().to_string();
+        let mut user = User::new(token.clone(), tx);
+        user.encoding = encoding;
+        let user = Arc::new(user);
+        self.receivers.lock().insert(user.id, rx);
+        self.server.users().insert(token, user.clone());
+        user
+    }
+
+    /// The packets sent to a user since the last call.
+    pub fn received(&self, user: &User) -> Vec<SendPacket> {
+        let mut receivers = self.receivers.lock();
+        let rx = receivers.get_mut(&user.id).expect("not a user of the test");
+        std::iter::from_fn(|| rx.try_next().ok().flatten())
+            .map(|msg| match msg {
+                WebSocketMessage::Text(text) => serde_json::from_str(&text).unwrap(),
+                WebSocketMessage::Binary(bytes) => rmp_serde::from_slice(&bytes).unwrap(),
+                msg => panic!("unexpected message: {msg:?}"),
+            })
+            .collect()
+    }
+
+    /// A new user that joined the map with a password. The join is announced to the
+    /// other users.
+    pub fn join(&self, password: Option<&str>) -> Result<Arc<User>, Error> {
+        self.join_with(JoinReq {
+            name: MAP.to_owned(),
+            password: password.map(str::to_owned),
+            user: None,
+            invite: None,
+        })
+    }
+
+    /// A new user that joined a map, e.g. with a profile or an invite.
+    pub fn join_with(&self, join: JoinReq) -> Result<Arc<User>, Error> {
+        let user = self.user();
+        self.request(&user, Request::JoinMap(join))?;
+        Ok(user)
+    }
+
+    /// Sends a request on the websocket of a user.
+    pub fn request(&self, user: &Arc<User>, req: Request) -> Result<Response, Error> {
+        self.request_at(user, None, req)
+    }
+
+    /// Sends a request made on an older revision of the map.
+    pub fn request_at(
+        &self,
+        user: &Arc<User>,
+        base_revision: Option<u64>,
+        req: Request,
+    ) -> Result<Response, Error> {
+        let mut packet = RecvPacket::new(None, req);
+        packet.base_revision = base_revision;
+        match self
+            .server
+            .apply_request(Some(user.clone()), &packet)
+            .content
+        {
+            Message::Response(resp) => resp,
+            msg => panic!("not a response: {msg:?}"),
+        }
+    }
+
+    /// Sends a request to the REST routes, with a bearer token. A body is sent as
+    /// JSON. Returns the status and the body of the response.
+    pub async fn rest(
+        &self,
+        method: Method,
+        uri: &str,
+        token: Option<&str>,
+        body: Option<serde_json::Value>,
+    ) -> (StatusCode, Bytes) {
+        let mut req = http::Request::builder().method(method).uri(uri);
+        if let Some(token) = token {
+            req = req.header(header::AUTHORIZATION, format!("Bearer {token}"));
+        }
+        let body = match body {
+            Some(body) => {
+                req = req.header(header::CONTENT_TYPE, "application/json");
+                Body::from(body.to_string())
+            }
+            None => Body::empty(),
+        };
+        let mut req = req.body(body).unwrap();
+        // the rate limiter needs the address of the client.
+        let addr = std::net::SocketAddr::from(([127, 0, 0, 1], 0));
+        req.extensions_mut().insert(ConnectInfo(addr));
+
+        let router = Router::new(self.server.clone(), &self.cli).router;
+        let resp = router.oneshot(req).await.unwrap();
+        let status = resp.status();
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, body)
+    }
+}
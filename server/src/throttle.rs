@@ -0,0 +1,219 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+
+use crate::error::Error;
+
+// Failed password attempts are counted per connection and per IP. After a few free
+// attempts, each failure doubles the delay before the next attempt is accepted.
+// Attempts are checked before calling bcrypt, so a locked-out client costs nothing.
+// They are also counted as failures before calling bcrypt, so that concurrent
+// guesses cannot all pass the check; successful attempts are taken back.
+
+const FREE_ATTEMPTS: u32 = 3;
+const BASE_DELAY: Duration = Duration::from_secs(1);
+const MAX_DELAY: Duration = Duration::from_secs(600);
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum Key {
+    Token(String),
+    Ip(IpAddr),
+}
+
+struct Attempts {
+    failures: u32,
+    last: Instant,
+    until: Instant,
+}
+
+#[derive(Default)]
+pub struct LoginThrottle {
+    attempts: Mutex<HashMap<Key, Attempts>>,
+}
+
+fn keys(token: &str, ip: Option<IpAddr>) -> impl Iterator<Item = Key> {
+    [Some(Key::Token(token.to_owned())), ip.map(Key::Ip)]
+        .into_iter()
+        .flatten()
+}
+
+fn lockout(failures: u32) -> Duration {
+    if failures < FREE_ATTEMPTS {
+        Duration::ZERO
+    } else {
+        let exp = (failures - FREE_ATTEMPTS).min(16);
+        (BASE_DELAY * 2u32.pow(exp)).min(MAX_DELAY)
+    }
+}
+
+impl LoginThrottle {
+    /// Records a login attempt of a connection token or REST bearer token. Fails
+    /// with the number of seconds to wait if the client is locked out.
+    ///
+    /// The attempt counts as a failure until [`Self::succeed`] or [`Self::cancel`].
+    pub fn attempt(&self, token: &str, ip: Option<IpAddr>) -> Result<(), Error> {
+        self.attempt_at(token, ip, Instant::now())
+    }
+
+    fn attempt_at(&self, token: &str, ip: Option<IpAddr>, now: Instant) -> Result<(), Error> {
+        let mut attempts = self.attempts.lock();
+        let wait = keys(token, ip)
+            .filter_map(|key| attempts.get(&key))
+            .map(|a| a.until.saturating_duration_since(now))
+            .max()
+            .unwrap_or_default();
+
+        if !wait.is_zero() {
+            return Err(Error::TooManyAttempts(wait.as_secs().max(1)));
+        }
+
+        // forget clients that stopped trying.
+        attempts.retain(|_, a| now.saturating_duration_since(a.last) < MAX_DELAY * 2);
+
+        for key in keys(token, ip) {
+            let a = attempts.entry(key).or_insert(Attempts {
+                failures: 0,
+                last: now,
+                until: now,
+            });
+            a.failures += 1;
+            a.last = now;
+            a.until = now + lockout(a.failures);
+        }
+
+        Ok(())
+    }
+
+    /// Takes back an attempt that failed for another reason than a wrong password
+    /// or token.
+    pub fn cancel(&self, token: &str, ip: Option<IpAddr>) {
+        let mut attempts = self.attempts.lock();
+        for key in keys(token, ip) {
+            if let Some(a) = attempts.get_mut(&key) {
+                a.failures = a.failures.saturating_sub(1);
+                a.until = a.last + lockout(a.failures);
+            }
+        }
+    }
+
+    /// The connection counter is reset on success. The IP counter is not, so that
+    /// an attacker cannot reset it by joining another map.
+    pub fn succeed(&self, token: &str, ip: Option<IpAddr>) {
+        self.cancel(token, ip);
+        self.attempts.lock().remove(&Key::Token(token.to_owned()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IP: Option<IpAddr> = Some(IpAddr::V4(std::net::Ipv4Addr::LOCALHOST));
+
+    fn wait(res: Result<(), Error>) -> Option<u64> {
+        match res {
+            Ok(()) => None,
+            Err(Error::TooManyAttempts(secs)) => Some(secs),
+            Err(e) => panic!("unexpected error: {e}"),
+        }
+    }
+
+    #[test]
+    fn lockout_after_free_attempts() {
+        let throttle = LoginThrottle::default();
+        let now = Instant::now();
+
+        for _ in 0..FREE_ATTEMPTS {
+            assert_eq!(wait(throttle.attempt_at("a", IP, now)), None);
+        }
+        assert_eq!(wait(throttle.attempt_at("a", IP, now)), Some(1));
+        // the IP is locked out as well, whatever the token.
+        assert_eq!(wait(throttle.attempt_at("b", IP, now)), Some(1));
+        assert_eq!(wait(throttle.attempt_at("c", None, now)), None);
+    }
+
+    #[test]
+    fn lockout_doubles_and_expires() {
+        let throttle = LoginThrottle::default();
+        let mut now = Instant::now();
+
+        for _ in 0..FREE_ATTEMPTS {
+            throttle.attempt_at("a", IP, now).unwrap();
+        }
+        now += Duration::from_millis(999);
+        assert!(wait(throttle.attempt_at("a", IP, now)).is_some());
+
+        now += Duration::from_millis(1);
+        assert_eq!(wait(throttle.attempt_at("a", IP, now)), None);
+        assert_eq!(wait(throttle.attempt_at("a", IP, now)), Some(2));
+
+        now += Duration::from_secs(2);
+        assert_eq!(wait(throttle.attempt_at("a", IP, now)), None);
+        assert_eq!(wait(throttle.attempt_at("a", IP, now)), Some(4));
+    }
+
+    #[test]
+    fn lockout_is_capped() {
+        let throttle = LoginThrottle::default();
+        let mut now = Instant::now();
+
+        for _ in 0..64 {
+            throttle.attempt_at("a", IP, now).ok();
+            now += MAX_DELAY;
+        }
+        assert_eq!(
+            wait(throttle.attempt_at("a", IP, now - MAX_DELAY)),
+            Some(MAX_DELAY.as_secs())
+        );
+    }
+
+    #[test]
+    fn forgets_clients_after_a_while() {
+        let throttle = LoginThrottle::default();
+        let now = Instant::now();
+
+        for _ in 0..FREE_ATTEMPTS {
+            throttle.attempt_at("a", IP, now).unwrap();
+        }
+        let later = now + MAX_DELAY * 2;
+        throttle.attempt_at("b", None, later).unwrap();
+        assert_eq!(throttle.attempts.lock().len(), 1);
+    }
+
+    #[test]
+    fn success_resets_connection_only() {
+        let throttle = LoginThrottle::default();
+        let now = Instant::now();
+
+        throttle.attempt_at("a", IP, now).unwrap();
+        throttle.attempt_at("a", IP, now).unwrap();
+        throttle.attempt_at("a", IP, now).unwrap();
+        throttle.succeed("a", IP);
+
+        let attempts = throttle.attempts.lock();
+        assert!(!attempts.contains_key(&Key::Token("a".to_owned())));
+        // the successful attempt is taken back, the failed ones are not.
+        let ip = &attempts[&Key::Ip(IP.unwrap())];
+        assert_eq!(ip.failures, 2);
+        assert_eq!(ip.until, ip.last);
+    }
+
+    #[test]
+    fn pending_attempts_count_as_failures() {
+        let throttle = LoginThrottle::default();
+        let now = Instant::now();
+
+        // concurrent guesses that did not finish verifying yet.
+        for token in ["a", "b", "c"] {
+            throttle.attempt_at(token, IP, now).unwrap();
+        }
+        assert!(wait(throttle.attempt_at("d", IP, now)).is_some());
+
+        throttle.cancel("c", IP);
+        assert_eq!(wait(throttle.attempt_at("d", IP, now)), None);
+    }
+}
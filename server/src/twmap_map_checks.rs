@@ -1,30 +1,19 @@
 use image::RgbaImage;
-use ndarray::Array2;
+use ndarray::{Array2, ArrayView2};
 use thiserror::Error;
 use twmap::*;
 use vek::az::{OverflowingAs, OverflowingCast, UnwrappedAs, WrappingCast};
 use vek::num_traits::Signed;
 use vek::Extent2;
 
+use std::collections::HashSet;
 use std::fmt;
 use std::mem;
 
-#[derive(Debug)]
-pub(crate) enum MapItem {
-    Info,
-    Image,
-    Envelope,
-    // Group,
-    // Layer,
-    // Sound,
-    // Quad,
-    // SoundSource,
-    EnvPoint,
-}
+use crate::protocol::{MapDiagnostic, MapItemIndex, Severity};
+use crate::util::ViewAsBytes;
 
-#[derive(Error, Debug)]
-#[error(transparent)]
-pub struct MapError(#[from] pub(crate) MapErr);
+pub(crate) use crate::protocol::MapItemKind as MapItem;
 
 #[derive(Error, Debug)]
 pub(crate) enum MapErr {
@@ -52,6 +41,26 @@ impl MapErr {
         self
     }
 
+    fn into_diagnostic(self, severity: Severity) -> MapDiagnostic {
+        let mut path = Vec::new();
+        let mut err = self;
+        loop {
+            match err {
+                Self::Recursive { item, index, sub } => {
+                    path.push(MapItemIndex { item, index });
+                    err = *sub;
+                }
+                Self::Head(kind) => {
+                    return MapDiagnostic {
+                        severity,
+                        path,
+                        msg: kind.to_string(),
+                    }
+                }
+            }
+        }
+    }
+
     pub(crate) fn with_type(self, item: MapItem) -> Self {
         Self::Recursive {
             item,
@@ -72,33 +81,35 @@ pub(crate) enum MapErrorKind {
     //     amount: usize,
     //     max: usize,
     // },
-    // #[error("Invalid image index {index} for a map with {len} images")]
-    // ImageIndex {
-    //     index: u16,
-    //     len: usize,
-    // },
-    // #[error("Invalid sound index {index} for a map with {len} sounds")]
-    // SoundIndex {
-    //     index: u16,
-    //     len: usize,
-    // },
-    // #[error("Invalid envelope index {index} for a map with {len} envelopes")]
-    // EnvelopeIndex {
-    //     index: u16,
-    //     len: usize,
-    // },
-    // #[error("Envelope at index {index} referenced as a {expected:?} envelope is instead a {actual:?} envelope")]
-    // EnvelopeKind {
-    //     index: u16,
-    //     expected: EnvelopeKind,
-    //     actual: EnvelopeKind,
-    // },
+    #[error("Invalid image index {index} for a map with {len} images")]
+    ImageIndex {
+        index: u16,
+        len: usize,
+    },
+    #[error("Invalid sound index {index} for a map with {len} sounds")]
+    SoundIndex {
+        index: u16,
+        len: usize,
+    },
+    #[error("Invalid envelope index {index} for a map with {len} envelopes")]
+    EnvelopeIndex {
+        index: u16,
+        len: usize,
+    },
+    #[error("Envelope at index {index} referenced as a {expected:?} envelope is instead a {actual:?} envelope")]
+    EnvelopeKind {
+        index: u16,
+        expected: EnvelopeKind,
+        actual: EnvelopeKind,
+    },
+    #[error("{0}")]
+    Unused(UnusedItem),
     String(#[from] StringError),
     I32Fit(#[from] ValueMaxError),
     Negative(#[from] NegativeError),
     Image(#[from] ImageError),
     Info(#[from] InfoError),
-    // Sound(#[from] opus_headers::ParseError),
+    Sound(#[from] opus_headers::ParseError),
     Group(#[from] GroupError),
     Layer(#[from] LayerError),
     Tile(#[from] TileError),
@@ -115,12 +126,12 @@ pub enum DDNetError {
 pub enum TeeworldsError {
     #[error("Teeworlds does not support settings in the map info")]
     InfoSettings,
-    // #[error("Teeworlds does not support {0:?} layers")]
-    // DDNetLayer(LayerKind),
-    // #[error("Teeworlds does not support automapper configs")]
-    // TilesAutomapper,
-    // #[error("Teeworlds does not support sounds")]
-    // Sounds,
+    #[error("Teeworlds does not support {0:?} layers")]
+    DDNetLayer(LayerKind),
+    #[error("Teeworlds does not support automapper configs")]
+    TilesAutomapper,
+    #[error("Teeworlds does not support sounds")]
+    Sounds,
     #[error("Teeworlds does not support sound envelopes")]
     SoundEnv,
 }
@@ -246,6 +257,39 @@ pub(crate) trait InternalMapChecking: Sized {
         Ok(())
     }
 
+    /// Like `check_recursive_impl`, but collects all errors.
+    fn report_recursive_impl(&self, map: &TwMap, errors: &mut Vec<MapErr>) {
+        if let Err(err) = self.check_recursive_impl(map) {
+            errors.push(err);
+        }
+    }
+
+    /// Like `check`, but collects all errors instead of stopping at the first one.
+    fn report(&self, map: &TwMap, state: &mut Self::State, errors: &mut Vec<MapErr>) {
+        let with_type = |err: MapErr| err.with_type(Self::TYPE);
+        if let Err(err) = self.check_impl(map) {
+            errors.push(with_type(err.into()));
+        }
+        let mut sub = Vec::new();
+        self.report_recursive_impl(map, &mut sub);
+        errors.extend(sub.into_iter().map(with_type));
+        if let Err(err) = self.check_state_impl(map, state) {
+            errors.push(with_type(err.into()));
+        }
+    }
+
+    fn report_all(items: &[Self], map: &TwMap, errors: &mut Vec<MapErr>) {
+        let mut state = Self::State::default();
+        for (i, item) in items.iter().enumerate() {
+            let mut sub = Vec::new();
+            item.report(map, &mut state, &mut sub);
+            errors.extend(sub.into_iter().map(|err| err.with_index(i)));
+        }
+        if let Err(err) = Self::check_state(state) {
+            errors.push(MapErr::from(err).with_type(Self::TYPE));
+        }
+    }
+
     fn check_all(items: &[Self], map: &TwMap) -> Result<(), MapErr> {
         let mut state = Self::State::default();
         for (i, item) in items.iter().enumerate() {
@@ -357,6 +401,34 @@ impl CheckData for CompressedData<RgbaImage, ImageLoadInfo> {
     }
 }
 
+impl InternalMapChecking for Sound {
+    const TYPE: MapItem = MapItem::Sound;
+    type State = ();
+
+    fn check_impl(&self, map: &TwMap) -> Result<(), MapErrorKind> {
+        check_string(&self.name, Sound::MAX_NAME_LENGTH, Some("opus"))?;
+        self.data.check_data()?;
+        if map.version == Version::Teeworlds07 {
+            return Err(TeeworldsError::Sounds.into());
+        }
+        Ok(())
+    }
+}
+
+impl CheckData for CompressedData<Vec<u8>, ()> {
+    fn check_data(&self) -> Result<(), MapErrorKind> {
+        let data_size = match self {
+            CompressedData::Compressed(_, size, _) => *size,
+            CompressedData::Loaded(buf) => buf.len(),
+        };
+        check_i32_fit(data_size, "sound data size")?;
+        if let CompressedData::Loaded(buf) = self {
+            opus_headers::parse_from_read(&buf[..])?;
+        }
+        Ok(())
+    }
+}
+
 impl InternalMapChecking for Envelope {
     const TYPE: MapItem = MapItem::Envelope;
     type State = ();
@@ -382,6 +454,14 @@ impl InternalMapChecking for Envelope {
             Envelope::Sound(env) => EnvPoint::check_all(&env.points, map),
         }
     }
+
+    fn report_recursive_impl(&self, map: &TwMap, errors: &mut Vec<MapErr>) {
+        match self {
+            Envelope::Position(env) => EnvPoint::report_all(&env.points, map, errors),
+            Envelope::Color(env) => EnvPoint::report_all(&env.points, map, errors),
+            Envelope::Sound(env) => EnvPoint::report_all(&env.points, map, errors),
+        }
+    }
 }
 
 #[derive(Error, Debug)]
@@ -420,38 +500,267 @@ impl<T> InternalMapChecking for EnvPoint<T> {
     }
 }
 
+#[derive(Debug, Ord, PartialOrd, Eq, PartialEq, Copy, Clone)]
+pub enum EnvelopeKind {
+    Position,
+    Color,
+    Sound,
+}
+
+fn envelope_kind(env: &Envelope) -> EnvelopeKind {
+    match env {
+        Envelope::Position(_) => EnvelopeKind::Position,
+        Envelope::Color(_) => EnvelopeKind::Color,
+        Envelope::Sound(_) => EnvelopeKind::Sound,
+    }
+}
+
+fn check_envelope_index(
+    map: &TwMap,
+    index: Option<u16>,
+    expected: EnvelopeKind,
+) -> Result<(), MapErrorKind> {
+    let Some(index) = index else {
+        return Ok(());
+    };
+    match map.envelopes.get(index as usize) {
+        None => Err(MapErrorKind::EnvelopeIndex {
+            index,
+            len: map.envelopes.len(),
+        }),
+        Some(env) if envelope_kind(env) != expected => Err(MapErrorKind::EnvelopeKind {
+            index,
+            expected,
+            actual: envelope_kind(env),
+        }),
+        Some(_) => Ok(()),
+    }
+}
+
+fn check_image_index(map: &TwMap, index: Option<u16>) -> Result<(), MapErrorKind> {
+    match index {
+        Some(index) if index as usize >= map.images.len() => Err(MapErrorKind::ImageIndex {
+            index,
+            len: map.images.len(),
+        }),
+        _ => Ok(()),
+    }
+}
+
+fn check_sound_index(map: &TwMap, index: Option<u16>) -> Result<(), MapErrorKind> {
+    match index {
+        Some(index) if index as usize >= map.sounds.len() => Err(MapErrorKind::SoundIndex {
+            index,
+            len: map.sounds.len(),
+        }),
+        _ => Ok(()),
+    }
+}
+
+impl InternalMapChecking for Group {
+    const TYPE: MapItem = MapItem::Group;
+    /// Represents if a physics group was already found
+    type State = bool;
+
+    fn check_impl(&self, _: &TwMap) -> Result<(), MapErrorKind> {
+        check_string(&self.name, Group::MAX_NAME_LENGTH, None)?;
+        check_i32_fit(self.layers.len(), "layers amount")?;
+        check_non_negative(self.clip.w, "clip width")?;
+        check_non_negative(self.clip.h, "clip height")?;
+        if self.is_physics_group() {
+            if !self.layers.iter().any(|l| matches!(l, Layer::Game(_))) {
+                return Err(GroupError::NoGameLayer.into());
+            }
+            let default = Group::physics();
+            if self.name != default.name {
+                return Err(GroupError::PhysicsName(self.name.clone()).into());
+            }
+            if self.clipping != default.clipping || self.clip != default.clip {
+                return Err(GroupError::PhysicsClip.into());
+            }
+            if self.offset != default.offset {
+                return Err(GroupError::PhysicsOffset.into());
+            }
+            if self.parallax != default.parallax {
+                return Err(GroupError::PhysicsParallax.into());
+            }
+        }
+        Ok(())
+    }
+
+    fn check_state_impl(&self, _: &TwMap, has_physics: &mut bool) -> Result<(), MapErrorKind> {
+        if self.is_physics_group() {
+            if *has_physics {
+                return Err(GroupError::SecondPhysicsGroup.into());
+            }
+            *has_physics = true;
+        }
+        Ok(())
+    }
+
+    fn check_recursive_impl(&self, map: &TwMap) -> Result<(), MapErr> {
+        Layer::check_all(&self.layers, map)
+    }
+
+    fn report_recursive_impl(&self, map: &TwMap, errors: &mut Vec<MapErr>) {
+        Layer::report_all(&self.layers, map, errors)
+    }
+
+    fn check_state(has_physics: bool) -> Result<(), MapErrorKind> {
+        if !has_physics {
+            return Err(GroupError::NoPhysicsGroup.into());
+        }
+        Ok(())
+    }
+}
+
+impl InternalMapChecking for Layer {
+    const TYPE: MapItem = MapItem::Layer;
+    type State = (HashSet<LayerKind>, Option<Extent2<usize>>);
+
+    fn check_impl(&self, map: &TwMap) -> Result<(), MapErrorKind> {
+        use Layer::*;
+        check_string(self.name(), Layer::MAX_NAME_LENGTH, None)?;
+        if let Invalid(inv) = self {
+            return Err(LayerError::InvalidKind(*inv).into());
+        }
+        if map.version == Version::Teeworlds07 && !matches!(self, Game(_) | Tiles(_) | Quads(_)) {
+            return Err(TeeworldsError::DDNetLayer(self.kind()).into());
+        }
+        match self {
+            Game(l) => check_layer_tiles(l)?,
+            Front(l) => check_layer_tiles(l)?,
+            Tele(l) => check_layer_tiles(l)?,
+            Speedup(l) => check_layer_tiles(l)?,
+            Switch(l) => check_layer_tiles(l)?,
+            Tune(l) => check_layer_tiles(l)?,
+            Tiles(l) => {
+                check_layer_tiles(l)?;
+                check_envelope_index(map, l.color_env, EnvelopeKind::Color)?;
+                check_image_index(map, l.image)?;
+                if let Some(image) = l.image {
+                    if !map.images[image as usize].for_tilemap() {
+                        return Err(LayerError::ImageDimensions.into());
+                    }
+                }
+                if map.version == Version::Teeworlds07
+                    && l.automapper_config != AutomapperConfig::default()
+                {
+                    return Err(TeeworldsError::TilesAutomapper.into());
+                }
+                if l.automapper_config.seed > 1_000_000_000 {
+                    return Err(LayerError::AutomapperSeed(l.automapper_config.seed).into());
+                }
+            }
+            Quads(l) => check_image_index(map, l.image)?,
+            Sounds(l) => check_sound_index(map, l.sound)?,
+            Invalid(_) => {}
+        }
+        Ok(())
+    }
+
+    fn check_state_impl(&self, _: &TwMap, state: &mut Self::State) -> Result<(), MapErrorKind> {
+        let kind = self.kind();
+        if !kind.is_physics_layer() {
+            return Ok(());
+        }
+        let (layers, expected_shape) = state;
+        if layers.replace(kind).is_some() {
+            return Err(LayerError::DuplicatePhysics(kind).into());
+        }
+        let Some(shape) = self.shape() else {
+            return Ok(());
+        };
+        match expected_shape {
+            None => *expected_shape = Some(shape),
+            Some(expected) => {
+                if *expected != shape {
+                    return Err(LayerError::DifferentPhysicsShapes.into());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn check_recursive_impl(&self, map: &TwMap) -> Result<(), MapErr> {
+        match self {
+            Layer::Quads(l) => Quad::check_all(&l.quads, map),
+            Layer::Sounds(l) => SoundSource::check_all(&l.sources, map),
+            _ => Ok(()),
+        }
+    }
+
+    fn report_recursive_impl(&self, map: &TwMap, errors: &mut Vec<MapErr>) {
+        match self {
+            Layer::Quads(l) => Quad::report_all(&l.quads, map, errors),
+            Layer::Sounds(l) => SoundSource::report_all(&l.sources, map, errors),
+            _ => {}
+        }
+    }
+}
+
+impl InternalMapChecking for Quad {
+    const TYPE: MapItem = MapItem::Quad;
+    type State = ();
+
+    fn check_impl(&self, map: &TwMap) -> Result<(), MapErrorKind> {
+        check_envelope_index(map, self.position_env, EnvelopeKind::Position)?;
+        check_envelope_index(map, self.color_env, EnvelopeKind::Color)?;
+        Ok(())
+    }
+}
+
+impl InternalMapChecking for SoundSource {
+    const TYPE: MapItem = MapItem::SoundSource;
+    type State = ();
+
+    fn check_impl(&self, map: &TwMap) -> Result<(), MapErrorKind> {
+        check_non_negative(self.delay, "delay")?;
+        check_envelope_index(map, self.position_env, EnvelopeKind::Position)?;
+        check_envelope_index(map, self.sound_env, EnvelopeKind::Sound)?;
+        match self.area {
+            SoundArea::Rectangle(rect) => {
+                check_non_negative(rect.w, "area width")?;
+                check_non_negative(rect.h, "area height")?;
+            }
+            SoundArea::Circle(disk) => check_non_negative(disk.radius, "area radius")?,
+        }
+        Ok(())
+    }
+}
+
 #[derive(Error, Debug)]
 pub(crate) enum GroupError {
-    // #[error("No physics group")]
-    // NoPhysicsGroup,
-    // #[error("There must be only one physics group")]
-    // SecondPhysicsGroup,
-    // #[error("No game layer in physics group")]
-    // NoGameLayer,
-    // #[error("The physics group '{0}' should be called 'Game' instead")]
-    // PhysicsName(String),
-    // #[error("The clipping values of the physics group are changed")]
-    // PhysicsClip,
-    // #[error("The parallax values of the physics group are changed")]
-    // PhysicsParallax,
-    // #[error("The offset values of the physics group are changed")]
-    // PhysicsOffset,
+    #[error("No physics group")]
+    NoPhysicsGroup,
+    #[error("There must be only one physics group")]
+    SecondPhysicsGroup,
+    #[error("No game layer in physics group")]
+    NoGameLayer,
+    #[error("The physics group '{0}' should be called 'Game' instead")]
+    PhysicsName(String),
+    #[error("The clipping values of the physics group are changed")]
+    PhysicsClip,
+    #[error("The parallax values of the physics group are changed")]
+    PhysicsParallax,
+    #[error("The offset values of the physics group are changed")]
+    PhysicsOffset,
 }
 
 #[derive(Error, Debug)]
 pub(crate) enum LayerError {
-    // #[error("Invalid layer kind: {0:?}")]
-    // InvalidKind(InvalidLayerKind),
+    #[error("Invalid layer kind: {0:?}")]
+    InvalidKind(InvalidLayerKind),
     #[error("Width and height must be at least 2")]
     TooSmall,
-    // #[error("Images used by tiles layers must have width and height be divisible by 16")]
-    // ImageDimensions,
-    // #[error("Automapper seed ({0}) must be below 1,000,000,000")]
-    // AutomapperSeed(u32),
-    // #[error("Second {0:?} layer")]
-    // DuplicatePhysics(LayerKind),
-    // #[error("The physics layers have different shapes")]
-    // DifferentPhysicsShapes,
+    #[error("Images used by tiles layers must have width and height be divisible by 16")]
+    ImageDimensions,
+    #[error("Automapper seed ({0}) must be below 1,000,000,000")]
+    AutomapperSeed(u32),
+    #[error("Second {0:?} layer")]
+    DuplicatePhysics(LayerKind),
+    #[error("The physics layers have different shapes")]
+    DifferentPhysicsShapes,
     #[error("0.7 compressed tile data length must be a multiple of 4")]
     CompressedSize,
     #[error("The tile data size doesn't match with the layer dimensions")]
@@ -470,27 +779,317 @@ pub(crate) struct TileError {
 
 #[derive(Error, Debug)]
 pub enum TileErrorKind {
-    // #[error("Skip byte of tile is {0} instead of zero")]
-    // TileSkip(u8),
-    // #[error("Unused byte of tile is {0} instead of zero")]
-    // TileUnused(u8),
-    // #[error("Unknown tile flags used, flags: {:#010b}", .0)]
-    // UnknownTileFlags(u8),
-    // #[error("Opaque tile flag used in physics layer")]
-    // OpaqueTileFlag,
-    // #[error("Unused byte of speedup is {0} instead of zero")]
-    // SpeedupUnused(u8),
-    // #[error("Angle of speedup is {0}, but should be between 0 and (exclusive) 360")]
-    // SpeedupAngle(i16),
-}
-
-pub trait TileChecking {
-    fn check(&self) -> Result<(), TileErrorKind> {
+    #[error("Skip byte of tile is {0} instead of zero")]
+    TileSkip(u8),
+    #[error("Unused byte of tile is {0} instead of zero")]
+    TileUnused(u8),
+    #[error("Unknown tile flags used, flags: {:#010b}", .0)]
+    UnknownTileFlags(u8),
+    #[error("Opaque tile flag used in physics layer")]
+    OpaqueTileFlag,
+    #[error("Unused byte of speedup is {0} instead of zero")]
+    SpeedupUnused(u8),
+    #[error("Angle of speedup is {0}, but should be between 0 and (exclusive) 360")]
+    SpeedupAngle(i16),
+    #[error("Tile id {id} is not valid in a {kind:?} layer")]
+    TileId { id: u8, kind: LayerKind },
+}
+
+// Tile ids of DDNet physics layers, see `mapitems.h` in DDNet.
+pub(crate) mod tile_id {
+    pub const AIR: u8 = 0;
+    pub const SOLID: u8 = 1;
+    pub const DEATH: u8 = 2;
+    pub const NOHOOK: u8 = 3;
+    pub const NOLASER: u8 = 4;
+    pub const THROUGH: u8 = 6;
+    pub const JUMP: u8 = 7;
+    pub const FREEZE: u8 = 9;
+    pub const TELEINEVIL: u8 = 10;
+    pub const UNFREEZE: u8 = 11;
+    pub const DFREEZE: u8 = 12;
+    pub const DUNFREEZE: u8 = 13;
+    pub const TELEINWEAPON: u8 = 14;
+    pub const TELEINHOOK: u8 = 15;
+    pub const WALLJUMP: u8 = 16;
+    pub const HIT_ENABLE: u8 = 19;
+    pub const HIT_DISABLE: u8 = 20;
+    pub const SOLO_DISABLE: u8 = 22;
+    pub const SWITCHTIMEDOPEN: u8 = 22;
+    pub const SWITCHCLOSE: u8 = 25;
+    pub const TELEIN: u8 = 26;
+    pub const TELEOUT: u8 = 27;
+    pub const BOOST: u8 = 28;
+    pub const SPEED_BOOST: u8 = 29;
+    pub const TELECHECK: u8 = 29;
+    pub const TELECHECKOUT: u8 = 30;
+    pub const TELECHECKIN: u8 = 31;
+    pub const REFILL_JUMPS: u8 = 32;
+    pub const START: u8 = 33;
+    pub const FINISH: u8 = 34;
+    pub const STOPA: u8 = 62;
+    pub const TELECHECKINEVIL: u8 = 63;
+    pub const CP: u8 = 64;
+    pub const THROUGH_DIR: u8 = 67;
+    pub const TUNE: u8 = 68;
+    pub const OLDLASER: u8 = 71;
+    pub const UNLOCK_TEAM: u8 = 76;
+    pub const ADD_TIME: u8 = 79;
+    pub const NPC_DISABLE: u8 = 88;
+    pub const NPH_DISABLE: u8 = 91;
+    pub const SUBTRACT_TIME: u8 = 95;
+    pub const TELE_GUN_ENABLE: u8 = 96;
+    pub const ALLOW_TELE_GUN: u8 = 98;
+    pub const ALLOW_BLUE_TELE_GUN: u8 = 99;
+    pub const NPC_ENABLE: u8 = 104;
+    pub const NPH_ENABLE: u8 = 107;
+    pub const TELE_GRENADE_ENABLE: u8 = 112;
+    pub const TELE_GRENADE_DISABLE: u8 = 113;
+    pub const TELE_LASER_ENABLE: u8 = 128;
+    pub const TELE_LASER_DISABLE: u8 = 129;
+    pub const CREDITS_1: u8 = 140;
+    pub const CREDITS_3: u8 = 142;
+    pub const LFREEZE: u8 = 144;
+    pub const LUNFREEZE: u8 = 145;
+    pub const CREDITS_4: u8 = 156;
+    pub const CREDITS_6: u8 = 158;
+    pub const CREDITS_7: u8 = 172;
+    pub const CREDITS_8: u8 = 173;
+    pub const ENTITIES_OFF_1: u8 = 190;
+    pub const ENTITIES_OFF_2: u8 = 191;
+
+    pub const ENTITY_OFFSET: u8 = 255 - 16 * 4;
+    pub const ENTITY_SPAWN: u8 = 1;
+    pub const ENTITY_SPAWN_BLUE: u8 = 3;
+    pub const ENTITY_ARMOR_1: u8 = 6;
+    pub const ENTITY_LASER_O_FAST: u8 = 27;
+    pub const ENTITY_PLASMAE: u8 = 29;
+    pub const ENTITY_ARMOR_LASER: u8 = 38;
+    pub const ENTITY_DRAGGER_WEAK: u8 = 42;
+    pub const ENTITY_DRAGGER_STRONG_NW: u8 = 47;
+    pub const ENTITY_DOOR: u8 = 49;
+}
+
+fn is_valid_entity(id: u8) -> bool {
+    use tile_id::*;
+    let Some(id) = id.checked_sub(ENTITY_OFFSET) else {
+        return false;
+    };
+    (ENTITY_SPAWN..=ENTITY_LASER_O_FAST).contains(&id)
+        || (ENTITY_PLASMAE..=ENTITY_ARMOR_LASER).contains(&id)
+        || (ENTITY_DRAGGER_WEAK..=ENTITY_DRAGGER_STRONG_NW).contains(&id)
+        || id == ENTITY_DOOR
+}
+
+/// Tiles shared by the game and front layers.
+fn is_valid_game_or_front_tile(id: u8) -> bool {
+    use tile_id::*;
+    id == AIR
+        || id == FREEZE
+        || (UNFREEZE..=DUNFREEZE).contains(&id)
+        || (LFREEZE..=LUNFREEZE).contains(&id)
+        || (WALLJUMP..=SOLO_DISABLE).contains(&id)
+        || (REFILL_JUMPS..=STOPA).contains(&id)
+        || (CP..=THROUGH_DIR).contains(&id)
+        || (OLDLASER..=UNLOCK_TEAM).contains(&id)
+        || (NPC_DISABLE..=NPH_DISABLE).contains(&id)
+        || (TELE_GUN_ENABLE..=ALLOW_BLUE_TELE_GUN).contains(&id)
+        || (NPC_ENABLE..=NPH_ENABLE).contains(&id)
+        || (TELE_GRENADE_ENABLE..=TELE_GRENADE_DISABLE).contains(&id)
+        || (TELE_LASER_ENABLE..=TELE_LASER_DISABLE).contains(&id)
+        || (CREDITS_1..=CREDITS_3).contains(&id)
+        || (CREDITS_4..=CREDITS_6).contains(&id)
+        || (CREDITS_7..=CREDITS_8).contains(&id)
+        || (ENTITIES_OFF_1..=ENTITIES_OFF_2).contains(&id)
+        || is_valid_entity(id)
+}
+
+fn is_valid_tile_id(id: u8, kind: LayerKind) -> bool {
+    use tile_id::*;
+    match kind {
+        LayerKind::Game => {
+            (SOLID..=NOLASER).contains(&id) || id == THROUGH || is_valid_game_or_front_tile(id)
+        }
+        LayerKind::Front => {
+            id == DEATH || (NOLASER..=THROUGH).contains(&id) || is_valid_game_or_front_tile(id)
+        }
+        LayerKind::Tele => matches!(
+            id,
+            AIR | TELEINEVIL
+                | TELEINWEAPON
+                | TELEINHOOK
+                | TELEIN
+                | TELEOUT
+                | TELECHECK
+                | TELECHECKOUT
+                | TELECHECKIN
+                | TELECHECKINEVIL
+        ),
+        LayerKind::Speedup => matches!(id, AIR | BOOST | SPEED_BOOST),
+        LayerKind::Switch => {
+            matches!(
+                id,
+                AIR | JUMP
+                    | FREEZE
+                    | DFREEZE
+                    | DUNFREEZE
+                    | LFREEZE
+                    | LUNFREEZE
+                    | HIT_ENABLE
+                    | HIT_DISABLE
+                    | ADD_TIME
+                    | SUBTRACT_TIME
+                    | ALLOW_TELE_GUN
+                    | ALLOW_BLUE_TELE_GUN
+            ) || (SWITCHTIMEDOPEN..=SWITCHCLOSE).contains(&id)
+                || (is_valid_entity(id) && id >= ENTITY_OFFSET + ENTITY_ARMOR_1)
+        }
+        LayerKind::Tune => matches!(id, AIR | TUNE),
+        _ => true,
+    }
+}
+
+fn check_tile_flags(flags: TileFlags, physics: bool) -> Result<(), TileErrorKind> {
+    if TileFlags::from_bits(flags.bits()).is_none() {
+        return Err(TileErrorKind::UnknownTileFlags(flags.bits()));
+    }
+    if physics && flags.contains(TileFlags::OPAQUE) {
+        return Err(TileErrorKind::OpaqueTileFlag);
+    }
+    Ok(())
+}
+
+/// Checks the tile id against the layer kind, since game and front layers share
+/// the same tile type. `bytes` is the raw tile, since twmap does not expose the
+/// padding bytes of tiles.
+pub trait TileChecking: AnyTile {
+    fn check(&self, _bytes: &[u8], kind: LayerKind) -> Result<(), TileErrorKind> {
+        if is_valid_tile_id(self.id(), kind) {
+            Ok(())
+        } else {
+            Err(TileErrorKind::TileId {
+                id: self.id(),
+                kind,
+            })
+        }
+    }
+}
+
+impl TileChecking for Tile {
+    fn check(&self, bytes: &[u8], _kind: LayerKind) -> Result<(), TileErrorKind> {
+        if bytes[2] != 0 {
+            return Err(TileErrorKind::TileSkip(bytes[2]));
+        }
+        if bytes[3] != 0 {
+            return Err(TileErrorKind::TileUnused(bytes[3]));
+        }
+        check_tile_flags(self.flags, false)
+    }
+}
+
+impl TileChecking for GameTile {
+    fn check(&self, bytes: &[u8], kind: LayerKind) -> Result<(), TileErrorKind> {
+        if bytes[2] != 0 {
+            return Err(TileErrorKind::TileSkip(bytes[2]));
+        }
+        if bytes[3] != 0 {
+            return Err(TileErrorKind::TileUnused(bytes[3]));
+        }
+        check_tile_flags(self.flags, true)?;
+        if !is_valid_tile_id(self.id, kind) {
+            return Err(TileErrorKind::TileId { id: self.id, kind });
+        }
+        Ok(())
+    }
+}
+
+impl TileChecking for Tele {}
+
+impl TileChecking for Speedup {
+    fn check(&self, bytes: &[u8], kind: LayerKind) -> Result<(), TileErrorKind> {
+        if bytes[3] != 0 {
+            return Err(TileErrorKind::SpeedupUnused(bytes[3]));
+        }
+        let angle = i16::from(self.angle);
+        if !(0..360).contains(&angle) {
+            return Err(TileErrorKind::SpeedupAngle(angle));
+        }
+        if !is_valid_tile_id(self.id, kind) {
+            return Err(TileErrorKind::TileId { id: self.id, kind });
+        }
         Ok(())
     }
 }
 
-impl<T: TileChecking> CheckData for CompressedData<Array2<T>, TilesLoadInfo> {
+impl TileChecking for Switch {
+    fn check(&self, _bytes: &[u8], kind: LayerKind) -> Result<(), TileErrorKind> {
+        check_tile_flags(self.flags, true)?;
+        if !is_valid_tile_id(self.id, kind) {
+            return Err(TileErrorKind::TileId { id: self.id, kind });
+        }
+        Ok(())
+    }
+}
+
+impl TileChecking for Tune {}
+
+/// Checks a region of tiles of a layer. `offset` is the position of the region in
+/// the layer, for error reporting.
+pub(crate) fn check_tiles<T: TileChecking>(
+    tiles: ArrayView2<T>,
+    kind: LayerKind,
+    offset: (usize, usize),
+) -> Result<(), TileError> {
+    let bytes = ViewAsBytes::into_boxed_bytes(tiles.iter().copied().collect());
+    let tile_bytes = bytes.chunks_exact(mem::size_of::<T>());
+    for (((y, x), tile), bytes) in tiles.indexed_iter().zip(tile_bytes) {
+        TileChecking::check(tile, bytes, kind).map_err(|err| TileError {
+            x: x + offset.0,
+            y: y + offset.1,
+            err,
+        })?;
+    }
+    Ok(())
+}
+
+fn check_layer_tiles<L: TilemapLayer>(layer: &L) -> Result<(), MapErrorKind>
+where
+    L::TileType: TileChecking,
+{
+    layer.tiles().check_data()?;
+    if let CompressedData::Loaded(tiles) = layer.tiles() {
+        check_tiles(tiles.view(), L::kind(), (0, 0))?;
+    }
+    Ok(())
+}
+
+/// Checks the tiles of all tilemap layers, which must be loaded.
+pub(crate) fn check_map_tiles(map: &TwMap) -> Result<(), MapErr> {
+    for (g, group) in map.groups.iter().enumerate() {
+        for (l, layer) in group.layers.iter().enumerate() {
+            match layer {
+                Layer::Game(layer) => check_layer_tiles(layer),
+                Layer::Tiles(layer) => check_layer_tiles(layer),
+                Layer::Front(layer) => check_layer_tiles(layer),
+                Layer::Tele(layer) => check_layer_tiles(layer),
+                Layer::Speedup(layer) => check_layer_tiles(layer),
+                Layer::Switch(layer) => check_layer_tiles(layer),
+                Layer::Tune(layer) => check_layer_tiles(layer),
+                Layer::Quads(_) | Layer::Sounds(_) | Layer::Invalid(_) => Ok(()),
+            }
+            .map_err(|err| {
+                MapErr::from(err)
+                    .with_type(MapItem::Layer)
+                    .with_index(l)
+                    .with_type(MapItem::Group)
+                    .with_index(g)
+            })?;
+        }
+    }
+    Ok(())
+}
+
+impl<T: AnyTile> CheckData for CompressedData<Array2<T>, TilesLoadInfo> {
     fn check_data(&self) -> Result<(), MapErrorKind> {
         let size = self.shape();
         check_i32_fit(size.w, "width")?;
@@ -504,11 +1103,8 @@ impl<T: TileChecking> CheckData for CompressedData<Array2<T>, TilesLoadInfo> {
             return Err(LayerError::TooSmall.into());
         }
         match self {
-            CompressedData::Loaded(tiles) => {
-                for ((y, x), tile) in tiles.indexed_iter() {
-                    tile.check().map_err(|err| TileError { x, y, err })?;
-                }
-            }
+            // the tiles are checked with the layer kind in `check_layer_tiles`.
+            CompressedData::Loaded(_) => {}
             CompressedData::Compressed(_, data_size, info) => {
                 if info.compression {
                     if data_size % 4 != 0 {
@@ -522,3 +1118,382 @@ impl<T: TileChecking> CheckData for CompressedData<Array2<T>, TilesLoadInfo> {
         Ok(())
     }
 }
+
+#[derive(Error, Debug)]
+pub(crate) enum UnusedItem {
+    #[error("The image is not used by any layer")]
+    Image,
+    #[error("The envelope is not used by any layer, quad or sound source")]
+    Envelope,
+    #[error("The sound is not used by any sound layer")]
+    Sound,
+}
+
+/// Images, envelopes and sounds which are not referenced anywhere in the map.
+fn unused_items(map: &TwMap) -> Vec<MapErr> {
+    let mut images = HashSet::new();
+    let mut envelopes = HashSet::new();
+    let mut sounds = HashSet::new();
+
+    for layer in map.groups.iter().flat_map(|g| &g.layers) {
+        match layer {
+            Layer::Tiles(l) => {
+                images.extend(l.image);
+                envelopes.extend(l.color_env);
+            }
+            Layer::Quads(l) => {
+                images.extend(l.image);
+                for quad in &l.quads {
+                    envelopes.extend(quad.position_env);
+                    envelopes.extend(quad.color_env);
+                }
+            }
+            Layer::Sounds(l) => {
+                sounds.extend(l.sound);
+                for source in &l.sources {
+                    envelopes.extend(source.position_env);
+                    envelopes.extend(source.sound_env);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let unused = |item: MapItem, len: usize, used: &HashSet<u16>, kind: fn() -> UnusedItem| {
+        (0..len)
+            .filter(|i| !used.contains(&(*i as u16)))
+            .map(move |i| {
+                MapErr::from(MapErrorKind::Unused(kind()))
+                    .with_type(item)
+                    .with_index(i)
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let mut warnings = unused(MapItem::Image, map.images.len(), &images, || {
+        UnusedItem::Image
+    });
+    warnings.extend(unused(
+        MapItem::Envelope,
+        map.envelopes.len(),
+        &envelopes,
+        || UnusedItem::Envelope,
+    ));
+    warnings.extend(unused(MapItem::Sound, map.sounds.len(), &sounds, || {
+        UnusedItem::Sound
+    }));
+    warnings
+}
+
+/// Runs all checks over the whole map and reports every problem found, rather
+/// than stopping at the first error. The map must be loaded.
+pub(crate) fn check_map_report(map: &TwMap) -> Vec<MapDiagnostic> {
+    let mut errors = Vec::new();
+    let mut state = ();
+    map.info.report(map, &mut state, &mut errors);
+    Image::report_all(&map.images, map, &mut errors);
+    Envelope::report_all(&map.envelopes, map, &mut errors);
+    Group::report_all(&map.groups, map, &mut errors);
+    Sound::report_all(&map.sounds, map, &mut errors);
+
+    let mut diagnostics: Vec<_> = errors
+        .into_iter()
+        .map(|err| err.into_diagnostic(Severity::Error))
+        .collect();
+
+    // the checks of twmap, in case something was missed above.
+    if diagnostics.is_empty() {
+        if let Err(err) = map.check() {
+            diagnostics.push(MapDiagnostic {
+                severity: Severity::Error,
+                path: Vec::new(),
+                msg: err.to_string(),
+            });
+        }
+    }
+
+    diagnostics.extend(
+        unused_items(map)
+            .into_iter()
+            .map(|err| err.into_diagnostic(Severity::Warning)),
+    );
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tile<T: AnyTile>(bytes: &[u8]) -> T {
+        *T::view(bytes).unwrap()
+    }
+
+    fn tiles<T: AnyTile>(bytes: &[u8]) -> Array2<T> {
+        let tiles = bytes.chunks_exact(mem::size_of::<T>()).map(tile).collect();
+        Array2::from_shape_vec((2, 2), tiles).unwrap()
+    }
+
+    #[test]
+    fn tiles_padding_bytes() {
+        let bytes = [1, 0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0];
+        let game = tiles::<GameTile>(&bytes);
+        assert!(check_tiles(game.view(), LayerKind::Game, (0, 0)).is_ok());
+
+        let mut bytes = bytes;
+        bytes[14] = 3;
+        let game = tiles::<GameTile>(&bytes);
+        let err = check_tiles(game.view(), LayerKind::Game, (5, 7)).unwrap_err();
+        assert!(matches!(err.err, TileErrorKind::TileSkip(3)));
+        assert_eq!((err.x, err.y), (6, 8));
+
+        bytes[14] = 0;
+        bytes[7] = 4;
+        let tiles = tiles::<Tile>(&bytes);
+        let err = check_tiles(tiles.view(), LayerKind::Tiles, (0, 0)).unwrap_err();
+        assert!(matches!(err.err, TileErrorKind::TileUnused(4)));
+        assert_eq!((err.x, err.y), (1, 0));
+    }
+
+    fn blank_map() -> TwMap {
+        let mut map = TwMap::empty(Version::DDNet06);
+        let mut group = Group::physics();
+        group.layers.push(Layer::Game(GameLayer {
+            tiles: CompressedData::Loaded(Array2::default((2, 2))),
+        }));
+        map.groups.push(group);
+        map
+    }
+
+    fn grass() -> Image {
+        Image::External(ExternalImage {
+            name: "grass_main".to_owned(),
+            size: Extent2::new(1024, 1024),
+        })
+    }
+
+    type Path = Vec<(MapItem, Option<usize>)>;
+
+    /// (severity, path, message) of the diagnostics of a map.
+    fn report(map: &TwMap) -> Vec<(Severity, Path, String)> {
+        check_map_report(map)
+            .into_iter()
+            .map(|d| {
+                let path = d.path.iter().map(|p| (p.item, p.index)).collect();
+                (d.severity, path, d.msg)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn report_valid_map() {
+        assert_eq!(report(&blank_map()), []);
+    }
+
+    #[test]
+    fn report_unused_items() {
+        let mut map = blank_map();
+        map.images.extend([grass(), grass()]);
+        map.envelopes.push(Envelope::Position(Env {
+            name: "env".to_owned(),
+            synchronized: false,
+            points: Vec::new(),
+        }));
+        map.sounds.push(Sound {
+            name: "sound".to_owned(),
+            data: CompressedData::Compressed(Vec::new(), 0, ()),
+        });
+        let mut layer = TilesLayer::new((2, 2));
+        layer.image = Some(1);
+        map.groups[0].layers.push(Layer::Tiles(layer));
+
+        let warning = |item, msg: &str| (Severity::Warning, vec![(item, Some(0))], msg.to_owned());
+        assert_eq!(
+            report(&map),
+            [
+                warning(MapItem::Image, "The image is not used by any layer"),
+                warning(
+                    MapItem::Envelope,
+                    "The envelope is not used by any layer, quad or sound source"
+                ),
+                warning(MapItem::Sound, "The sound is not used by any sound layer"),
+            ]
+        );
+    }
+
+    #[test]
+    fn report_all_errors() {
+        let mut map = blank_map();
+        map.groups.push(map.groups[0].clone());
+        map.groups[0]
+            .layers
+            .push(Layer::Tiles(TilesLayer::new((1, 2))));
+        assert!(map.check().is_err());
+
+        // the errors are reported at their item, and twmap does not add its own.
+        assert_eq!(
+            report(&map),
+            [
+                (
+                    Severity::Error,
+                    vec![(MapItem::Group, Some(0)), (MapItem::Layer, Some(1)),],
+                    "Width and height must be at least 2".to_owned(),
+                ),
+                (
+                    Severity::Error,
+                    vec![(MapItem::Group, Some(1))],
+                    "There must be only one physics group".to_owned(),
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn report_falls_back_to_map_check() {
+        // the amount of images is only checked by twmap.
+        let mut map = blank_map();
+        map.images = vec![grass(); 65];
+        let err = map.check().unwrap_err().to_string();
+
+        let report = report(&map);
+        let errors: Vec<_> = report
+            .iter()
+            .filter(|(severity, ..)| *severity == Severity::Error)
+            .collect();
+        assert_eq!(errors, [&(Severity::Error, vec![], err)]);
+        assert_eq!(report.len(), 1 + 65);
+    }
+
+    fn check_tile<T: TileChecking>(tile: T, kind: LayerKind) -> Result<(), TileErrorKind> {
+        let tiles = Array2::from_elem((2, 2), tile);
+        check_tiles(tiles.view(), kind, (0, 0)).map_err(|err| err.err)
+    }
+
+    fn game_tile(id: u8, flags: u8) -> GameTile {
+        tile(&[id, flags, 0, 0])
+    }
+
+    #[test]
+    fn game_tile_rules() {
+        use tile_id::*;
+        assert!(check_tile(game_tile(FREEZE, 0), LayerKind::Game).is_ok());
+        assert!(check_tile(game_tile(SOLID, 0b1011), LayerKind::Game).is_ok());
+        assert!(check_tile(game_tile(DEATH, 0), LayerKind::Front).is_ok());
+
+        let err = check_tile(game_tile(5, 0), LayerKind::Game).unwrap_err();
+        assert!(matches!(
+            err,
+            TileErrorKind::TileId {
+                id: 5,
+                kind: LayerKind::Game
+            }
+        ));
+        // solid tiles only exist in the game layer.
+        let err = check_tile(game_tile(SOLID, 0), LayerKind::Front).unwrap_err();
+        assert!(matches!(err, TileErrorKind::TileId { id: SOLID, .. }));
+
+        let err = check_tile(game_tile(SOLID, 0b0100), LayerKind::Game).unwrap_err();
+        assert!(matches!(err, TileErrorKind::OpaqueTileFlag));
+        let err = check_tile(game_tile(SOLID, 0b1_0000), LayerKind::Game).unwrap_err();
+        assert!(matches!(err, TileErrorKind::UnknownTileFlags(0b1_0000)));
+    }
+
+    #[test]
+    fn design_tile_rules() {
+        // design layers use any tile of the image, and may be opaque.
+        let opaque: Tile = tile(&[200, 0b0100, 0, 0]);
+        assert!(check_tile(opaque, LayerKind::Tiles).is_ok());
+
+        let unknown: Tile = tile(&[1, 0b1_0000, 0, 0]);
+        let err = check_tile(unknown, LayerKind::Tiles).unwrap_err();
+        assert!(matches!(err, TileErrorKind::UnknownTileFlags(0b1_0000)));
+    }
+
+    #[test]
+    fn tele_tile_rules() {
+        use tile_id::*;
+        let tele = |id| Tele { number: 1, id };
+        assert!(check_tile(tele(TELEOUT), LayerKind::Tele).is_ok());
+        assert!(check_tile(tele(TELECHECKINEVIL), LayerKind::Tele).is_ok());
+        // a teleporter without a number is valid, it is ignored by the lints.
+        let unnumbered = Tele {
+            number: 0,
+            id: TELEIN,
+        };
+        assert!(check_tile(unnumbered, LayerKind::Tele).is_ok());
+
+        let err = check_tile(tele(SOLID), LayerKind::Tele).unwrap_err();
+        assert!(matches!(
+            err,
+            TileErrorKind::TileId {
+                id: SOLID,
+                kind: LayerKind::Tele
+            }
+        ));
+    }
+
+    #[test]
+    fn speedup_tile_rules() {
+        use tile_id::*;
+        let speedup = Speedup::new(BOOST, 10, 0, 359);
+        assert!(check_tile(speedup, LayerKind::Speedup).is_ok());
+
+        let speedup = Speedup::new(BOOST, 10, 0, 360);
+        let err = check_tile(speedup, LayerKind::Speedup).unwrap_err();
+        assert!(matches!(err, TileErrorKind::SpeedupAngle(360)));
+        let speedup = Speedup::new(BOOST, 10, 0, -1);
+        let err = check_tile(speedup, LayerKind::Speedup).unwrap_err();
+        assert!(matches!(err, TileErrorKind::SpeedupAngle(-1)));
+
+        let speedup = Speedup::new(FREEZE, 10, 0, 0);
+        let err = check_tile(speedup, LayerKind::Speedup).unwrap_err();
+        assert!(matches!(err, TileErrorKind::TileId { id: FREEZE, .. }));
+    }
+
+    #[test]
+    fn switch_tile_rules() {
+        use tile_id::*;
+        let switch = |id, flags| Switch {
+            number: 1,
+            id,
+            flags,
+            delay: 0,
+        };
+        let door = ENTITY_OFFSET + ENTITY_DOOR;
+        assert!(check_tile(switch(door, TileFlags::ROTATE), LayerKind::Switch).is_ok());
+        assert!(check_tile(switch(SWITCHCLOSE, TileFlags::empty()), LayerKind::Switch).is_ok());
+
+        let err = check_tile(switch(door, TileFlags::OPAQUE), LayerKind::Switch).unwrap_err();
+        assert!(matches!(err, TileErrorKind::OpaqueTileFlag));
+        let err = check_tile(switch(SOLID, TileFlags::empty()), LayerKind::Switch).unwrap_err();
+        assert!(matches!(err, TileErrorKind::TileId { id: SOLID, .. }));
+    }
+
+    #[test]
+    fn tune_tile_rules() {
+        use tile_id::*;
+        assert!(check_tile(
+            Tune {
+                number: 1,
+                id: TUNE
+            },
+            LayerKind::Tune
+        )
+        .is_ok());
+
+        let err = check_tile(
+            Tune {
+                number: 1,
+                id: SOLID,
+            },
+            LayerKind::Tune,
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            TileErrorKind::TileId {
+                id: SOLID,
+                kind: LayerKind::Tune
+            }
+        ));
+    }
+}
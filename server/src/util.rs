@@ -1,10 +1,27 @@
-use std::path::Path;
+use std::{
+    borrow::Cow,
+    fs::File,
+    io::{Read, Write},
+    path::Path,
+};
+
+use flate2::{read::ZlibDecoder, write::ZlibEncoder};
 
 use crate::{
-    protocol::AutomapperKind,
+    error::Error,
+    protocol::{AutomapperKind, Compression},
     twmap_map_edit::{extend_layer, shrink_layer},
 };
 
+pub(crate) fn random_token(len: usize) -> String {
+    use rand::Rng;
+    rand::thread_rng()
+        .sample_iter(&rand::distributions::Alphanumeric)
+        .take(len)
+        .map(char::from)
+        .collect()
+}
+
 pub(crate) fn timestamp_now() -> u64 {
     std::time::SystemTime::now()
         .duration_since(std::time::SystemTime::UNIX_EPOCH)
@@ -12,6 +29,99 @@ pub(crate) fn timestamp_now() -> u64 {
         .as_secs()
 }
 
+/// Writes a file without ever leaving it half-written: the data is written and
+/// synced to a temporary file next to `path`, which is then renamed over `path`.
+pub(crate) fn write_atomic(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    write_atomic_with(
+        path,
+        |file| file.write_all(data),
+        |from, to| std::fs::rename(from, to),
+    )
+}
+
+/// [`write_atomic`] with the write and rename steps passed in, so that tests can
+/// make them fail.
+fn write_atomic_with(
+    path: &Path,
+    write: impl FnOnce(&mut File) -> std::io::Result<()>,
+    rename: impl FnOnce(&Path, &Path) -> std::io::Result<()>,
+) -> std::io::Result<()> {
+    let mut tmp_name = path
+        .file_name()
+        .ok_or(std::io::ErrorKind::InvalidInput)?
+        .to_owned();
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+
+    let res = (|| {
+        let mut file = File::create(&tmp_path)?;
+        write(&mut file)?;
+        file.sync_all()?;
+        rename(&tmp_path, path)
+    })();
+
+    if res.is_err() {
+        std::fs::remove_file(&tmp_path).ok();
+        return res;
+    }
+
+    sync_parent_dir(path)
+}
+
+/// Makes a rename in the parent directory of `path` durable.
+#[cfg(unix)]
+fn sync_parent_dir(path: &Path) -> std::io::Result<()> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    File::open(parent)?.sync_all()
+}
+
+// directories cannot be opened as files on other platforms.
+#[cfg(not(unix))]
+fn sync_parent_dir(_path: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+pub(crate) fn compress(data: Box<[u8]>, compression: Compression) -> Result<Box<[u8]>, Error> {
+    match compression {
+        Compression::None => Ok(data),
+        Compression::Zlib => {
+            let mut enc = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+            enc.write_all(&data)
+                .and_then(|_| enc.finish())
+                .map(Vec::into_boxed_slice)
+                .map_err(|e| Error::Internal(e.to_string().into()))
+        }
+    }
+}
+
+/// Decompresses exactly `len` bytes of data. The decoder stops reading past `len`,
+/// so that a small payload cannot expand into a huge buffer.
+pub(crate) fn decompress(
+    data: &[u8],
+    compression: Compression,
+    len: usize,
+) -> Result<Cow<'_, [u8]>, Error> {
+    let data = match compression {
+        Compression::None => Cow::Borrowed(data),
+        Compression::Zlib => {
+            let mut buf = Vec::with_capacity(len);
+            ZlibDecoder::new(data)
+                .take(len as u64 + 1)
+                .read_to_end(&mut buf)
+                .map_err(|_| Error::InvalidTiles)?;
+            Cow::Owned(buf)
+        }
+    };
+
+    if data.len() != len {
+        return Err(Error::InvalidTiles);
+    }
+    Ok(data)
+}
+
 pub(crate) mod macros {
     macro_rules! apply_partial {
         ($src:expr => $tgt:expr, $($field:ident),*) => {{
@@ -39,6 +149,19 @@ pub(crate) trait ViewAsBytes: structview::View {
 }
 impl<T: structview::View> ViewAsBytes for T {}
 
+/// New position of the item at index `i` after moving the item at `src` to `tgt`.
+pub(crate) fn move_index(i: u16, src: u16, tgt: u16) -> u16 {
+    if i == src {
+        tgt
+    } else if src < i && i <= tgt {
+        i - 1
+    } else if tgt <= i && i < src {
+        i + 1
+    } else {
+        i
+    }
+}
+
 pub(crate) fn check_file_name(name: &str) -> bool {
     // this is a very primitive sanitization to prevent path traversal attacks.
     !(name.chars().any(std::path::is_separator) || name.starts_with('.') || name.is_empty())
@@ -84,6 +207,27 @@ pub(crate) fn set_layer_height<T: twmap::TilemapLayer>(
     Ok(())
 }
 
+/// Copies a rectangle of tiles out of a tilemap layer as raw bytes.
+/// The rectangle must be within the layer bounds.
+pub(crate) fn tiles_region_bytes<T: twmap::TilemapLayer>(
+    layer: &T,
+    rect: vek::Rect<u32, u32>,
+) -> Box<[u8]> {
+    let x = rect.x as usize;
+    let y = rect.y as usize;
+    let w = rect.w as usize;
+    let h = rect.h as usize;
+
+    let data = layer
+        .tiles()
+        .unwrap_ref()
+        .slice(ndarray::s![y..y + h, x..x + w])
+        .to_owned()
+        .into_raw_vec()
+        .into_boxed_slice();
+    ViewAsBytes::into_boxed_bytes(data)
+}
+
 pub(crate) fn automapper_kind(path: &Path) -> Option<AutomapperKind> {
     let extensions = &[
         ("rules", AutomapperKind::DDNet),
@@ -101,3 +245,139 @@ pub(crate) fn automapper_kind(path: &Path) -> Option<AutomapperKind> {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestDir;
+
+    fn injected_error() -> std::io::Error {
+        std::io::Error::other("injected")
+    }
+
+    #[test]
+    fn write_atomic_replaces_file() {
+        let dir = TestDir::new("write-atomic-replaces");
+        let path = dir.0.join("map.map");
+        std::fs::write(&path, b"old").unwrap();
+
+        write_atomic(&path, b"new").unwrap();
+
+        assert_eq!(std::fs::read(&path).unwrap(), b"new");
+        assert_eq!(dir.file_names(), ["map.map"]);
+    }
+
+    #[test]
+    fn write_atomic_write_failure_keeps_old_file() {
+        let dir = TestDir::new("write-atomic-write-failure");
+        let path = dir.0.join("map.map");
+        std::fs::write(&path, b"old").unwrap();
+
+        let res = write_atomic_with(
+            &path,
+            |file| {
+                file.write_all(b"ne")?;
+                Err(injected_error())
+            },
+            |from, to| std::fs::rename(from, to),
+        );
+
+        assert!(res.is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), b"old");
+        assert_eq!(dir.file_names(), ["map.map"]);
+    }
+
+    #[test]
+    fn write_atomic_rename_failure_keeps_old_file() {
+        let dir = TestDir::new("write-atomic-rename-failure");
+        let path = dir.0.join("map.map");
+        std::fs::write(&path, b"old").unwrap();
+
+        let res = write_atomic_with(
+            &path,
+            |file| file.write_all(b"new"),
+            |_, _| Err(injected_error()),
+        );
+
+        assert!(res.is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), b"old");
+        assert_eq!(dir.file_names(), ["map.map"]);
+    }
+
+    /// Tiles with every byte set, so that a round trip that loses a field is caught.
+    fn pattern_tiles<T: twmap::AnyTile>(w: usize, h: usize) -> ndarray::Array2<T> {
+        let bytes: Vec<u8> = (0..w * h * std::mem::size_of::<T>())
+            .map(|i| (i * 7 + 1) as u8)
+            .collect();
+        let tiles = structview::View::view_slice(&bytes).unwrap();
+        ndarray::ArrayView2::from_shape((h, w), tiles)
+            .unwrap()
+            .to_owned()
+    }
+
+    fn check_round_trip<T>(layer: &T)
+    where
+        T: twmap::TilemapLayer,
+        T::TileType: std::fmt::Debug,
+    {
+        let tiles = layer.tiles().unwrap_ref();
+        let (h, w) = tiles.dim();
+        let rect = vek::Rect::new(0, 0, w as u32, h as u32);
+        let bytes = tiles_region_bytes(layer, rect);
+        let len = w * h * std::mem::size_of::<T::TileType>();
+        assert_eq!(bytes.len(), len);
+
+        for compression in [Compression::None, Compression::Zlib] {
+            let compressed = compress(bytes.clone(), compression).unwrap();
+            let data = decompress(&compressed, compression, len).unwrap();
+            assert_eq!(*data, *bytes);
+
+            let view: &[T::TileType] = structview::View::view_slice(&data).unwrap();
+            let view = ndarray::ArrayView2::from_shape((h, w), view).unwrap();
+            assert_eq!(view, tiles.view());
+        }
+    }
+
+    macro_rules! round_trip {
+        ($struct:ident) => {
+            check_round_trip(&twmap::$struct {
+                tiles: twmap::CompressedData::Loaded(pattern_tiles(13, 5)),
+            })
+        };
+    }
+
+    #[test]
+    fn tiles_round_trip() {
+        round_trip!(GameLayer);
+        round_trip!(FrontLayer);
+        round_trip!(TeleLayer);
+        round_trip!(SpeedupLayer);
+        round_trip!(SwitchLayer);
+        round_trip!(TuneLayer);
+    }
+
+    #[test]
+    fn decompress_rejects_wrong_length() {
+        let data: Box<[u8]> = vec![1; 64].into();
+        let compressed = compress(data.clone(), Compression::Zlib).unwrap();
+
+        assert!(decompress(&data, Compression::None, 63).is_err());
+        assert!(decompress(&data, Compression::None, 65).is_err());
+        assert!(decompress(&compressed, Compression::Zlib, 63).is_err());
+        assert!(decompress(&compressed, Compression::Zlib, 65).is_err());
+        assert!(decompress(&data, Compression::Zlib, 64).is_err());
+    }
+
+    #[test]
+    fn decompress_stops_at_length_cap() {
+        // a few kilobytes that expand to 16 MiB.
+        let data: Box<[u8]> = vec![0; 16 << 20].into();
+        let compressed = compress(data, Compression::Zlib).unwrap();
+        assert!(compressed.len() < 1 << 16);
+
+        assert!(matches!(
+            decompress(&compressed, Compression::Zlib, 1024),
+            Err(Error::InvalidTiles)
+        ));
+    }
+}